@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// Chain followed by scraper.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainConfig {
+    /// EIP-155 chain id. Stored on every record, so the same address can be tracked on several chains.
+    pub id: u64,
+    pub name: String,
+    /// RPC endpoint. `ws://` is followed with subscriptions, others are polled.
+    pub url: String,
+}
+
+/// Chains config file.
+///
+/// ```toml
+/// [[chain]]
+/// id = 2018
+/// name = "dev"
+/// url = "ws://localhost:8546"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default, rename = "chain")]
+    pub chains: Vec<ChainConfig>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path).with_context(|| format!("Can't read config {:?}", path))?;
+
+        Config::parse(&content).with_context(|| format!("Bad config {:?}", path))
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
+
+        if config.chains.is_empty() {
+            bail!("No chains configured");
+        }
+
+        let mut ids = HashSet::new();
+        for chain in config.chains.iter() {
+            if !ids.insert(chain.id) {
+                bail!("Chain {} configured twice", chain.id);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Config of a single chain given by command line.
+    pub fn single(id: u64, url: &str) -> Config {
+        Config {
+            chains: vec![ChainConfig {
+                id,
+                name: id.to_string(),
+                url: url.into(),
+            }],
+        }
+    }
+
+    pub fn chain(&self, id: u64) -> Option<&ChainConfig> {
+        self.chains.iter().find(|c| c.id == id)
+    }
+
+    /// Chain used by requests not specifying one.
+    pub fn default_chain(&self) -> &ChainConfig {
+        &self.chains[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_chains() {
+        let config = Config::parse(r#"
+            [[chain]]
+            id = 2018
+            name = "dev"
+            url = "ws://localhost:8546"
+
+            [[chain]]
+            id = 2019
+            name = "staging"
+            url = "http://staging:8545"
+        "#).unwrap();
+
+        assert_eq!(config.chains.len(), 2);
+        assert_eq!(config.default_chain().name, "dev");
+        assert_eq!(config.chain(2019).unwrap().url, "http://staging:8545");
+
+        assert!(Config::parse("").is_err());
+        assert!(Config::parse(r#"
+            [[chain]]
+            id = 1
+            name = "a"
+            url = "ws://a"
+            [[chain]]
+            id = 1
+            name = "b"
+            url = "ws://b"
+        "#).is_err());
+    }
+}
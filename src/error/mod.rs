@@ -1,7 +1,4 @@
-use std::{
-    panic::{self, PanicInfo},
-    process, thread, time,
-};
+use std::panic::{self, PanicHookInfo};
 
 use serde::Serialize;
 use backtrace::Backtrace;
@@ -13,29 +10,23 @@ pub struct CrashInfo {
     backtrace: String,
 }
 
-/// Invoke to ensure process exits on a thread panic.
+/// Invoke to report every thread panic with details and backtrace.
 ///
-/// Tokio's default behavior is to catch panics and ignore them.  Invoking this function will
-/// ensure that all subsequent thread panics (even Tokio threads) will report the
-/// details/backtrace and then exit.
+/// Tokio catches panics of spawned tasks, so such panic ends that task only and the rest of
+/// the service keeps running. Panic of the main task still brings the whole process down.
+/// Tests never install it, so a panicking test doesn't spam the output of others.
 pub fn setup_panic_handler() {
-    panic::set_hook(Box::new(move |pi: &PanicInfo<'_>| {
+    panic::set_hook(Box::new(move |pi: &PanicHookInfo<'_>| {
         handle_panic(pi);
     }));
 }
 
 // Formats and logs panic information
-fn handle_panic(panic_info: &PanicInfo<'_>) {
+fn handle_panic(panic_info: &PanicHookInfo<'_>) {
     // The Display formatter for a PanicInfo contains the message, payload and location.
     let details = format!("{}", panic_info);
     let backtrace = format!("{:#?}", Backtrace::new());
 
     let info = CrashInfo { details, backtrace };
     error!("{}", toml::to_string_pretty(&info).unwrap());
-
-    // Provide some time to save the log to disk
-    thread::sleep(time::Duration::from_millis(100));
-
-    // Kill the process
-    process::exit(1);
 }
@@ -1,68 +1,142 @@
 use std::slice::Iter;
 use std::sync::Arc;
 
-use anyhow::Result;
-use elasticsearch::{BulkParts, Elasticsearch};
+use anyhow::{bail, Result};
+use elasticsearch::{BulkParts, DeleteByQueryParts, Elasticsearch};
 use elasticsearch::http::request::JsonBody;
 use elasticsearch::http::transport::Transport;
-use log::{debug, error};
-use mongodb::results::InsertOneResult;
+use elasticsearch::indices::{IndicesDeleteParts, IndicesGetParts, IndicesPutTemplateParts};
+use log::{debug, error, info};
 use rustc_hex::ToHex;
+use serde::Serialize;
 use serde_json::{json, Value};
 
-use crate::mongo::model::{Contract, Transaction};
-use crate::mongo::MongoDB;
+use crate::mongo::model::{Contract, PendingTransaction, Transaction};
+use crate::notify::Notifier;
+use crate::parse::rules::{self, TrxFields};
 use crate::parse::trx;
+use crate::storage::Storage;
 
 mod model;
 
+const TRANSACTIONS_INDEX: &str = "transactions";
+const PENDING_TRANSACTIONS_INDEX: &str = "pending_transactions";
+
+/// Each chain is indexed separately, `transactions-*` pattern covers all of them.
+fn chain_index(index: &str, chain_id: i64) -> String {
+    format!("{}-{}", index, chain_id)
+}
 
 #[derive(Debug)]
 pub struct ContractProcessor {
-    mongo: Arc<MongoDB>,
+    storage: Arc<dyn Storage>,
     elastic: Arc<Elastic>,
+    notifier: Option<Arc<Notifier>>,
 }
 
 impl ContractProcessor {
-    pub fn new(mongo: Arc<MongoDB>, elastic: Arc<Elastic>) -> Self {
+    pub fn new(storage: Arc<dyn Storage>, elastic: Arc<Elastic>) -> Self {
         Self {
-            mongo,
+            storage,
             elastic,
+            notifier: None,
         }
     }
 
-    pub fn get_mongo(&self) -> Arc<MongoDB> {
-        self.mongo.clone()
+    /// Decoded transactions are sent to webhooks of their contracts as well.
+    pub fn with_notifier(mut self, notifier: Arc<Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub fn get_storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
     }
 
-    pub async fn save_contract(&self, contract: &Contract) -> Result<InsertOneResult> {
-        self.mongo.save_contract(contract).await
+    pub async fn save_contract(&self, contract: &Contract) -> Result<()> {
+        self.storage.save_contract(contract).await
     }
 
+    /// Decodes transactions sent to contract, notifies its webhooks and indexes ones passing its filter rules.
     pub async fn process_contract(&self, contract: &Contract, transactions: impl Into<Iter<'_, Transaction>>) -> Result<()> {
         let transactions = transactions.into();
         debug!("Processing {} trx for contract {}", transactions.len(), contract.address);
         let map = trx::create_id_method_map(&contract.abi_json);
+        let abi_version = contract.abi_version();
 
-        let data: Vec<_> = transactions
-            .map(|t| {
-                match trx::parse_trx(&map, t.input.0.to_hex::<String>().as_ref()) {
-                    Some(input) => Some(model::Transaction::new(&t, input)),
-                    None => None,
-                }
-            })
-            .filter(Option::is_some)
-            .map(Option::unwrap)
+        let decoded: Vec<_> = transactions
+            .filter_map(|t| trx::parse_trx(&map, t.input.0.to_hex::<String>().as_ref()).map(|input| (t, input)))
+            .collect();
+
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(contract, &decoded).await;
+        }
+
+        let data: Vec<_> = decoded.into_iter()
+            .filter(|(t, input)| rules::accepts(&contract.rules, &TrxFields::new(input, t)))
+            .map(|(t, input)| model::Transaction::new(t, Some(input), Some(abi_version)))
             .collect();
 
         let size = data.len();
 
-        let res = self.elastic.save_trx(data).await?;
+        if !self.elastic.save_trx(contract.chain_id, data).await? {
+            bail!("Can't save trx of {}", contract.id);
+        }
+
+        debug!("{} saved", size);
+        Ok(())
+    }
+
+    /// Indexes transactions of watched addresses as they are, with no input decoding.
+    pub async fn process_undecoded(&self, chain_id: i64, transactions: &[Transaction]) -> Result<()> {
+        debug!("Processing {} undecoded trx", transactions.len());
+
+        let data: Vec<_> = transactions.iter()
+            .map(|t| model::Transaction::new(t, None, None))
+            .collect();
 
-        if res {
-            debug!("{} saved", size);
-        } else {
-            panic!("Can't save data.")
+        if !self.elastic.save_trx(chain_id, data).await? {
+            bail!("Can't save undecoded trx");
+        }
+        Ok(())
+    }
+
+    /// Removes indexed transactions of contract, so none decoded with outdated ABI remain.
+    pub async fn drop_indexed(&self, contract: &Contract) -> Result<()> {
+        self.elastic.delete_trx_to(contract.chain_id, &contract.address).await
+    }
+
+    /// Removes everything stored for contract transactions from storage and ES.
+    pub async fn purge(&self, contract: &Contract) -> Result<()> {
+        let deleted = self.storage.delete_trx_to(contract.chain_id as u64, &contract.address).await?;
+        debug!("Deleted {} stored trx of {}", deleted, contract.id);
+
+        self.elastic.delete_trx_to(contract.chain_id, &contract.address).await?;
+        self.elastic.delete_pending_to(contract.chain_id, &contract.address).await
+    }
+
+    /// Decodes and stores transaction to a tracked contract seen in mempool.
+    pub async fn process_pending(&self, contract: &Contract, trx: &web3::types::Transaction, first_seen: i64) -> Result<()> {
+        let map = trx::create_id_method_map(&contract.abi_json);
+        let input_data = trx::parse_trx(&map, trx.input.0.to_hex::<String>().as_ref());
+
+        let pending = PendingTransaction::new(contract.chain_id as u64, trx, input_data, first_seen);
+
+        debug!("Pending trx {:?} for contract {}", pending.hash, contract.address);
+
+        self.storage.save_pending(&pending).await?;
+        self.save_pending_es(&pending).await
+    }
+
+    /// Stores reconciled pending transaction state.
+    pub async fn update_pending(&self, pending: &PendingTransaction) -> Result<()> {
+        self.storage.update_pending(pending).await?;
+        self.save_pending_es(pending).await
+    }
+
+    async fn save_pending_es(&self, pending: &PendingTransaction) -> Result<()> {
+        if !self.elastic.save_pending(pending.chain_id, vec![model::PendingTransaction::new(pending)]).await? {
+            error!("Can't save pending trx {:?} to ES", pending.hash);
         }
         Ok(())
     }
@@ -82,33 +156,168 @@ impl Elastic {
         }
     }
 
-    pub async fn save_trx(&self, transactions: Vec<model::Transaction>) -> Result<bool> {
-        if transactions.is_empty() {
+    /// Per-chain indices are read through `transactions` and `pending_transactions` aliases as the single
+    /// index was before. That index is moved to index of `legacy_chain_id` first, alias can't take its name.
+    pub async fn init(&self, legacy_chain_id: i64) -> Result<()> {
+        for index in [TRANSACTIONS_INDEX, PENDING_TRANSACTIONS_INDEX].iter() {
+            self.move_legacy(index, legacy_chain_id).await?;
+            self.alias_chains(index).await?;
+        }
+        Ok(())
+    }
+
+    async fn move_legacy(&self, index: &str, chain_id: i64) -> Result<()> {
+        let response = self.es.indices().get(IndicesGetParts::Index(&[index])).send().await?;
+
+        if response.status_code().as_u16() == 404 {
+            return Ok(());
+        }
+
+        // alias resolves to per-chain indices
+        if response.json::<Value>().await?.get(index).is_none() {
+            return Ok(());
+        }
+
+        let dest = chain_index(index, chain_id);
+        info!("Moving ES [{}] to [{}]", index, dest);
+
+        let response = self.es.reindex()
+            .wait_for_completion(true)
+            .body(json!({
+                "source": { "index": index },
+                "dest": { "index": dest },
+                "script": {
+                    "source": "ctx._source.chain_id = params.chain_id",
+                    "params": { "chain_id": chain_id }
+                }
+            }))
+            .send()
+            .await?;
+
+        let status = response.status_code();
+        let response_body = response.json::<Value>().await?;
+
+        if !status.is_success() || response_body["failures"].as_array().is_some_and(|f| !f.is_empty()) {
+            bail!("Failed to move ES [{}] to [{}]: {:?}", index, dest, response_body);
+        }
+
+        let response = self.es.indices().delete(IndicesDeleteParts::Index(&[index])).send().await?;
+
+        if !response.status_code().is_success() {
+            bail!("Failed to delete moved ES [{}]: {:?}", index, response.json::<Value>().await?);
+        }
+
+        Ok(())
+    }
+
+    /// Indices of chains indexed for the first time join alias by template.
+    async fn alias_chains(&self, index: &str) -> Result<()> {
+        let pattern = format!("{}-*", index);
+        let mut aliases = serde_json::Map::new();
+        aliases.insert(index.to_string(), json!({}));
+
+        let response = self.es.indices().put_template(IndicesPutTemplateParts::Name(index))
+            .body(json!({
+                "index_patterns": [pattern],
+                "aliases": aliases
+            }))
+            .send()
+            .await?;
+
+        if !response.status_code().is_success() {
+            bail!("Failed to put ES template [{}]: {:?}", index, response.json::<Value>().await?);
+        }
+
+        let response = self.es.indices().update_aliases()
+            .body(json!({
+                "actions": [{ "add": { "index": pattern, "alias": index } }]
+            }))
+            .send()
+            .await?;
+
+        let status = response.status_code();
+
+        // nothing indexed yet
+        if !status.is_success() && status.as_u16() != 404 {
+            bail!("Failed to alias ES [{}]: {:?}", pattern, response.json::<Value>().await?);
+        }
+
+        Ok(())
+    }
+
+    pub async fn save_trx(&self, chain_id: i64, transactions: Vec<model::Transaction>) -> Result<bool> {
+        let items = transactions.into_iter()
+            .map(|t| (t.hash, t));
+
+        self.bulk_index(&chain_index(TRANSACTIONS_INDEX, chain_id), items).await
+    }
+
+    pub async fn save_pending(&self, chain_id: i64, transactions: Vec<model::PendingTransaction>) -> Result<bool> {
+        let items = transactions.into_iter()
+            .map(|t| (t.hash, t));
+
+        self.bulk_index(&chain_index(PENDING_TRANSACTIONS_INDEX, chain_id), items).await
+    }
+
+    pub async fn delete_trx_to(&self, chain_id: i64, address: &str) -> Result<()> {
+        self.delete_to(&chain_index(TRANSACTIONS_INDEX, chain_id), address).await
+    }
+
+    pub async fn delete_pending_to(&self, chain_id: i64, address: &str) -> Result<()> {
+        self.delete_to(&chain_index(PENDING_TRANSACTIONS_INDEX, chain_id), address).await
+    }
+
+    async fn delete_to(&self, index: &str, address: &str) -> Result<()> {
+        let response = self.es.delete_by_query(DeleteByQueryParts::Index(&[index]))
+            .body(json!({
+                "query": {
+                    "match": { "to": address }
+                }
+            }))
+            .send()
+            .await?;
+
+        let status = response.status_code();
+        let response_body = response.json::<Value>().await?;
+
+        // nothing indexed for the chain yet
+        if !status.is_success() && status.as_u16() != 404 {
+            bail!("Failed to delete trx to {} from ES [{}]: {:?}", address, index, response_body);
+        }
+
+        debug!("Deleted {} trx to {} from ES [{}]", response_body["deleted"], address, index);
+
+        Ok(())
+    }
+
+    /// Indexes documents by id. Document indexed again replaces the one before, so writes are idempotent.
+    async fn bulk_index<I: Serialize, D: Serialize>(&self, index: &str, items: impl ExactSizeIterator<Item=(I, D)>) -> Result<bool> {
+        if items.len() == 0 {
             return Ok(true);
         }
 
-        let mut body: Vec<JsonBody<_>> = Vec::with_capacity(transactions.len());
+        let mut body: Vec<JsonBody<_>> = Vec::with_capacity(items.len() * 2);
 
-        debug!("Saving to ES {} trx", transactions.len());
+        debug!("Saving to ES [{}] {} docs", index, items.len());
 
-        for trx in transactions {
-            let res = serde_json::to_value(&trx)?;
+        for (id, item) in items {
+            let res = serde_json::to_value(&item)?;
             debug!("Putting to map: {}", res);
             body.push(json!({
             "index": {
-                "_id": trx.hash
+                "_id": id
             }
             }).into());
             body.push(res.into());
         }
 
-        let response = self.es.bulk(BulkParts::Index("transactions"))
+        let response = self.es.bulk(BulkParts::Index(index))
             .body(body)
             .send()
             .await?;
 
         let response_body = response.json::<Value>().await?;
-        let successful = response_body["errors"].as_bool().unwrap() == false;
+        let successful = !response_body["errors"].as_bool().unwrap();
 
         if !successful {
             error!("Errors while saving to ES: {:?}", response_body)
@@ -1,11 +1,13 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use web3::types::{Bytes, H160, H256, Index, U256};
 
+use crate::mongo::model::PendingStatus;
 use crate::parse::input_data::InputData;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Transaction {
+    pub chain_id: i64,
     pub timestamp: DateTime<Utc>,
     /// Hash
     pub hash: H256,
@@ -36,14 +38,21 @@ pub struct Transaction {
     /// Raw transaction data
     #[serde(default)]
     pub raw: Option<Bytes>,
-    pub input_data: InputData,
+    /// None for transactions of watched addresses without ABI. Not sent then,
+    /// so upsert doesn't wipe input decoded for the same transaction by contract.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_data: Option<InputData>,
+    /// Version of contract ABI `input_data` was decoded with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abi_version: Option<i64>,
 }
 
 impl Transaction {
-    pub fn new(trx: &crate::mongo::model::Transaction, input_data: InputData) -> Self {
+    pub fn new(trx: &crate::mongo::model::Transaction, input_data: Option<InputData>, abi_version: Option<i64>) -> Self {
         let now: DateTime<Utc> = convert_to_date(trx.timestamp);
 
         Transaction {
+            chain_id: trx.chain_id,
             timestamp: now,
             hash: trx.hash,
             nonce: trx.nonce.as_u64(),
@@ -58,6 +67,50 @@ impl Transaction {
             input: trx.input.clone(),
             raw: trx.raw.clone(),
             input_data,
+            abi_version,
+        }
+    }
+}
+
+/// Numbers are decimal strings, as any of them might not fit ES `long`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingTransaction {
+    pub chain_id: i64,
+    /// First seen in mempool
+    pub timestamp: DateTime<Utc>,
+    pub hash: H256,
+    pub nonce: String,
+    pub from: H160,
+    pub to: Option<H160>,
+    pub value: String,
+    #[serde(rename = "gasPrice")]
+    pub gas_price: String,
+    pub gas: String,
+    pub status: PendingStatus,
+    #[serde(rename = "blockNumber")]
+    pub block_number: Option<u64>,
+    pub inclusion_latency_ms: Option<i64>,
+    pub replaced_by: Option<H256>,
+    pub input_data: Option<InputData>,
+}
+
+impl PendingTransaction {
+    pub fn new(trx: &crate::mongo::model::PendingTransaction) -> Self {
+        PendingTransaction {
+            chain_id: trx.chain_id,
+            timestamp: Utc.timestamp_millis(trx.first_seen),
+            hash: trx.hash,
+            nonce: trx.nonce.to_string(),
+            from: trx.from,
+            to: trx.to,
+            value: trx.value.to_string(),
+            gas_price: trx.gas_price.to_string(),
+            gas: trx.gas.to_string(),
+            status: trx.status,
+            block_number: trx.block_number.map(|n| n.as_u64()),
+            inclusion_latency_ms: trx.inclusion_latency_ms,
+            replaced_by: trx.replaced_by,
+            input_data: trx.input_data.clone(),
         }
     }
 }
@@ -74,6 +127,20 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn pending_amounts_beyond_u64() {
+        let trx = web3::types::Transaction {
+            value: U256::exp10(20),
+            gas_price: U256::from(u64::MAX) + 1,
+            ..Default::default()
+        };
+        let pending = PendingTransaction::new(&crate::mongo::model::PendingTransaction::new(1, &trx, None, 0));
+
+        assert_eq!(pending.value, "100000000000000000000");
+        assert_eq!(pending.gas_price, "18446744073709551616");
+        assert_eq!(pending.nonce, "0");
+    }
+
     #[test]
     fn convert_to_date() {
         let time = web3::types::U256::from_str("5ff5bb64").unwrap();
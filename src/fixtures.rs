@@ -0,0 +1,123 @@
+//! Fixtures shared by tests of scrapers, jobs and notifications.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+use futures::future::LocalBoxFuture;
+use jsonrpc_core as rpc;
+use serde_json::Value;
+use web3::RequestId;
+use web3::types::U64;
+use web3::Web3;
+
+use crate::es::{ContractProcessor, Elastic};
+use crate::storage::{SqlStorage, Storage};
+use crate::traversal::config::TraversalConfig;
+use crate::traversal::connection::RecordingTransport;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::{self, ChainSource, MemoryChainSource, RpcChainSource};
+
+/// Empty storage kept in memory
+pub async fn storage() -> Result<Arc<dyn Storage>> {
+    let storage = SqlStorage::connect("sqlite::memory:").await?;
+    storage.init().await?;
+
+    Ok(Arc::new(storage))
+}
+
+/// Processor of storage. Elastic is never reached by transactions no ABI decodes.
+pub fn processor(storage: &Arc<dyn Storage>) -> Arc<ContractProcessor> {
+    Arc::new(ContractProcessor::new(storage.clone(), Arc::new(Elastic::new("http://localhost:9200"))))
+}
+
+/// Source replaying chain recorded to cassette `name` in temp dir, its throttle and empty storage.
+pub async fn replayed(chain: &MemoryChainSource, name: &str) -> Result<(Arc<dyn ChainSource>, Arc<Throttle>, Arc<dyn Storage>)> {
+    let throttle = Arc::new(Throttle::new(TraversalConfig::default()));
+    let path = record(chain, name, throttle.clone()).await?;
+    let source = source::create_source(&format!("replay://{}", path.display()), throttle.clone()).await?;
+
+    Ok((source, throttle, storage().await?))
+}
+
+/// Requests head and every block of chain over RPC, so `RecordingTransport` writes them down
+/// the same way it does against a node.
+async fn record(chain: &MemoryChainSource, name: &str, throttle: Arc<Throttle>) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(name);
+
+    {
+        let transport = RecordingTransport::new(MemoryTransport::new(chain.clone()), &path)?;
+        let node = RpcChainSource::new(Web3::new(transport), throttle);
+
+        node.block_number().await?;
+
+        for number in chain.blocks().filter_map(|b| b.number) {
+            node.block_with_txs(number.as_u64()).await?;
+        }
+    }
+
+    Ok(path)
+}
+
+/// Serves chain kept in memory over JSON-RPC, as a node would.
+#[derive(Debug, Clone)]
+struct MemoryTransport {
+    chain: Arc<MemoryChainSource>,
+    id: Arc<AtomicUsize>,
+}
+
+impl MemoryTransport {
+    fn new(chain: MemoryChainSource) -> Self {
+        MemoryTransport {
+            chain: Arc::new(chain),
+            id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+}
+
+async fn respond(chain: &MemoryChainSource, method: &str, params: &[Value]) -> Result<Value> {
+    let number = || params.first()
+        .and_then(|p| serde_json::from_value::<U64>(p.clone()).ok())
+        .map(|n| n.as_u64())
+        .unwrap_or_default();
+
+    Ok(match method {
+        "eth_blockNumber" => serde_json::to_value(U64::from(chain.block_number().await?))?,
+        "eth_getBlockByNumber" if params.get(1) == Some(&Value::Bool(true)) => serde_json::to_value(chain.block_with_txs(number()).await?)?,
+        "eth_getBlockByNumber" => serde_json::to_value(chain.block(number()).await?)?,
+        "eth_getTransactionReceipt" => {
+            let hash = serde_json::from_value(params.first().cloned().unwrap_or_default())?;
+            serde_json::to_value(chain.transaction_receipt(hash).await?)?
+        }
+        _ => anyhow::bail!("{} is not served by memory chain", method),
+    })
+}
+
+impl web3::Transport for MemoryTransport {
+    type Out = LocalBoxFuture<'static, web3::error::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        (id, web3::helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let chain = self.chain.clone();
+
+        Box::pin(async move {
+            let (method, params) = match request {
+                rpc::Call::MethodCall(c) => (c.method, c.params),
+                _ => return Err(web3::Error::Transport("Only method calls are served".into())),
+            };
+
+            let params = match params {
+                rpc::Params::Array(params) => params,
+                _ => vec![],
+            };
+
+            respond(&chain, &method, &params).await
+                .map_err(|e| web3::Error::Transport(format!("{:?}", e)))
+        })
+    }
+}
@@ -0,0 +1,328 @@
+use std::io::{ErrorKind, Read};
+
+use anyhow::{bail, Context, Result};
+use rlp::{Rlp, RlpStream};
+use secp256k1::{Message, Secp256k1, VerifyOnly};
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use tiny_keccak::{Hasher, Keccak};
+use web3::types::{Block, Bytes, H160, H2048, H256, H64, Index, Transaction, U256};
+
+/// Reads blocks written by `geth export` / `besu blocks export`: RLP encoded blocks one after another.
+/// Block is `[header, transactions, uncles]`.
+pub struct RlpBlocks<R> {
+    reader: R,
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl<R: Read> RlpBlocks<R> {
+    pub fn new(reader: R) -> Self {
+        RlpBlocks {
+            reader,
+            secp: Secp256k1::verification_only(),
+        }
+    }
+
+    /// Raw RLP of the next block. `None` at the end of export.
+    fn next_item(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut prefix = [0u8; 1];
+
+        match self.reader.read_exact(&mut prefix) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut item = vec![prefix[0]];
+
+        let payload_len = match prefix[0] {
+            b @ 0xc0..=0xf7 => (b - 0xc0) as usize,
+            b @ 0xf8..=0xff => {
+                let mut len = vec![0u8; (b - 0xf7) as usize];
+                self.reader.read_exact(&mut len)?;
+                item.extend_from_slice(&len);
+
+                len.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+            }
+            b => bail!("Block expected to be RLP list. Got prefix {:#x}", b),
+        };
+
+        let header_len = item.len();
+        item.resize(header_len + payload_len, 0);
+        self.reader.read_exact(&mut item[header_len..]).context("Truncated block")?;
+
+        Ok(Some(item))
+    }
+
+    fn decode_block(&self, raw: &[u8]) -> Result<Block<Transaction>> {
+        let block = Rlp::new(raw);
+        let header = block.at(0)?;
+
+        let number = uint(&header.at(8)?)?.as_u64();
+        let hash = keccak(header.as_raw());
+        let base_fee = match header.item_count()? {
+            n if n > 15 => Some(uint(&header.at(15)?)?),
+            _ => None,
+        };
+
+        let transactions = block.at(1)?.iter()
+            .enumerate()
+            .map(|(i, trx)| self.decode_transaction(&trx, base_fee)
+                .map(|mut t| {
+                    t.block_hash = Some(hash);
+                    t.block_number = Some(number.into());
+                    t.transaction_index = Some(Index::from(i));
+                    t
+                })
+                .with_context(|| format!("Bad transaction {} in block {}", i, number)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let uncles = block.at(2)?.iter()
+            .map(|u| keccak(u.as_raw()))
+            .collect();
+
+        Ok(Block {
+            hash: Some(hash),
+            parent_hash: H256::from_slice(header.at(0)?.data()?),
+            uncles_hash: H256::from_slice(header.at(1)?.data()?),
+            author: H160::from_slice(header.at(2)?.data()?),
+            state_root: H256::from_slice(header.at(3)?.data()?),
+            transactions_root: H256::from_slice(header.at(4)?.data()?),
+            receipts_root: H256::from_slice(header.at(5)?.data()?),
+            logs_bloom: Some(H2048::from_slice(header.at(6)?.data()?)),
+            difficulty: uint(&header.at(7)?)?,
+            number: Some(number.into()),
+            gas_limit: uint(&header.at(9)?)?,
+            gas_used: uint(&header.at(10)?)?,
+            timestamp: uint(&header.at(11)?)?,
+            extra_data: Bytes(header.at(12)?.data()?.to_vec()),
+            mix_hash: Some(H256::from_slice(header.at(13)?.data()?)),
+            nonce: Some(H64::from_slice(header.at(14)?.data()?)),
+            total_difficulty: None,
+            seal_fields: vec![],
+            uncles,
+            transactions,
+            size: Some(raw.len().into()),
+        })
+    }
+
+    /// Legacy transaction is RLP list, typed one (EIP-2718) is a string of `type || rlp(payload)`.
+    fn decode_transaction(&self, trx: &Rlp, base_fee: Option<U256>) -> Result<Transaction> {
+        if trx.is_list() {
+            return self.decode_legacy(trx);
+        }
+
+        let data = trx.data()?;
+
+        match data.first() {
+            Some(&tx_type) if tx_type == 1 || tx_type == 2 => self.decode_typed(tx_type, data, base_fee),
+            t => bail!("Unsupported transaction type {:?}", t),
+        }
+    }
+
+    /// `[nonce, gasPrice, gas, to, value, data, v, r, s]`
+    fn decode_legacy(&self, trx: &Rlp) -> Result<Transaction> {
+        let v = uint(&trx.at(6)?)?.as_u64();
+
+        let mut unsigned = RlpStream::new_list(if v >= 35 { 9 } else { 6 });
+        for i in 0..6 {
+            unsigned.append_raw(trx.at(i)?.as_raw(), 1);
+        }
+
+        // EIP-155 replay protection signs chain id as well
+        let recovery_id = match v {
+            27 | 28 => v - 27,
+            v if v >= 35 => {
+                unsigned.append(&((v - 35) / 2));
+                unsigned.append(&0u8);
+                unsigned.append(&0u8);
+                (v - 35) % 2
+            }
+            v => bail!("Bad signature v {}", v),
+        };
+
+        let from = self.recover(&keccak(&unsigned.out()), recovery_id, &trx.at(7)?, &trx.at(8)?)?;
+
+        Ok(Transaction {
+            hash: keccak(trx.as_raw()),
+            nonce: uint(&trx.at(0)?)?,
+            gas_price: uint(&trx.at(1)?)?,
+            gas: uint(&trx.at(2)?)?,
+            to: address(&trx.at(3)?)?,
+            value: uint(&trx.at(4)?)?,
+            input: Bytes(trx.at(5)?.data()?.to_vec()),
+            from,
+            block_hash: None,
+            block_number: None,
+            transaction_index: None,
+            raw: None,
+        })
+    }
+
+    /// EIP-2930: `[chainId, nonce, gasPrice, gas, to, value, data, accessList, yParity, r, s]`
+    /// EIP-1559: `[chainId, nonce, maxPriorityFee, maxFee, gas, to, value, data, accessList, yParity, r, s]`
+    fn decode_typed(&self, tx_type: u8, data: &[u8], base_fee: Option<U256>) -> Result<Transaction> {
+        let payload = Rlp::new(&data[1..]);
+        let fields = payload.item_count()?;
+
+        if fields < 3 {
+            bail!("Typed transaction has {} fields only", fields);
+        }
+        let signed = fields - 3;
+
+        let mut unsigned = RlpStream::new_list(signed);
+        for i in 0..signed {
+            unsigned.append_raw(payload.at(i)?.as_raw(), 1);
+        }
+
+        let mut sighash = vec![tx_type];
+        sighash.extend_from_slice(&unsigned.out());
+
+        let recovery_id = uint(&payload.at(signed)?)?.as_u64();
+        let from = self.recover(&keccak(&sighash), recovery_id, &payload.at(signed + 1)?, &payload.at(signed + 2)?)?;
+
+        // the rest of fields are shifted by one for dynamic fee transaction
+        let shift = (tx_type == 2) as usize;
+
+        let gas_price = match (tx_type, base_fee) {
+            (2, Some(base_fee)) => {
+                let max_priority_fee = uint(&payload.at(2)?)?;
+                let max_fee = uint(&payload.at(3)?)?;
+                max_fee.min(base_fee + max_priority_fee)
+            }
+            _ => uint(&payload.at(2 + shift)?)?,
+        };
+
+        Ok(Transaction {
+            hash: keccak(data),
+            nonce: uint(&payload.at(1)?)?,
+            gas_price,
+            gas: uint(&payload.at(3 + shift)?)?,
+            to: address(&payload.at(4 + shift)?)?,
+            value: uint(&payload.at(5 + shift)?)?,
+            input: Bytes(payload.at(6 + shift)?.data()?.to_vec()),
+            from,
+            block_hash: None,
+            block_number: None,
+            transaction_index: None,
+            raw: None,
+        })
+    }
+
+    fn recover(&self, hash: &H256, recovery_id: u64, r: &Rlp, s: &Rlp) -> Result<H160> {
+        let mut signature = [0u8; 64];
+        let (r, s) = (r.data()?, s.data()?);
+        signature[32 - r.len()..32].copy_from_slice(r);
+        signature[64 - s.len()..].copy_from_slice(s);
+
+        let signature = RecoverableSignature::from_compact(&signature, RecoveryId::from_i32(recovery_id as i32)?)?;
+        let public = self.secp.recover(&Message::from_slice(hash.as_bytes())?, &signature)?;
+
+        // address is the last 20 bytes of public key hash. Key prefix byte is skipped
+        let public_hash = keccak(&public.serialize_uncompressed()[1..]);
+        Ok(H160::from_slice(&public_hash.as_bytes()[12..]))
+    }
+}
+
+impl<R: Read> Iterator for RlpBlocks<R> {
+    type Item = Result<Block<Transaction>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_item() {
+            Ok(Some(raw)) => Some(self.decode_block(&raw)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn keccak(input: &[u8]) -> H256 {
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(input);
+    keccak.finalize(&mut hash);
+    H256::from(hash)
+}
+
+fn uint(item: &Rlp) -> Result<U256> {
+    let data = item.data()?;
+
+    if data.len() > 32 {
+        bail!("Integer overflow: {} bytes", data.len());
+    }
+
+    Ok(U256::from_big_endian(data))
+}
+
+/// Empty for contract creation.
+fn address(item: &Rlp) -> Result<Option<H160>> {
+    Ok(match item.data()? {
+        [] => None,
+        a if a.len() == 20 => Some(H160::from_slice(a)),
+        a => bail!("Bad address length {}", a.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mainnet transaction used as EIP-155 example
+    const EIP155_TRX: &str = "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+
+    #[test]
+    fn decode_eip155_transaction() -> Result<()> {
+        let raw = hex::decode(EIP155_TRX)?;
+        let blocks = RlpBlocks::new(&[][..]);
+
+        let trx = blocks.decode_transaction(&Rlp::new(&raw), None)?;
+
+        assert_eq!(trx.nonce, 9.into());
+        assert_eq!(trx.to, Some(H160::from_slice(&[0x35; 20])));
+        assert_eq!(trx.value, U256::exp10(18));
+        assert_eq!(format!("{:#x}", trx.from), "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f");
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_concatenated_blocks() -> Result<()> {
+        let trx = hex::decode(EIP155_TRX)?;
+        let mut export = vec![];
+
+        for number in 0..2u64 {
+            let mut header = RlpStream::new_list(15);
+            header.append(&vec![0u8; 32]);
+            header.append(&vec![0u8; 32]);
+            header.append(&vec![0u8; 20]);
+            for _ in 0..3 {
+                header.append(&vec![0u8; 32]);
+            }
+            header.append(&vec![0u8; 256]);
+            header.append(&1u8);
+            header.append(&number);
+            header.append(&8_000_000u64);
+            header.append(&21_000u64);
+            header.append(&(1_600_000_000u64 + number));
+            header.append_empty_data();
+            header.append(&vec![0u8; 32]);
+            header.append(&vec![0u8; 8]);
+
+            let mut block = RlpStream::new_list(3);
+            block.append_raw(&header.out(), 1);
+            block.begin_list(1);
+            block.append_raw(&trx, 1);
+            block.begin_list(0);
+
+            export.extend_from_slice(&block.out());
+        }
+
+        let blocks = RlpBlocks::new(&export[..]).collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].number, Some(1.into()));
+        assert_eq!(blocks[1].transactions[0].block_number, Some(1.into()));
+        assert_eq!(blocks[1].transactions[0].nonce, 9.into());
+
+        Ok(())
+    }
+}
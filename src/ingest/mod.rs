@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Error, Result};
+use flate2::read::MultiGzDecoder;
+use log::{debug, info};
+use serde::Deserialize;
+use web3::types::{Block, Transaction};
+
+use crate::es::ContractProcessor;
+use crate::traversal::ChainData;
+
+pub(crate) use export::RlpBlocks;
+
+mod export;
+
+/// Blocks saved per `save_chain_data` call.
+const INGEST_CHUNK: u64 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IngestFormat {
+    /// `geth export` / `besu blocks export` output
+    Rlp,
+    /// `eth_getBlockByNumber` results with full transactions, one per line
+    Jsonl,
+}
+
+impl FromStr for IngestFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "rlp" => IngestFormat::Rlp,
+            "jsonl" | "json" => IngestFormat::Jsonl,
+            _ => bail!("Unknown ingest format: {}", s),
+        })
+    }
+}
+
+impl IngestFormat {
+    /// `.jsonl`/`.json` (optionally gzipped) are JSON dumps, everything else is RLP export.
+    pub fn detect(path: &Path) -> IngestFormat {
+        let name = path.to_string_lossy().to_lowercase();
+        let name = name.trim_end_matches(".gz");
+
+        if name.ends_with(".jsonl") || name.ends_with(".json") {
+            IngestFormat::Jsonl
+        } else {
+            IngestFormat::Rlp
+        }
+    }
+}
+
+/// Dump line is either a bare block or the whole JSON-RPC response.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonBlock {
+    Response { result: Block<Transaction> },
+    Block(Block<Transaction>),
+}
+
+fn json_blocks<R: BufRead>(reader: R) -> impl Iterator<Item=Result<Block<Transaction>>> {
+    reader.lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+        .map(|(i, line)| {
+            let block = serde_json::from_str(&line?).with_context(|| format!("Bad block at line {}", i + 1))?;
+
+            Ok(match block {
+                JsonBlock::Response { result } => result,
+                JsonBlock::Block(block) => block,
+            })
+        })
+}
+
+fn open(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path).with_context(|| format!("Can't open {:?}", path))?;
+
+    Ok(match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+/// Loads exported blocks through the same pipeline as live traversal, so no node is needed for backfill.
+/// Blocks are expected in ascending order.
+pub async fn ingest(path: &Path, format: IngestFormat, chain_id: u64, contract_processor: Arc<ContractProcessor>) -> Result<()> {
+    let reader = BufReader::new(open(path)?);
+
+    let blocks: Box<dyn Iterator<Item=Result<Block<Transaction>>>> = match format {
+        IngestFormat::Rlp => Box::new(RlpBlocks::new(reader)),
+        IngestFormat::Jsonl => Box::new(json_blocks(reader)),
+    };
+
+    let storage = contract_processor.get_storage();
+    let mut contracts = storage.get_contracts(chain_id).await?;
+    let watched = storage.get_watchlist(chain_id).await?;
+
+    if contracts.is_empty() && watched.is_empty() {
+        bail!("No contracts or watched addresses of chain {} to ingest blocks for. Upload ABI first", chain_id);
+    }
+
+    let filter = crate::live::filter(&contracts, &watched);
+
+    info!("Ingesting {:?} as {:?} for {:?} on chain {}", path, format, filter, chain_id);
+
+    let total_time = Instant::now();
+    let mut chunk: Option<Range<u64>> = None;
+    let mut found = vec![];
+    let mut total = 0;
+
+    for block in blocks {
+        let block = block?;
+        let number = match block.number {
+            Some(n) => n.as_u64(),
+            None => bail!("Block without number: {:?}", block.hash),
+        };
+
+        let range = chunk.get_or_insert(number..number);
+
+        if number != range.end {
+            bail!("Blocks are not contiguous. Expected {} got {}", range.end, number);
+        }
+        range.end += 1;
+        total += 1;
+
+        let related = block.transactions.iter()
+            .any(|t| filter.matches(&t.from, t.to.as_ref()));
+
+        if related {
+            found.push(block);
+        }
+
+        if range.end - range.start >= INGEST_CHUNK {
+            let range = chunk.take().unwrap();
+            chunk = Some(range.end..range.end);
+
+            save(&contract_processor, chain_id, &mut contracts, &watched, range, std::mem::take(&mut found)).await?;
+        }
+    }
+
+    if let Some(range) = chunk.filter(|r| !r.is_empty()) {
+        save(&contract_processor, chain_id, &mut contracts, &watched, range, found).await?;
+    }
+
+    info!("Ingested {} blocks from {:?} in {:?}", total, path, Instant::now() - total_time);
+
+    Ok(())
+}
+
+async fn save(contract_processor: &ContractProcessor, chain_id: u64, contracts: &mut [crate::mongo::model::Contract],
+              watched: &[crate::mongo::model::WatchedAddress], range: Range<u64>, blocks: Vec<Block<Transaction>>) -> Result<()> {
+    debug!("Saving range {:?}. Blocks found: {}", range, blocks.len());
+
+    let storage = contract_processor.get_storage();
+    let chain_data = ChainData::new(range.clone(), blocks, vec![range.clone()]);
+
+    crate::live::save_chain_data(contract_processor, chain_id, contracts, watched, &chain_data).await?;
+    crate::live::record_progress(storage.as_ref(), contracts, &chain_data.covered).await?;
+    crate::live::record_watch_progress(storage.as_ref(), watched, &chain_data.covered).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(IngestFormat::detect(Path::new("blocks.jsonl.gz")), IngestFormat::Jsonl);
+        assert_eq!(IngestFormat::detect(Path::new("export.rlp")), IngestFormat::Rlp);
+        assert_eq!(IngestFormat::detect(Path::new("export")), IngestFormat::Rlp);
+    }
+}
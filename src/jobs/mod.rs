@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures_util::pin_mut;
+use futures_util::stream::StreamExt;
+use log::{debug, info, warn};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::config::ChainConfig;
+use crate::es::ContractProcessor;
+use crate::mongo::model::{Contract, Job, JobKind, JobStatus, WatchedAddress};
+use crate::shutdown::Shutdown;
+use crate::traversal::batch::TraverseGuard;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::{self, ChainSource};
+
+/// How often busy traversal lock is checked by a waiting job.
+const GUARD_POLL: Duration = Duration::from_secs(5);
+/// Queue is checked that often even if nobody wakes runner up.
+const QUEUE_POLL: Duration = Duration::from_secs(30);
+/// Stored transactions decoded per ES request.
+const DECODE_BATCH: usize = 500;
+/// How often running jobs are checked on shutdown.
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+lazy_static! {
+    /// Wakes job runner of a chain to start queued jobs right away.
+    static ref WAKEUPS: Mutex<HashMap<u64, Arc<Notify>>> = Mutex::new(HashMap::new());
+}
+
+fn wakeup(chain_id: u64) -> Arc<Notify> {
+    WAKEUPS.lock().unwrap()
+        .entry(chain_id)
+        .or_insert_with(|| Arc::new(Notify::new()))
+        .clone()
+}
+
+/// Makes job runner of chain check the queue now.
+pub fn wake(chain_id: u64) {
+    wakeup(chain_id).notify();
+}
+
+/// Backfill and gap fill of live contracts hold traversal lock of the whole chain,
+/// so they run one at a time.
+fn saturated(running: &HashMap<String, JobKind>) -> Vec<JobKind> {
+    let locking = [JobKind::Backfill, JobKind::GapFill];
+
+    if running.values().any(|k| locking.contains(k)) {
+        locking.to_vec()
+    } else {
+        vec![]
+    }
+}
+
+fn blocks(ranges: &[Range<u64>]) -> i64 {
+    ranges.iter().map(|r| r.end - r.start).sum::<u64>() as i64
+}
+
+/// Runs queued jobs of a chain by priority, at most `max_jobs` at once.
+#[derive(Debug, Clone)]
+pub struct JobRunner {
+    chain_id: u64,
+    chain_url: String,
+    max_jobs: usize,
+    contract_processor: Arc<ContractProcessor>,
+    throttle: Arc<Throttle>,
+    /// Ids and kinds of jobs being run
+    running: Arc<Mutex<HashMap<String, JobKind>>>,
+    shutdown: Shutdown,
+}
+
+impl JobRunner {
+    pub fn new(chain: &ChainConfig, max_jobs: usize, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+               shutdown: Shutdown) -> Self {
+        Self {
+            chain_id: chain.id,
+            chain_url: chain.url.clone(),
+            max_jobs: max_jobs.max(1),
+            contract_processor,
+            throttle,
+            running: Arc::new(Mutex::new(HashMap::new())),
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) -> Result<JoinHandle<()>> {
+        let source = source::create_source(&self.chain_url, self.throttle.clone()).await?;
+
+        // jobs interrupted by restart
+        self.contract_processor.get_storage().requeue_running_jobs(self.chain_id).await?;
+
+        let wakeup = wakeup(self.chain_id);
+
+        Ok(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(QUEUE_POLL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = wakeup.notified() => debug!("Job runner of chain {} woken up", self.chain_id),
+                    _ = self.shutdown.requested() => break,
+                }
+
+                if let Err(e) = self.start_jobs(source.clone()).await {
+                    warn!("Failed to start jobs of chain {}. {:?}", self.chain_id, e);
+                }
+            }
+
+            // running jobs stop after the chunk they work on
+            while !self.running.lock().unwrap().is_empty() {
+                tokio::time::delay_for(STOP_POLL).await;
+            }
+
+            info!("Job runner of chain {} stopped", self.chain_id);
+        }))
+    }
+
+    async fn start_jobs(&self, source: Arc<dyn ChainSource>) -> Result<()> {
+        let storage = self.contract_processor.get_storage();
+
+        while !self.shutdown.is_requested() {
+            let exclude = {
+                let running = self.running.lock().unwrap();
+
+                if running.len() >= self.max_jobs {
+                    return Ok(());
+                }
+                saturated(&running)
+            };
+
+            let job = match storage.next_job(self.chain_id, &exclude).await? {
+                Some(job) => job,
+                None => return Ok(()),
+            };
+
+            info!("Starting {:?} job {}", job.kind, job.id);
+
+            self.running.lock().unwrap().insert(job.id.clone(), job.kind);
+
+            let runner = self.clone();
+            let source = source.clone();
+
+            tokio::spawn(async move {
+                let id = job.id.clone();
+
+                if let Err(e) = runner.finish(source, job).await {
+                    warn!("Failed to save outcome of job {}. {:?}", id, e);
+                }
+
+                runner.running.lock().unwrap().remove(&id);
+                wake(runner.chain_id);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Runs job and saves its outcome. Job paused or cancelled meanwhile keeps status set by user.
+    async fn finish(&self, source: Arc<dyn ChainSource>, mut job: Job) -> Result<()> {
+        job.started_at = Some(chrono::Utc::now().timestamp_millis());
+
+        job.status = match self.execute(source, &mut job).await {
+            Ok(true) => JobStatus::Done,
+            Ok(false) if self.shutdown.is_requested() => {
+                // continues after restart
+                self.contract_processor.get_storage().set_job_status(&job.id, &[JobStatus::Running], JobStatus::Queued).await?;
+                info!("Job {} interrupted by shutdown", job.id);
+                return Ok(());
+            }
+            Ok(false) => {
+                info!("Job {} stopped", job.id);
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("Job {} failed. {:?}", job.id, e);
+                job.error = Some(format!("{:?}", e));
+                JobStatus::Failed
+            }
+        };
+
+        info!("Job {} is {:?}", job.id, job.status);
+
+        self.report(&mut job).await?;
+        Ok(())
+    }
+
+    /// Saves progress. False means job was paused, cancelled or shutdown is requested and job has to stop.
+    async fn report(&self, job: &mut Job) -> Result<bool> {
+        job.updated_at = chrono::Utc::now().timestamp_millis();
+        let running = self.contract_processor.get_storage().update_running_job(job).await?;
+
+        Ok(running && !self.shutdown.is_requested())
+    }
+
+    /// Ok(false) if job stopped before it was done.
+    async fn execute(&self, source: Arc<dyn ChainSource>, job: &mut Job) -> Result<bool> {
+        let storage = self.contract_processor.get_storage();
+
+        if job.watched {
+            return match storage.get_watched(self.chain_id, &job.address).await? {
+                Some(w) => self.traverse(source, job, vec![], vec![w]).await,
+                None => bail!("Watched address {} not found", job.address),
+            };
+        }
+
+        let contract = match storage.get_contract(self.chain_id, &job.address).await? {
+            Some(c) => c,
+            None => bail!("Contract {} not found", job.address),
+        };
+
+        if job.kind.is_traversal() {
+            self.traverse(source, job, vec![contract], vec![]).await
+        } else {
+            self.decode(job, contract).await
+        }
+    }
+
+    /// Scans blocks of job range not covered yet, so stopped job continues where it stopped.
+    /// Job is either of a contract or of a watched address.
+    async fn traverse(&self, source: Arc<dyn ChainSource>, job: &mut Job, mut contracts: Vec<Contract>, watched: Vec<WatchedAddress>) -> Result<bool> {
+        let storage = self.contract_processor.get_storage();
+
+        // initial backfill covers contract not followed by live scraper, so they don't need to wait for each other
+        let _guard = match job.kind {
+            JobKind::Initial => {
+                job.range.end = job.range.start.max(source.block_number().await? as i64);
+                None
+            }
+            _ => Some(loop {
+                if let Some(guard) = TraverseGuard::acquire(self.chain_id) {
+                    break guard;
+                }
+                tokio::time::delay_for(GUARD_POLL).await;
+            }),
+        };
+
+        let mut range = job.range.start as u64..job.range.end as u64;
+        let coverage = match job.watched {
+            true => storage.get_watch_coverage(self.chain_id, &job.address).await?,
+            false => storage.get_coverage(self.chain_id, &job.address).await?,
+        };
+
+        job.total = blocks(&[range.clone()]);
+        job.done = job.total - blocks(&coverage.covered.missing(&range));
+        job.started_done = job.done;
+
+        if !self.report(job).await? {
+            return Ok(false);
+        }
+
+        // contract created at the head has no history
+        if !range.is_empty() {
+            let strategy = crate::live::resolve_strategy(self.throttle.config.strategy, &contracts, &watched);
+            let blooms = crate::live::stored_blooms(storage.as_ref(), self.chain_id, strategy, &range).await?;
+
+            let stream = crate::traversal::batch::traversal(source, crate::live::filter(&contracts, &watched), &mut range,
+                                                            self.throttle.clone(), strategy, &coverage.covered, blooms).await?;
+
+            let stream = match stream {
+                Some(s) => s,
+                None => bail!("Range {:?} is ahead of chain", range),
+            };
+
+            pin_mut!(stream);
+
+            while let Some(chain_data) = stream.next().await {
+                crate::live::save_chain_data(&self.contract_processor, self.chain_id, &contracts, &watched, &chain_data).await?;
+                crate::live::record_progress(storage.as_ref(), &mut contracts, &chain_data.covered).await?;
+                crate::live::record_watch_progress(storage.as_ref(), &watched, &chain_data.covered).await?;
+
+                job.done += blocks(&chain_data.covered);
+
+                if !self.report(job).await? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        if job.kind == JobKind::Initial {
+            let contract = &mut contracts[0];
+            contract.backfilling = false;
+            storage.update_contract(contract).await?;
+
+            info!("Contract {} caught up at {}. Following it live", contract.address, job.range.end);
+        }
+
+        Ok(true)
+    }
+
+    /// Decodes stored transactions of job range with current ABI of contract and indexes them.
+    /// Indexing is idempotent, so stopped job starts over.
+    async fn decode(&self, job: &mut Job, contract: Contract) -> Result<bool> {
+        let storage = self.contract_processor.get_storage();
+        let range = job.range.start as u64..job.range.end as u64;
+
+        job.total = storage.count_trx_to(self.chain_id, &job.address, &range).await?;
+        job.done = 0;
+        job.started_done = 0;
+
+        if !self.report(job).await? {
+            return Ok(false);
+        }
+
+        if job.kind == JobKind::Reindex {
+            self.contract_processor.drop_indexed(&contract).await?;
+        }
+
+        let mut after = None;
+
+        loop {
+            let page = storage.find_trx_to(self.chain_id, &job.address, after, DECODE_BATCH as u32).await?;
+
+            after = match page.last() {
+                Some(trx) => Some(trx.hash),
+                None => break,
+            };
+
+            let batch: Vec<_> = page.into_iter()
+                .filter(|trx| range.contains(&trx.block_number.as_u64()))
+                .collect();
+            job.done += batch.len() as i64;
+
+            self.contract_processor.process_contract(&contract, batch.iter()).await?;
+
+            if !self.report(job).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Bytes, H160, H256, Index, U256};
+
+    use crate::fixtures::{processor, storage};
+    use crate::mongo::model::Transaction;
+    use crate::traversal::config::TraversalConfig;
+
+    use super::*;
+
+    #[test]
+    fn chain_locking_kinds_run_one_at_a_time() {
+        let mut running = HashMap::new();
+        assert!(saturated(&running).is_empty());
+
+        running.insert("initial:1:0x01".to_string(), JobKind::Initial);
+        running.insert("redecode:1:0x02".to_string(), JobKind::Redecode);
+        assert!(saturated(&running).is_empty());
+
+        running.insert("gap:1:0x01:0-10".to_string(), JobKind::GapFill);
+        assert_eq!(saturated(&running), vec![JobKind::Backfill, JobKind::GapFill]);
+    }
+
+    fn trx(n: u64, to: H160) -> Transaction {
+        Transaction::new(1, &web3::types::Transaction {
+            hash: H256::from_low_u64_be(n),
+            block_number: Some(n.into()),
+            transaction_index: Some(Index::from(0)),
+            to: Some(to),
+            // unknown method
+            input: Bytes(vec![0xde, 0xad, 0xbe, 0xef]),
+            ..Default::default()
+        }, U256::zero())
+    }
+
+    #[tokio::test]
+    async fn decode_range() -> Result<()> {
+        let storage = storage().await?;
+
+        let to = H160::from_low_u64_be(0xc0);
+        let address = format!("{:#x}", to);
+        let transactions: Vec<_> = (1..=10).map(|n| trx(n, to)).collect();
+        storage.save_transactions(&transactions).await?;
+
+        let contract = Contract::new(1, &address, serde_json::from_str("[]")?);
+        storage.save_contract(&contract).await?;
+
+        let chain = ChainConfig { id: 1, name: "test".into(), url: "file://none".into() };
+        let runner = JobRunner::new(&chain, 1, processor(&storage), Arc::new(Throttle::new(TraversalConfig::default())), Shutdown::default());
+
+        storage.enqueue_job(&Job::new(JobKind::Redecode, 1, &address, 3..7)).await?;
+        let mut job = storage.next_job(1, &[]).await?.unwrap();
+
+        assert!(runner.decode(&mut job, contract).await?);
+        assert_eq!(job.total, 4);
+        assert_eq!(job.done, 4);
+        assert_eq!(job.progress(), 100.0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use tokio::task::JoinHandle;
+
+use crate::config::ChainConfig;
+use crate::es::ContractProcessor;
+use crate::mongo::model::{Coverage, Job};
+use crate::shutdown::Shutdown;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::{self, ChainSource};
+
+/// Compares scanned ranges of tracked contracts and watched addresses with the chain, reports holes
+/// and queues gap fill jobs for ones behind live scraper.
+#[derive(Debug)]
+pub struct Auditor {
+    chain_id: u64,
+    chain_url: String,
+    interval: Duration,
+    contract_processor: Arc<ContractProcessor>,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
+}
+
+impl Auditor {
+    pub fn new(chain: &ChainConfig, interval: Duration, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+               shutdown: Shutdown) -> Self {
+        Self {
+            chain_id: chain.id,
+            chain_url: chain.url.clone(),
+            interval,
+            contract_processor,
+            throttle,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) -> Result<JoinHandle<()>> {
+        let source = source::create_source(&self.chain_url, self.throttle.clone()).await?;
+
+        Ok(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = self.shutdown.requested() => break,
+                }
+
+                match self.audit(source.as_ref()).await {
+                    Ok(report) if !report.is_empty() => crate::jobs::wake(self.chain_id),
+                    Ok(_) => (),
+                    Err(e) => warn!("Audit failed. {:?}", e),
+                }
+            }
+        }))
+    }
+
+    /// Finds holes in scanned ranges of every contract and watched address up to chain head.
+    /// Ones behind the tail followed by live scraper are queued for gap fill.
+    pub async fn audit(&self, source: &dyn ChainSource) -> Result<Vec<(String, Vec<Range<u64>>)>> {
+        let storage = self.contract_processor.get_storage();
+        let head = source.block_number().await?;
+        let mut report = vec![];
+
+        for contract in storage.get_contracts(self.chain_id).await? {
+            // history of backfilling contract is not complete yet, paused one isn't scanned at all
+            if contract.backfilling || contract.paused {
+                continue;
+            }
+
+            let coverage = storage.get_coverage(self.chain_id, &contract.address).await?;
+            let start = contract.create_block.unwrap_or(0).max(0) as u64;
+            let holes = find_holes(&coverage, start, head);
+
+            if holes.is_empty() {
+                debug!("No holes for {}", contract.address);
+                continue;
+            }
+
+            warn!("Found {} holes for {}: {:?}", holes.len(), contract.address, holes);
+
+            for hole in behind_live(&coverage, start, head, &holes) {
+                if storage.enqueue_job(&Job::gap_fill(self.chain_id, &contract.address, hole.clone())).await? {
+                    info!("Queued gap fill of {:?} for {}", hole, contract.address);
+                }
+            }
+
+            report.push((contract.id, holes));
+        }
+
+        for watched in storage.get_watchlist(self.chain_id).await? {
+            // not seen by live scraper yet
+            let start = match watched.start_block {
+                Some(start) => start.max(0) as u64,
+                None => continue,
+            };
+
+            let coverage = storage.get_watch_coverage(self.chain_id, &watched.address).await?;
+            let holes = find_holes(&coverage, start, head);
+
+            if holes.is_empty() {
+                debug!("No holes for {}", watched.id);
+                continue;
+            }
+
+            warn!("Found {} holes for {}: {:?}", holes.len(), watched.id, holes);
+
+            for hole in behind_live(&coverage, start, head, &holes) {
+                if storage.enqueue_job(&Job::gap_fill_watched(self.chain_id, &watched.address, hole.clone())).await? {
+                    info!("Queued gap fill of {:?} for {}", hole, watched.id);
+                }
+            }
+
+            report.push((watched.id, holes));
+        }
+
+        Ok(report)
+    }
+}
+
+/// Uncovered ranges from the first block of interest up to `head`, missing prefix included.
+pub fn find_holes(coverage: &Coverage, start: u64, head: u64) -> Vec<Range<u64>> {
+    coverage.covered.missing(&(start..head.max(start)))
+}
+
+/// Holes live scraper doesn't follow, so they are left to gap fill.
+fn behind_live<'a>(coverage: &Coverage, start: u64, head: u64, holes: &'a [Range<u64>]) -> impl Iterator<Item = &'a Range<u64>> {
+    let tail = super::tail(&coverage.covered, start, head);
+
+    holes.iter().filter(move |h| h.end <= tail.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_holes_up_to_head() {
+        let mut coverage = Coverage::new(1, "0x01");
+
+        assert_eq!(find_holes(&coverage, 50, 600), vec![50..600]);
+        assert!(find_holes(&coverage, 50, 50).is_empty());
+
+        coverage.covered.insert(100..200);
+        coverage.covered.insert(250..300);
+        coverage.covered.insert(400..500);
+
+        let holes = find_holes(&coverage, 50, 600);
+        assert_eq!(holes, vec![50..100, 200..250, 300..400, 500..600]);
+
+        // the tail is followed by live scraper
+        assert_eq!(behind_live(&coverage, 50, 600, &holes).cloned().collect::<Vec<_>>(), vec![50..100, 200..250, 300..400]);
+    }
+}
@@ -1,185 +1,617 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use clokwerk::{Interval, ScheduleHandle, Scheduler};
 use futures_util::pin_mut;
 use futures_util::stream::StreamExt;
-use log::{info, debug};
+use log::{info, debug, warn};
+use tokio::task::JoinHandle;
+use web3::types::H2048;
+use web3::Web3;
 
+use crate::config::ChainConfig;
 use crate::es::ContractProcessor;
-use crate::mongo::model::{ChainDataDO, Contract};
+use crate::mongo::model::{ChainDataDO, Contract, Job, JobStatus, WatchedAddress};
 use crate::mongo::model::Transaction;
-use crate::mongo::MongoDB;
+use crate::storage::Storage;
+use crate::shutdown::Shutdown;
+use crate::traversal::config::TraversalStrategy;
+use crate::traversal::connection;
+use crate::traversal::{ChainData, RangeSet, Role, TrxFilter};
+use crate::traversal::batch::TraverseGuard;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::{self, ChainSource};
 use std::ops::Range;
 
+pub(crate) mod audit;
+pub(crate) mod pending;
+pub(crate) mod planner;
+
+/// Subscription considered healthy once it lived that long. Resets reconnect backoff.
+const HEALTHY_SUBSCRIPTION: Duration = Duration::from_secs(60);
+const MAX_RECONNECT_BACKOFF_POW: u32 = 6;
+
 #[derive(Debug)]
 pub struct ScheduledScraper {
     timeout_sec: u64,
+    chain_id: u64,
     chain_url: String,
     contract_processor: Arc<ContractProcessor>,
+    throttle: Arc<Throttle>,
+    shutdown: Shutdown,
 }
 
 impl ScheduledScraper {
-    pub fn new(timeout_sec: u64, chain_url: &str, contract_processor: Arc<ContractProcessor>) -> Self {
+    pub fn new(timeout_sec: u64, chain: &ChainConfig, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+               shutdown: Shutdown) -> Self {
         Self {
             timeout_sec,
-            chain_url: chain_url.into(),
+            chain_id: chain.id,
+            chain_url: chain.url.clone(),
             contract_processor,
+            throttle,
+            shutdown,
         }
     }
 
-    pub async fn run(&self) -> Result<ScheduleHandle> {
-        let timeout_sec = self.timeout_sec.clone() as u32;
+    /// Follows chain head. WebSocket endpoints are followed with `newHeads` subscription,
+    /// HTTP ones and fixture files are polled every `timeout_sec`.
+    pub async fn run(&self) -> Result<JoinHandle<()>> {
         let contract_processor = self.contract_processor.clone();
-        let url = Arc::new(self.chain_url.clone());
+        let chain_id = self.chain_id;
+        let url = self.chain_url.clone();
+        let throttle = self.throttle.clone();
+        let shutdown = self.shutdown.clone();
+
+        let handler = if url.starts_with("ws") {
+            tokio::spawn(follow_heads(chain_id, url, contract_processor, throttle, shutdown))
+        } else {
+            let source = source::create_source(&url, throttle.clone()).await?;
+            let interval = Duration::from_secs(self.timeout_sec);
+            tokio::spawn(poll(chain_id, source, interval, contract_processor, throttle, shutdown))
+        };
+
+        Ok(handler)
+    }
+}
+
+async fn poll(chain_id: u64, source: Arc<dyn ChainSource>, interval: Duration, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+              shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.requested() => break,
+        }
+        info!("Starting fetch of chain {}...", chain_id);
+
+        if let Err(e) = find(chain_id, source.clone(), contract_processor.clone(), throttle.clone(), &shutdown).await {
+            warn!("Fetch failed. {:?}", e);
+        }
+    }
+
+    info!("Stopped following chain {}", chain_id);
+}
+
+async fn follow_heads(chain_id: u64, url: String, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>, shutdown: Shutdown) {
+    let mut attempt = 0;
+
+    while !shutdown.is_requested() {
+        let started = Instant::now();
+
+        match subscribe_heads(chain_id, &url, contract_processor.clone(), throttle.clone(), &shutdown).await {
+            Ok(()) if shutdown.is_requested() => break,
+            Ok(()) => warn!("newHeads subscription closed by [{}]", url),
+            Err(e) => warn!("newHeads subscription to [{}] failed. {:?}", url, e),
+        }
+
+        if Instant::now() - started > HEALTHY_SUBSCRIPTION {
+            attempt = 0;
+        }
+
+        let backoff = Duration::from_secs(1 << attempt);
+        attempt = (attempt + 1).min(MAX_RECONNECT_BACKOFF_POW);
+
+        info!("Reconnecting to [{}] in {:?}", url, backoff);
+
+        tokio::select! {
+            _ = tokio::time::delay_for(backoff) => (),
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    info!("Stopped following chain {}", chain_id);
+}
 
-        find(url.clone(), contract_processor.clone()).await?;
+async fn subscribe_heads(chain_id: u64, url: &str, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+                         shutdown: &Shutdown) -> Result<()> {
+    let ws = connection::create_ws(url).await?;
+    let source = source::rpc_source(web3::transports::Either::Left(ws.clone()), throttle.clone())?;
 
-        let handler = tokio::spawn(async move {
-            let mut scheduler = Scheduler::new();
+    let mut heads = Web3::new(ws).eth_subscribe().subscribe_new_heads().await?;
+    info!("Subscribed to newHeads on [{}]", url);
 
-            scheduler.every(Interval::Seconds(timeout_sec)).run(move || {
-                info!("Starting fetch...");
+    // blocks mined while we were away
+    find(chain_id, source.clone(), contract_processor.clone(), throttle.clone(), shutdown).await?;
 
-                let _ = tokio::runtime::Runtime::new().unwrap().block_on(
-                    async {
-                        find(url.clone(), contract_processor.clone()).await
-                    }
-                );
-            });
-            scheduler.watch_thread(Duration::from_secs(5))
-        }).await;
+    loop {
+        let head = tokio::select! {
+            head = heads.next() => head,
+            _ = shutdown.requested() => return Ok(()),
+        };
 
-        Ok(handler?)
+        let head = match head {
+            Some(head) => head?,
+            None => break,
+        };
+
+        debug!("New head: {:?}", head.number);
+
+        find(chain_id, source.clone(), contract_processor.clone(), throttle.clone(), shutdown).await?;
     }
+
+    Ok(())
 }
 
-async fn find(url: Arc<String>, contract_processor: Arc<ContractProcessor>) -> Result<()> {
-    let mongodb = contract_processor.get_mongo();
+async fn find(chain_id: u64, source: Arc<dyn ChainSource>, contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>,
+              shutdown: &Shutdown) -> Result<()> {
+    let _guard = match TraverseGuard::acquire(chain_id) {
+        Some(guard) => guard,
+        None => {
+            info!("Travers of chain {} in progress", chain_id);
+            return Ok(());
+        }
+    };
+
+    let storage = contract_processor.get_storage();
+
+    // contracts still being backfilled join once the initial job is done
+    let mut contracts: Vec<Contract> = vec![];
 
-    let web3 = Arc::new(crate::traversal::connection::create_web3(&url).await);
+    for mut contract in storage.get_contracts(chain_id).await? {
+        if !contract.paused && (!contract.backfilling || release_backfilling(storage.as_ref(), &mut contract).await?) {
+            contracts.push(contract);
+        }
+    }
 
-    let mut contracts: Vec<Contract> = mongodb.get_contracts().await?;
+    let mut watched = storage.get_watchlist(chain_id).await?;
 
-    if contracts.is_empty() {
+    if contracts.is_empty() && watched.is_empty() {
         info!("Nothing to proceed. Returning...");
         return Ok(());
     }
 
-    debug!("Found contracts: {:?}", contracts);
+    debug!("Found contracts: {:?}. Watched addresses: {:?}", contracts, watched);
 
-    let max_default = 100_000_000_000 as u64;
-    let max_low = contracts.iter()
-        .map(|c| c.processed_range.as_ref())
-        .filter(Option::is_some)
-        .map(|processed_range| processed_range.as_ref().unwrap().start)
-        .max().unwrap_or(-1);
+    let head = source.block_number().await?;
+    let mut needs = vec![];
 
-    let min_high = contracts.iter()
-        .map(|c| c.processed_range.as_ref())
-        .filter(Option::is_some)
-        .map(|processed_range| processed_range.as_ref().unwrap().end)
-        .min().unwrap_or(-1);
+    for contract in contracts.iter_mut() {
+        let coverage = storage.get_coverage(chain_id, &contract.address).await?;
+        let start = contract.create_block.unwrap_or(0).max(0) as u64;
+        let tail = tail(&coverage.covered, start, head);
 
-    let mut range = match (max_low, min_high) {
-        (low, _) if low != 0 => 0 as u64..low as u64,
-        (0, high) => high as u64..max_default,
-        (-1, -1) => 0..max_default,
-        (x, y) => panic!("Not expected range {}..{}", x, y),
-    };// as u64..max_low as u64;
+        // unknown creation block means the whole history, too long to scan while holding traversal guard
+        if contract.create_block.is_none() && tail.start == 0 && !tail.is_empty() {
+            queue_history(storage.as_ref(), contract).await?;
+            continue;
+        }
 
-    let to_addresses: Vec<_> = contracts.iter()
-        .map(|c| c.address.clone())
-        .collect();
+        needs.push((contract.address.clone(), vec![tail]));
+    }
 
-    let total_time = Instant::now();
+    contracts.retain(|c| !c.backfilling);
 
-    info!("Starting range: {:?}", range);
+    // watched addresses are planned by id, so they don't mix up with contracts of the same address
+    for w in watched.iter_mut() {
+        if w.start_block.is_none() {
+            w.start_block = Some(head as i64);
+            storage.save_watched(w).await?;
+        }
 
-    let stream = crate::traversal::batch::traversal(web3, to_addresses.clone(), &mut range, 10).await;
+        let coverage = storage.get_watch_coverage(chain_id, &w.address).await?;
+        let start = w.start_block.unwrap_or(0).max(0) as u64;
 
-    if stream.is_some() {
-        let stream = stream.unwrap();
+        needs.push((w.id.clone(), vec![tail(&coverage.covered, start, head)]));
+    }
 
-        pin_mut!(stream);
+    let jobs = planner::plan(&needs);
 
-        while let Some(chain_data) = stream.next().await {
-            let mut chain_data = ChainDataDO::from(&chain_data);
+    if jobs.is_empty() {
+        info!("Chain {} is up to date at {}", chain_id, head);
+        return Ok(());
+    }
 
-            {
-                let trx: Vec<_> = chain_data.transactions.into_iter()
-                    .filter(|t| t.to.is_some())
-                    .filter(|t| to_addresses.contains(&crate::parse::h160_to_address(t.to.as_ref())))
-                    .collect();
+    let total_time = Instant::now();
 
-                chain_data.transactions = trx;
-            }
+    for job in jobs {
+        if shutdown.is_requested() {
+            break;
+        }
+
+        info!("Starting range: {:?} for {:?}", job.range, job.addresses);
+
+        let mut job_contracts: Vec<Contract> = contracts.iter()
+            .filter(|c| job.addresses.contains(&c.address))
+            .cloned()
+            .collect();
+
+        let job_watched: Vec<WatchedAddress> = watched.iter()
+            .filter(|w| job.addresses.contains(&w.id))
+            .cloned()
+            .collect();
 
-            mongodb.save_chain_data(&chain_data).await.expect("Wasn't able to save data to Mongo");
+        let strategy = resolve_strategy(throttle.config.strategy, &job_contracts, &job_watched);
+        let blooms = stored_blooms(storage.as_ref(), chain_id, strategy, &job.range).await?;
+        let mut range = job.range.clone();
 
-            let mut address_trx: HashMap<String, Vec<Transaction>> = HashMap::new();
+        let stream = crate::traversal::batch::traversal(source.clone(), filter(&job_contracts, &job_watched), &mut range,
+                                                        throttle.clone(), strategy, &RangeSet::new(), blooms).await?;
 
-            if contracts.is_empty() {
-                // info!("Empty contract");
+        let stream = match stream {
+            Some(s) => s,
+            None => {
+                info!("Range {:?} is ahead of chain", range);
                 continue;
             }
+        };
+
+        pin_mut!(stream);
+
+        while let Some(chain_data) = stream.next().await {
+            save_chain_data(&contract_processor, chain_id, &job_contracts, &job_watched, &chain_data).await?;
+            record_progress(storage.as_ref(), &mut job_contracts, &chain_data.covered).await?;
+            record_watch_progress(storage.as_ref(), &job_watched, &chain_data.covered).await?;
+
+            // the rest of range is picked up after restart
+            if shutdown.is_requested() {
+                info!("Stopping traversal of chain {} at {:?}", chain_id, chain_data.range);
+                return Ok(());
+            }
+        }
 
-            for trx in chain_data.transactions {
-                if trx.to.is_none() {
-                    // info!("to=None");
-                    continue;
-                }
-
-                let to = format!("{:#x}", trx.to.unwrap()).to_lowercase();
-                let vec = address_trx.get_mut(&to);
-                if vec.is_some() {
-                    vec.unwrap().push(trx);
-                } else {
-                    let mut vec = vec![];
-                    vec.push(trx);
-                    address_trx.insert(to, vec);
-                }
+        // keep processed ranges of the rest of jobs up to date
+        for contract in contracts.iter_mut() {
+            if let Some(updated) = job_contracts.iter().find(|c| c.id == contract.id) {
+                contract.processed_range = updated.processed_range.clone();
             }
+        }
+    }
 
-            // info!("Going to contracts");
+    info!("Total spent time: {:?}", Instant::now() - total_time);
 
-            for contract in contracts.iter_mut() {
-                if let Some(trx_to_save) = address_trx.remove(&contract.address) {
-                    info!("Found {} trx for {}", trx_to_save.len(), contract.address);
-                    contract_processor.process_contract(contract, trx_to_save.iter()).await.expect("Success");
+    Ok(())
+}
 
-                    update_contract(mongodb.clone(), range.clone(), contract).await;
-                }
-            }
+/// Blocks from the end of scanned ones up to chain head, the part of history live scraper follows.
+/// Holes behind it are found by audit and filled by gap fill jobs.
+pub(crate) fn tail(covered: &RangeSet, start: u64, head: u64) -> Range<u64> {
+    let from = covered.ranges().last().map_or(start, |r| r.end.max(start));
+
+    from.min(head)..head
+}
+
+/// Initial job failed or cancelled never clears `backfilling`, so the live scraper takes such contract over.
+async fn release_backfilling(storage: &dyn Storage, contract: &mut Contract) -> Result<bool> {
+    let job = storage.get_job(&Job::initial(contract.chain_id as u64, &contract.address, 0).id).await?;
+
+    match job {
+        Some(job) if job.status == JobStatus::Failed || job.status == JobStatus::Cancelled => {
+            warn!("Initial job {} is {:?}. Following {} live", job.id, job.status, contract.id);
+            contract.backfilling = false;
+            storage.update_contract(contract).await?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Hands history of contract over to initial backfill job. Live scraper picks the contract up again once it is done.
+async fn queue_history(storage: &dyn Storage, contract: &mut Contract) -> Result<()> {
+    let job = Job::initial(contract.chain_id as u64, &contract.address, 0);
+
+    if storage.enqueue_job(&job).await? {
+        info!("History of {} queued as job {}", contract.id, job.id);
+        crate::jobs::wake(contract.chain_id as u64);
+    }
+
+    contract.backfilling = true;
+    storage.update_contract(contract).await
+}
+
+/// Matches transactions sent to contracts and transactions of watched addresses by their roles.
+pub(crate) fn filter(contracts: &[Contract], watched: &[WatchedAddress]) -> TrxFilter {
+    let filter = contracts.iter()
+        .fold(TrxFilter::new(), |f, c| f.watch(&c.address, Role::To));
+
+    watched.iter()
+        .fold(filter, |f, w| f.watch(&w.address, w.role))
+}
+
+/// Saves blocks and transactions related to contracts and watched addresses. Transactions sent to
+/// a known contract are indexed decoded, the rest of watched ones are indexed as they are.
+pub(crate) async fn save_chain_data(contract_processor: &ContractProcessor, chain_id: u64, contracts: &[Contract],
+                                    watched: &[WatchedAddress], chain_data: &ChainData) -> Result<()> {
+    let storage = contract_processor.get_storage();
+    let filter = filter(contracts, watched);
+
+    let mut chain_data = ChainDataDO::new(chain_id, chain_data);
+
+    {
+        let trx: Vec<_> = chain_data.transactions.into_iter()
+            .filter(|t| filter.matches(&t.from, t.to.as_ref()))
+            .collect();
+
+        chain_data.transactions = trx;
+    }
+
+    storage.save_chain_data(&chain_data).await?;
+
+    let mut address_trx: HashMap<String, Vec<Transaction>> = HashMap::new();
+    let mut undecoded = vec![];
+
+    for trx in chain_data.transactions {
+        match trx.to {
+            Some(to) => address_trx.entry(format!("{:#x}", to)).or_default().push(trx),
+            None => undecoded.push(trx),
+        }
+    }
+
+    for contract in contracts.iter() {
+        if let Some(trx_to_save) = address_trx.remove(&contract.address) {
+            info!("Found {} trx for {}", trx_to_save.len(), contract.address);
+            contract_processor.process_contract(contract, trx_to_save.iter()).await?;
+        }
+    }
+
+    // sent by watched addresses to contracts followed by other jobs or not followed at all
+    for (to, trx) in address_trx {
+        match storage.get_contract(chain_id, &to).await? {
+            Some(contract) => contract_processor.process_contract(&contract, trx.iter()).await?,
+            None => undecoded.extend(trx),
         }
-    } else {
-        info!("Travers in progress");
+    }
+
+    if !undecoded.is_empty() {
+        info!("Found {} trx of watched addresses", undecoded.len());
+        contract_processor.process_undecoded(chain_id as i64, &undecoded).await?;
+    }
+
+    Ok(())
+}
+
+/// Records block ranges actually scanned for each of contracts.
+pub(crate) async fn update_coverage(storage: &dyn Storage, contracts: &[Contract], covered: &[Range<u64>]) -> Result<()> {
+    if covered.is_empty() {
         return Ok(());
     }
 
-    // for contract in contracts.iter_mut() {
-    //     update_contract(mongodb, range, contract).await;
-    // }
+    for contract in contracts {
+        let mut coverage = storage.get_coverage(contract.chain_id as u64, &contract.address).await?;
 
-    info!("Total spent time: {:?}", Instant::now() - total_time);
+        for r in covered {
+            coverage.covered.insert(r.clone());
+        }
+        coverage.updated_at = chrono::Utc::now().timestamp_millis();
+
+        storage.save_coverage(&coverage).await?;
+    }
+
+    Ok(())
+}
+
+/// Records scanned ranges and advances processed range of each contract independently,
+/// whether matching transactions were found or not.
+pub(crate) async fn record_progress(storage: &dyn Storage, contracts: &mut [Contract], covered: &[Range<u64>]) -> Result<()> {
+    update_coverage(storage, contracts, covered).await?;
+
+    for contract in contracts.iter_mut() {
+        let coverage = storage.get_coverage(contract.chain_id as u64, &contract.address).await?;
+        update_contract(storage, &coverage.covered, contract).await?;
+    }
+
+    Ok(())
+}
+
+/// Records block ranges scanned for each of watched addresses.
+pub(crate) async fn record_watch_progress(storage: &dyn Storage, watched: &[WatchedAddress], covered: &[Range<u64>]) -> Result<()> {
+    if covered.is_empty() {
+        return Ok(());
+    }
 
-    *crate::traversal::batch::TRAVERSE_IN_PROGRESS.lock().unwrap() = false;
+    for w in watched {
+        let mut coverage = storage.get_watch_coverage(w.chain_id as u64, &w.address).await?;
+
+        for r in covered {
+            coverage.covered.insert(r.clone());
+        }
+        coverage.updated_at = chrono::Utc::now().timestamp_millis();
+
+        storage.save_coverage(&coverage).await?;
+    }
 
     Ok(())
 }
 
-async fn update_contract(mongodb: Arc<MongoDB>, range: Range<u64>, contract: &mut Contract) {
-    let start = if contract.processed_range.is_some() {
-        contract.processed_range.as_ref().unwrap().start
-    } else {
-        range.start as i64
+/// Log based strategies miss contracts without events, so they fall back to full scan for them.
+/// Watched addresses are matched by sender or might be wallets emitting no logs, so they are always scanned in full.
+pub(crate) fn resolve_strategy(strategy: TraversalStrategy, contracts: &[Contract], watched: &[WatchedAddress]) -> TraversalStrategy {
+    if !watched.is_empty() || !contracts.iter().all(|c| c.abi_json.has_events()) {
+        return TraversalStrategy::Full;
+    }
+
+    strategy
+}
+
+/// Logs bloom of blocks stored in range. Only `Bloom` strategy makes use of them.
+pub(crate) async fn stored_blooms(storage: &dyn Storage, chain_id: u64, strategy: TraversalStrategy, range: &Range<u64>) -> Result<BTreeMap<u64, H2048>> {
+    match strategy {
+        TraversalStrategy::Bloom => storage.get_blooms(chain_id, range).await,
+        _ => Ok(BTreeMap::new()),
+    }
+}
+
+/// Advances contract processed range over contiguous covered ranges only.
+/// Contract seen for the first time starts from the most recent covered range.
+pub(crate) async fn update_contract(storage: &dyn Storage, covered: &RangeSet, contract: &mut Contract) -> Result<()> {
+    let processed = match contract.processed_range.as_ref() {
+        Some(r) => Some(covered.extend_contiguous(r.start as u64..r.end as u64)),
+        None => covered.ranges().pop(),
     };
 
-    contract.processed_range = Some(start..range.end as i64);
+    let processed = processed
+        .map(|r| r.start as i64..r.end as i64)
+        .filter(|r| !r.is_empty());
+
+    if processed.is_none() || processed == contract.processed_range {
+        return Ok(());
+    }
+
+    contract.processed_range = processed;
 
     debug!("Updating contract {} with range: {:?}", contract.id, contract.processed_range);
 
-    mongodb.update_contract(contract).await.expect("Success updating contract range");
-}
\ No newline at end of file
+    storage.update_contract(contract).await
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H160;
+
+    use crate::fixtures::{processor, replayed};
+    use crate::traversal::source::ChainBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replay_through_find() -> Result<()> {
+        let chain_id = 77;
+        let watched_address = H160::from_low_u64_be(0xaa);
+        let chain = ChainBuilder::new()
+            .empty_blocks(3)
+            .transaction(H160::from_low_u64_be(0xbb), Some(H160::from_low_u64_be(0xcc)), vec![])
+            .mine()
+            .empty_blocks(6)
+            .build();
+
+        let (source, throttle, storage) = replayed(&chain, "chain_scraper_find.ndjson.gz").await?;
+
+        let mut watched = WatchedAddress::new(chain_id, &format!("{:#x}", watched_address), Role::From);
+        watched.start_block = Some(0);
+        storage.save_watched(&watched).await?;
+
+        find(chain_id, source, processor(&storage), throttle, &Shutdown::default()).await?;
+
+        let coverage = storage.get_watch_coverage(chain_id, &watched.address).await?;
+        assert_eq!(coverage.covered.ranges(), vec![0..9]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn history_goes_to_initial_job() -> Result<()> {
+        let chain_id = 78;
+        let chain = ChainBuilder::new().empty_blocks(10).build();
+
+        let (source, throttle, storage) = replayed(&chain, "chain_scraper_history.ndjson.gz").await?;
+
+        let mut contract = Contract::new(chain_id, "0xc0", serde_json::from_str("[]")?);
+        contract.backfilling = false;
+        storage.save_contract(&contract).await?;
+
+        find(chain_id, source, processor(&storage), throttle, &Shutdown::default()).await?;
+
+        let job = storage.get_job(&Job::initial(chain_id, "0xc0", 0).id).await?.expect("initial job");
+        assert_eq!(job.range, 0..0);
+        assert!(storage.get_contract(chain_id, "0xc0").await?.unwrap().backfilling);
+        assert!(storage.get_coverage(chain_id, "0xc0").await?.covered.ranges().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_initial_job_releases_contract() -> Result<()> {
+        let chain_id = 79;
+        let chain = ChainBuilder::new().empty_blocks(10).build();
+
+        let (source, throttle, storage) = replayed(&chain, "chain_scraper_released.ndjson.gz").await?;
+
+        let mut contract = Contract::new(chain_id, "0xc0", serde_json::from_str("[]")?);
+        contract.create_block = Some(5);
+        storage.save_contract(&contract).await?;
+
+        let job = Job::initial(chain_id, "0xc0", 5);
+        storage.enqueue_job(&job).await?;
+
+        // still queued
+        find(chain_id, source.clone(), processor(&storage), throttle.clone(), &Shutdown::default()).await?;
+        assert!(storage.get_contract(chain_id, "0xc0").await?.unwrap().backfilling);
+
+        storage.set_job_status(&job.id, &[JobStatus::Queued], JobStatus::Failed).await?;
+        find(chain_id, source, processor(&storage), throttle, &Shutdown::default()).await?;
+
+        assert!(!storage.get_contract(chain_id, "0xc0").await?.unwrap().backfilling);
+        assert_eq!(storage.get_coverage(chain_id, "0xc0").await?.covered.ranges(), vec![5..9]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paused_contract_catches_up_once_resumed() -> Result<()> {
+        let chain_id = 80;
+        let chain = ChainBuilder::new().empty_blocks(10).build();
+
+        let (source, throttle, storage) = replayed(&chain, "chain_scraper_paused.ndjson.gz").await?;
+
+        let mut contract = Contract::new(chain_id, "0xc0", serde_json::from_str("[]")?);
+        contract.create_block = Some(2);
+        contract.backfilling = false;
+        contract.paused = true;
+        storage.save_contract(&contract).await?;
+
+        find(chain_id, source.clone(), processor(&storage), throttle.clone(), &Shutdown::default()).await?;
+        assert!(storage.get_coverage(chain_id, "0xc0").await?.covered.ranges().is_empty());
+
+        contract.paused = false;
+        storage.update_contract_info(&contract).await?;
+        find(chain_id, source, processor(&storage), throttle, &Shutdown::default()).await?;
+
+        assert_eq!(storage.get_coverage(chain_id, "0xc0").await?.covered.ranges(), vec![2..9]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hole_is_left_to_audit() -> Result<()> {
+        let chain_id = 81;
+        let chain = ChainBuilder::new().empty_blocks(10).build();
+
+        let (source, throttle, storage) = replayed(&chain, "chain_scraper_audit.ndjson.gz").await?;
+
+        let mut contract = Contract::new(chain_id, "0xc0", serde_json::from_str("[]")?);
+        contract.create_block = Some(2);
+        contract.backfilling = false;
+        storage.save_contract(&contract).await?;
+
+        let mut coverage = storage.get_coverage(chain_id, "0xc0").await?;
+        coverage.covered.insert(4..6);
+        storage.save_coverage(&coverage).await?;
+
+        find(chain_id, source.clone(), processor(&storage), throttle.clone(), &Shutdown::default()).await?;
+        assert_eq!(storage.get_coverage(chain_id, "0xc0").await?.covered.ranges(), vec![4..9]);
+
+        let config = ChainConfig { id: chain_id, name: "test".into(), url: "file://none".into() };
+        let auditor = audit::Auditor::new(&config, Duration::from_secs(60), processor(&storage), throttle, Shutdown::default());
+
+        let report = auditor.audit(source.as_ref()).await?;
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].1.len(), 1);
+        assert_eq!(report[0].0, Contract::key(chain_id, "0xc0"));
+        assert_eq!(report[0].1.first(), Some(&(2..4)));
+        assert!(storage.get_job(&Job::gap_fill(chain_id, "0xc0", 2..4).id).await?.is_some());
+
+        Ok(())
+    }
+}
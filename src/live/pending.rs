@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::Utc;
+use futures_util::stream::StreamExt;
+use log::{debug, info, warn};
+use tokio::task::JoinHandle;
+use web3::Web3;
+
+use crate::config::ChainConfig;
+use crate::es::ContractProcessor;
+use crate::mongo::model::{Contract, PendingStatus, PendingTransaction};
+use crate::shutdown::Shutdown;
+use crate::traversal::connection;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::{self, ChainSource};
+
+const HEALTHY_SUBSCRIPTION: Duration = Duration::from_secs(60);
+const MAX_RECONNECT_BACKOFF_POW: u32 = 6;
+
+/// Watches mempool for transactions to tracked contracts and reconciles them
+/// as mined, replaced or dropped later on.
+#[derive(Debug)]
+pub struct PendingMonitor {
+    chain_id: u64,
+    chain_url: String,
+    reconcile_interval: Duration,
+    drop_after: Duration,
+    contract_processor: Arc<ContractProcessor>,
+    throttle: Arc<Throttle>,
+    contracts: Arc<RwLock<HashMap<String, Contract>>>,
+    shutdown: Shutdown,
+}
+
+impl PendingMonitor {
+    pub fn new(chain: &ChainConfig, reconcile_interval: Duration, drop_after: Duration,
+               contract_processor: Arc<ContractProcessor>, throttle: Arc<Throttle>, shutdown: Shutdown) -> Self {
+        Self {
+            chain_id: chain.id,
+            chain_url: chain.url.clone(),
+            reconcile_interval,
+            drop_after,
+            contract_processor,
+            throttle,
+            contracts: Arc::new(RwLock::new(HashMap::new())),
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) -> Result<JoinHandle<()>> {
+        if !self.chain_url.starts_with("ws") {
+            anyhow::bail!("Pending transactions monitoring requires WebSocket endpoint. Got [{}]", self.chain_url);
+        }
+
+        let monitor = Arc::new(self);
+        monitor.refresh_contracts().await?;
+
+        let reconcile = tokio::spawn(monitor.clone().reconcile_loop());
+        let subscribe = tokio::spawn(monitor.subscribe_loop());
+
+        Ok(tokio::spawn(async move {
+            let _ = futures::join!(reconcile, subscribe);
+        }))
+    }
+
+    async fn refresh_contracts(&self) -> Result<()> {
+        let contracts = self.contract_processor.get_storage().get_contracts(self.chain_id).await?;
+
+        *self.contracts.write().unwrap() = contracts.into_iter()
+            .filter(|c| !c.paused)
+            .map(|c| (c.address.clone(), c))
+            .collect();
+
+        Ok(())
+    }
+
+    async fn subscribe_loop(self: Arc<Self>) {
+        let mut attempt = 0;
+
+        while !self.shutdown.is_requested() {
+            let started = Instant::now();
+
+            match self.subscribe().await {
+                Ok(()) if self.shutdown.is_requested() => break,
+                Ok(()) => warn!("newPendingTransactions subscription closed by [{}]", self.chain_url),
+                Err(e) => warn!("newPendingTransactions subscription to [{}] failed. {:?}", self.chain_url, e),
+            }
+
+            if Instant::now() - started > HEALTHY_SUBSCRIPTION {
+                attempt = 0;
+            }
+
+            let backoff = Duration::from_secs(1 << attempt);
+            attempt = (attempt + 1).min(MAX_RECONNECT_BACKOFF_POW);
+
+            tokio::select! {
+                _ = tokio::time::delay_for(backoff) => (),
+                _ = self.shutdown.requested() => break,
+            }
+        }
+
+        info!("Stopped monitoring mempool of chain {}", self.chain_id);
+    }
+
+    async fn subscribe(&self) -> Result<()> {
+        let ws = connection::create_ws(&self.chain_url).await?;
+        let source = source::rpc_source(web3::transports::Either::Left(ws.clone()), self.throttle.clone())?;
+
+        let mut hashes = Web3::new(ws).eth_subscribe().subscribe_new_pending_transactions().await?;
+        info!("Subscribed to newPendingTransactions on [{}]", self.chain_url);
+
+        loop {
+            let hash = tokio::select! {
+                hash = hashes.next() => hash,
+                _ = self.shutdown.requested() => return Ok(()),
+            };
+
+            let hash = match hash {
+                Some(hash) => hash?,
+                None => break,
+            };
+            let first_seen = Utc::now().timestamp_millis();
+
+            let trx = match source.transaction(hash).await? {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let to = match trx.to {
+                Some(to) => format!("{:#x}", to),
+                None => continue,
+            };
+
+            let contract = self.contracts.read().unwrap().get(&to).cloned();
+
+            if let Some(contract) = contract {
+                if let Err(e) = self.contract_processor.process_pending(&contract, &trx, first_seen).await {
+                    warn!("Failed to save pending trx {:?}. {:?}", hash, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reconcile_loop(self: Arc<Self>) {
+        let source = match source::create_source(&self.chain_url, self.throttle.clone()).await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Pending transactions reconciliation disabled. {:?}", e);
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(self.reconcile_interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = self.shutdown.requested() => break,
+            }
+
+            if let Err(e) = self.refresh_contracts().await {
+                warn!("Failed to refresh contracts. {:?}", e);
+            }
+
+            if let Err(e) = self.reconcile(source.as_ref()).await {
+                warn!("Pending transactions reconciliation failed. {:?}", e);
+            }
+        }
+    }
+
+    async fn reconcile(&self, source: &dyn ChainSource) -> Result<()> {
+        let pending = self.contract_processor.get_storage().get_pending(self.chain_id).await?;
+
+        debug!("Reconciling {} pending trx", pending.len());
+
+        for mut trx in pending {
+            if let Some(status) = check(source, &mut trx, self.drop_after).await? {
+                info!("Pending trx {:?} is {:?}", trx.hash, status);
+                trx.status = status;
+
+                self.contract_processor.update_pending(&trx).await?;
+
+                if status == PendingStatus::Mined {
+                    self.contract_processor.get_storage().replace_pending(&trx.id, &trx.from, &trx.nonce).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// New status of pending transaction if it changed.
+async fn check(source: &dyn ChainSource, trx: &mut PendingTransaction, drop_after: Duration) -> Result<Option<PendingStatus>> {
+    if let Some(receipt) = source.transaction_receipt(trx.hash).await? {
+        if let Some(number) = receipt.block_number {
+            let block = source.block(number.as_u64()).await?;
+
+            trx.block_number = Some(number);
+            trx.inclusion_latency_ms = block
+                .map(|b| (b.timestamp.as_u64() as i64 * 1000 - trx.first_seen).max(0));
+
+            return Ok(Some(PendingStatus::Mined));
+        }
+    }
+
+    let nonce = source.transaction_count(trx.from).await?;
+
+    if nonce > trx.nonce {
+        return Ok(Some(PendingStatus::Replaced));
+    }
+
+    let age = Duration::from_millis((Utc::now().timestamp_millis() - trx.first_seen).max(0) as u64);
+
+    if age > drop_after && source.transaction(trx.hash).await?.is_none() {
+        return Ok(Some(PendingStatus::Dropped));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{H160, H256};
+
+    use crate::traversal::source::{ChainBuilder, MemoryChainSource};
+
+    use super::*;
+
+    const DROP_AFTER: Duration = Duration::from_secs(600);
+
+    fn chain(sender: H160, contract: H160) -> MemoryChainSource {
+        ChainBuilder::new()
+            .empty_blocks(5)
+            .transaction(sender, Some(contract), vec![])
+            .mine()
+            .build()
+    }
+
+    /// Transaction seen in mempool `age_sec` ago
+    fn seen(trx: &web3::types::Transaction, age_sec: i64) -> PendingTransaction {
+        PendingTransaction::new(1, trx, None, Utc::now().timestamp_millis() - age_sec * 1000)
+    }
+
+    fn unmined(from: H160, nonce: u64) -> web3::types::Transaction {
+        web3::types::Transaction {
+            hash: H256::from_low_u64_be(0xdead),
+            nonce: nonce.into(),
+            from,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn mined() -> Result<()> {
+        let sender = H160::from_low_u64_be(1);
+        let source = chain(sender, H160::from_low_u64_be(0xc0));
+        let mined = source.block_with_txs(5).await?.unwrap().transactions.remove(0);
+
+        let mut trx = seen(&mined, 0);
+        trx.first_seen = source.block(5).await?.unwrap().timestamp.as_u64() as i64 * 1000 - 3000;
+
+        assert_eq!(check(&source, &mut trx, DROP_AFTER).await?, Some(PendingStatus::Mined));
+        assert_eq!(trx.block_number, Some(5.into()));
+        assert_eq!(trx.inclusion_latency_ms, Some(3000));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replaced_by_same_nonce() -> Result<()> {
+        let sender = H160::from_low_u64_be(1);
+        let source = chain(sender, H160::from_low_u64_be(0xc0));
+
+        let mut trx = seen(&unmined(sender, 0), 0);
+        assert_eq!(check(&source, &mut trx, DROP_AFTER).await?, Some(PendingStatus::Replaced));
+
+        // next nonce is still pending
+        let mut trx = seen(&unmined(sender, 1), 0);
+        assert_eq!(check(&source, &mut trx, DROP_AFTER).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dropped_once_old_and_gone() -> Result<()> {
+        let source = chain(H160::from_low_u64_be(1), H160::from_low_u64_be(0xc0));
+        let other = H160::from_low_u64_be(2);
+
+        let mut trx = seen(&unmined(other, 0), 60);
+        assert_eq!(check(&source, &mut trx, DROP_AFTER).await?, None);
+
+        let mut trx = seen(&unmined(other, 0), 3600);
+        assert_eq!(check(&source, &mut trx, DROP_AFTER).await?, Some(PendingStatus::Dropped));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::ops::Range;
+
+/// Blocks to be traversed once for a set of contracts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraversalJob {
+    pub range: Range<u64>,
+    pub addresses: Vec<String>,
+}
+
+/// Merges ranges each contract still needs into shared jobs. A block needed by several
+/// contracts is traversed once for all of them, a contract never waits for blocks it already has.
+/// Jobs are ordered newest first, so chain head isn't starved by a long history backfill.
+pub fn plan(needs: &[(String, Vec<Range<u64>>)]) -> Vec<TraversalJob> {
+    let mut bounds: Vec<u64> = needs.iter()
+        .flat_map(|(_, ranges)| ranges.iter())
+        .filter(|r| !r.is_empty())
+        .flat_map(|r| vec![r.start, r.end])
+        .collect();
+    bounds.sort();
+    bounds.dedup();
+
+    let mut jobs: Vec<TraversalJob> = vec![];
+
+    // every need either covers a segment between neighbouring bounds entirely or doesn't touch it
+    for segment in bounds.windows(2).map(|w| w[0]..w[1]) {
+        let addresses: Vec<String> = needs.iter()
+            .filter(|(_, ranges)| ranges.iter().any(|r| r.start <= segment.start && segment.end <= r.end))
+            .map(|(address, _)| address.clone())
+            .collect();
+
+        if addresses.is_empty() {
+            continue;
+        }
+
+        match jobs.last_mut() {
+            Some(last) if last.range.end == segment.start && last.addresses == addresses => last.range.end = segment.end,
+            _ => jobs.push(TraversalJob {
+                range: segment,
+                addresses,
+            }),
+        }
+    }
+
+    jobs.reverse();
+    jobs
+}
+
+#[cfg(test)]
+// needs are lists of ranges, even single ones
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn job(range: Range<u64>, addresses: &[&str]) -> TraversalJob {
+        TraversalJob {
+            range,
+            addresses: addresses.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn plan_shared_and_own_ranges() {
+        let needs = vec![
+            // new contract needs whole history
+            ("0x01".to_string(), vec![0..1000]),
+            // old contract needs the tail only
+            ("0x02".to_string(), vec![900..1000]),
+            // the other one has a hole as well
+            ("0x03".to_string(), vec![100..200, 900..1000]),
+        ];
+
+        assert_eq!(plan(&needs), vec![
+            job(900..1000, &["0x01", "0x02", "0x03"]),
+            job(200..900, &["0x01"]),
+            job(100..200, &["0x01", "0x03"]),
+            job(0..100, &["0x01"]),
+        ]);
+    }
+
+    #[test]
+    fn plan_nothing_needed() {
+        assert!(plan(&[]).is_empty());
+        assert!(plan(&[("0x01".to_string(), vec![]), ("0x02".to_string(), vec![5..5])]).is_empty());
+    }
+}
@@ -3,15 +3,30 @@
 #[macro_use]
 extern crate lazy_static;
 
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
+use log::info;
 use structopt::StructOpt;
 
+use crate::config::Config;
 use crate::error::setup_panic_handler;
 use crate::es::{ContractProcessor, Elastic};
+use crate::ingest::IngestFormat;
+use crate::jobs::JobRunner;
 use crate::live::ScheduledScraper;
-
+use crate::live::audit::Auditor;
+use crate::live::pending::PendingMonitor;
+use crate::mongo::MongoConfig;
+use crate::notify::Notifier;
+use crate::shutdown::Shutdown;
+use crate::storage::StorageConfig;
+use crate::traversal::config::TraversalConfig;
+use crate::traversal::limiter::Throttle;
+
+mod config;
 mod traversal;
 mod parse;
 mod web;
@@ -19,6 +34,13 @@ mod es;
 mod mongo;
 mod error;
 mod live;
+mod ingest;
+mod jobs;
+mod notify;
+mod shutdown;
+mod storage;
+#[cfg(test)]
+mod fixtures;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
@@ -31,20 +53,84 @@ async fn main() -> Result<()> {
 
     log4rs::init_file(args.log_file, Default::default()).unwrap();
 
+    let shutdown = Shutdown::new(Duration::from_secs(args.shutdown_grace_sec));
+
     let elastic = Arc::new(Elastic::new(&args.es_url));
 
-    let mongodb = mongo::MongoDB::new(&args.mongo_url);
-    mongodb.init().await?;
+    let config = Arc::new(match args.config.as_ref() {
+        Some(path) => Config::load(path)?,
+        None => Config::single(args.chain_id, &args.chain_url),
+    });
+
+    let storage = storage::connect(&args.storage, &args.mongo).await?;
+
+    if let Some(Command::Migrate { to, dry_run }) = args.command {
+        for report in storage.migrate(to, dry_run, config.default_chain().id).await? {
+            println!("{}", report);
+        }
+        return Ok(());
+    }
+
+    // documents stored before chains were configured belong to the default one
+    storage.migrate(None, false, config.default_chain().id).await?;
+    elastic.init(config.default_chain().id as i64).await?;
+
+    let contract_processor = ContractProcessor::new(storage.clone(), elastic.clone());
+
+    if let Some(Command::Ingest { path, format, chain_id }) = args.command {
+        let format = format.unwrap_or_else(|| IngestFormat::detect(&path));
+        let chain_id = chain_id.unwrap_or_else(|| config.default_chain().id);
+        return ingest::ingest(&path, format, chain_id, Arc::new(contract_processor)).await;
+    }
+
+    // ingested history is older than webhooks anyway, so only scraping notifies
+    let notifier = Arc::new(Notifier::new(storage.clone(), args.webhook_attempts, shutdown.clone()));
+    let contract_processor = Arc::new(contract_processor.with_notifier(notifier.clone()));
+
+    let mut handlers = vec![notifier.run()];
+
+    for chain in config.chains.iter() {
+        info!("Following chain {} [{}] at [{}]", chain.id, chain.name, chain.url);
+
+        let throttle = Throttle::for_endpoint(&chain.url, &args.traversal)?;
+
+        let scheduled_scraper = ScheduledScraper::new(args.update_interval_sec, chain, contract_processor.clone(), throttle.clone(), shutdown.clone());
+
+        handlers.push(scheduled_scraper.run().await?);
+
+        let job_runner = JobRunner::new(chain, args.max_jobs, contract_processor.clone(), throttle.clone(), shutdown.clone());
+
+        handlers.push(job_runner.run().await?);
+
+        let auditor = Auditor::new(chain, Duration::from_secs(args.audit_interval_sec), contract_processor.clone(), throttle.clone(),
+                                   shutdown.clone());
+
+        handlers.push(auditor.run().await?);
 
-    let mongodb = Arc::new(mongodb);
+        if args.pending {
+            let pending_monitor = PendingMonitor::new(chain,
+                                                      Duration::from_secs(args.update_interval_sec),
+                                                      Duration::from_secs(args.pending_drop_after_sec),
+                                                      contract_processor.clone(), throttle, shutdown.clone());
 
-    let contract_processor = Arc::new(ContractProcessor::new(mongodb.clone(), elastic.clone()));
+            handlers.push(pending_monitor.run().await?);
+        }
+    }
 
-    let scheduled_scraper = ScheduledScraper::new(args.update_interval_sec, &args.chain_url, contract_processor.clone());
+    let served = web::server::run_server(contract_processor.clone(), config.clone(), args.listen_port, shutdown.clone()).await;
 
-    let _handler = scheduled_scraper.run().await?;
+    shutdown.request();
 
-    web::server::run_server(contract_processor.clone(), args.listen_port).await?;
+    if !shutdown.wait(handlers).await {
+        // jobs cut in the middle are continued after restart
+        for chain in config.chains.iter() {
+            storage.requeue_running_jobs(chain.id).await?;
+        }
+    }
+
+    info!("Shut down");
+
+    served?;
 
     Ok(())
 }
@@ -52,11 +138,21 @@ async fn main() -> Result<()> {
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Chain Scrapper")]
 struct Args {
+    /// Chains config file. `chain_url` and `chain_id` are used if omitted.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
     #[structopt(long = "chain_url", default_value = "ws://localhost:8546")]
     chain_url: String,
 
-    #[structopt(long = "mongo_url", default_value = "localhost")]
-    mongo_url: String,
+    #[structopt(long = "chain_id", default_value = "2018")]
+    chain_id: u64,
+
+    #[structopt(flatten)]
+    mongo: MongoConfig,
+
+    #[structopt(flatten)]
+    storage: StorageConfig,
 
     #[structopt(long = "elastic_url", default_value = "http://localhost:9200")]
     es_url: String,
@@ -69,4 +165,62 @@ struct Args {
 
     #[structopt(short = "i", long, default_value = "60")]
     update_interval_sec: u64,
+
+    /// How often scanned ranges are checked for holes.
+    #[structopt(long = "audit_interval_sec", default_value = "3600")]
+    audit_interval_sec: u64,
+
+    /// How long in-flight work is waited for on SIGTERM/SIGINT.
+    #[structopt(long = "shutdown_grace_sec", default_value = "30")]
+    shutdown_grace_sec: u64,
+
+    /// Jobs run at once per chain.
+    #[structopt(long = "max_jobs", default_value = "2")]
+    max_jobs: usize,
+
+    /// Monitor mempool for transactions to tracked contracts. Requires WebSocket chain url.
+    #[structopt(long)]
+    pending: bool,
+
+    /// Pending transaction not mined and gone from mempool after that long is considered dropped.
+    #[structopt(long = "pending_drop_after_sec", default_value = "3600")]
+    pending_drop_after_sec: u64,
+
+    /// Webhook notification failed that many times goes to dead letters.
+    #[structopt(long = "webhook_attempts", default_value = "5")]
+    webhook_attempts: u32,
+
+    #[structopt(flatten)]
+    traversal: TraversalConfig,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Loads blocks from export file instead of RPC node and exits.
+    Ingest {
+        /// `geth export` RLP file or JSONL dump of `eth_getBlockByNumber` results. Might be gzipped.
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// rlp | jsonl. Detected by file extension if omitted.
+        #[structopt(long)]
+        format: Option<IngestFormat>,
+
+        /// Chain the blocks belong to. The first configured chain if omitted.
+        #[structopt(long = "chain_id")]
+        chain_id: Option<u64>,
+    },
+    /// Migrates stored documents to schema version and exits. Migrations to the latest one run on every start.
+    Migrate {
+        /// Rolls back to that version if it is below the current one. The latest if omitted.
+        #[structopt(long)]
+        to: Option<i32>,
+
+        /// Only reports documents to be changed and ones models can't read.
+        #[structopt(long = "dry_run")]
+        dry_run: bool,
+    },
 }
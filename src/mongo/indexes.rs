@@ -0,0 +1,153 @@
+use mongodb::bson::{Bson, doc, Document};
+
+use crate::mongo::model;
+
+/// Index `init` keeps in place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSpec {
+    pub collection: &'static str,
+    pub name: &'static str,
+    pub keys: Document,
+}
+
+impl IndexSpec {
+    fn new(collection: &'static str, name: &'static str, keys: Document) -> Self {
+        IndexSpec {
+            collection,
+            name,
+            keys,
+        }
+    }
+
+    /// `createIndexes` entry. Background build doesn't lock collection on servers before 4.2.
+    pub fn to_document(&self, background: bool) -> Document {
+        doc! {
+            "key": self.keys.clone(),
+            "name": self.name,
+            "background": background,
+        }
+    }
+}
+
+/// Every query filters by chain, so indexes start with `chain_id`.
+pub fn declared() -> Vec<IndexSpec> {
+    vec![
+        // `count_trx_to` in block range, also serves queries by recipient only
+        IndexSpec::new(model::Transaction::COLLECTION_NAME, "chain_to_block", doc! { "chain_id": 1, "to": 1, "blockNumber": 1 }),
+        // `find_trx_to` pages of stored transactions of contract, sorted by hash
+        IndexSpec::new(model::Transaction::COLLECTION_NAME, "chain_to_hash", doc! { "chain_id": 1, "to": 1, "hash": 1 }),
+        IndexSpec::new(model::Transaction::COLLECTION_NAME, "chain_from", doc! { "chain_id": 1, "from": 1 }),
+        IndexSpec::new(model::Transaction::COLLECTION_NAME, "chain_block", doc! { "chain_id": 1, "blockNumber": 1 }),
+        IndexSpec::new(model::Transaction::COLLECTION_NAME, "chain_timestamp", doc! { "chain_id": 1, "timestamp": 1 }),
+        IndexSpec::new(model::Block::COLLECTION_NAME, "chain_number", doc! { "chain_id": 1, "number": 1 }),
+        IndexSpec::new(model::PendingTransaction::COLLECTION_NAME, "chain_status", doc! { "chain_id": 1, "status": 1 }),
+        IndexSpec::new(model::PendingTransaction::COLLECTION_NAME, "chain_from_nonce", doc! { "chain_id": 1, "from": 1, "nonce": 1 }),
+        IndexSpec::new(model::Contract::COLLECTION_NAME, "chain", doc! { "chain_id": 1 }),
+        IndexSpec::new(model::WatchedAddress::COLLECTION_NAME, "chain", doc! { "chain_id": 1 }),
+        IndexSpec::new(model::Job::COLLECTION_NAME, "chain_status_priority", doc! { "chain_id": 1, "status": 1, "priority": -1, "created_at": 1 }),
+        IndexSpec::new(model::Job::COLLECTION_NAME, "chain_address", doc! { "chain_id": 1, "address": 1 }),
+        IndexSpec::new(model::Webhook::COLLECTION_NAME, "chain_address", doc! { "chain_id": 1, "address": 1 }),
+        IndexSpec::new(model::DeadLetter::COLLECTION_NAME, "webhook_created", doc! { "webhook_id": 1, "created_at": -1 }),
+        IndexSpec::new(model::Notification::COLLECTION_NAME, "webhook", doc! { "webhook_id": 1 }),
+    ]
+}
+
+/// Difference between declared and existing indexes of a collection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexDiff {
+    Missing(IndexSpec),
+    /// Existing index of the same name has other keys
+    Changed { spec: IndexSpec, existing: Document },
+    /// Not declared. Left as it is, might be created by hand.
+    Unknown { collection: String, name: String, keys: Document },
+}
+
+/// Compares declared indexes of collection with `listIndexes` result. `_id` index is ignored.
+pub fn diff(collection: &str, declared: &[IndexSpec], existing: &[Document]) -> Vec<IndexDiff> {
+    let existing: Vec<(&str, Document)> = existing.iter()
+        .filter_map(|i| Some((i.get_str("name").ok()?, i.get_document("key").ok()?.clone())))
+        .filter(|(name, _)| *name != "_id_")
+        .collect();
+
+    let mut diffs = vec![];
+
+    for spec in declared.iter().filter(|s| s.collection == collection) {
+        match existing.iter().find(|(name, _)| *name == spec.name) {
+            None => diffs.push(IndexDiff::Missing(spec.clone())),
+            Some((_, keys)) if !same_keys(keys, &spec.keys) => diffs.push(IndexDiff::Changed {
+                spec: spec.clone(),
+                existing: keys.clone(),
+            }),
+            _ => (),
+        }
+    }
+
+    for (name, keys) in existing {
+        if !declared.iter().any(|s| s.collection == collection && s.name == name) {
+            diffs.push(IndexDiff::Unknown {
+                collection: collection.to_string(),
+                name: name.to_string(),
+                keys,
+            });
+        }
+    }
+
+    diffs
+}
+
+/// Key order matters. Directions compare by value as shell creates them as doubles.
+fn same_keys(a: &Document, b: &Document) -> bool {
+    let direction = |v: &Bson| match v {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    };
+
+    a.len() == b.len() && a.iter().zip(b.iter())
+        .all(|((ka, va), (kb, vb))| ka == kb && (va == vb || (direction(va).is_some() && direction(va) == direction(vb))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_with_existing() {
+        let declared = vec![
+            IndexSpec::new("trx", "chain_from", doc! { "chain_id": 1, "from": 1 }),
+            IndexSpec::new("trx", "chain_block", doc! { "chain_id": 1, "blockNumber": 1 }),
+            IndexSpec::new("trx", "chain_timestamp", doc! { "chain_id": 1, "timestamp": 1 }),
+            IndexSpec::new("jobs", "chain", doc! { "chain_id": 1 }),
+        ];
+
+        let existing = vec![
+            doc! { "v": 2, "key": { "_id": 1 }, "name": "_id_" },
+            doc! { "v": 2, "key": { "chain_id": 1.0, "from": 1.0 }, "name": "chain_from" },
+            doc! { "v": 2, "key": { "blockNumber": 1, "chain_id": 1 }, "name": "chain_block" },
+            doc! { "v": 2, "key": { "hash": 1 }, "name": "by_hash" },
+        ];
+
+        assert_eq!(diff("trx", &declared, &existing), vec![
+            IndexDiff::Changed {
+                spec: declared[1].clone(),
+                existing: doc! { "blockNumber": 1, "chain_id": 1 },
+            },
+            IndexDiff::Missing(declared[2].clone()),
+            IndexDiff::Unknown {
+                collection: "trx".to_string(),
+                name: "by_hash".to_string(),
+                keys: doc! { "hash": 1 },
+            },
+        ]);
+    }
+
+    #[test]
+    fn declared_names_are_unique_per_collection() {
+        let declared = declared();
+
+        for spec in declared.iter() {
+            assert_eq!(declared.iter().filter(|s| s.collection == spec.collection && s.name == spec.name).count(), 1, "{:?}", spec);
+        }
+    }
+}
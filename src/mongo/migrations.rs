@@ -0,0 +1,772 @@
+//! Schema versions of collections and migrations between them.
+//!
+//! Version of every collection models read is kept in `schema_versions`, also of ones no migration touched yet,
+//! so a migration added later knows where each of them starts. Migrations rewrite documents of one collection
+//! to their version in batches. Collections are migrated on start under a lock, so instances started together
+//! don't rewrite the same documents. Documents written meanwhile by instances of older versions aren't overwritten,
+//! they are migrated again as they are now.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use log::{debug, info, warn};
+use mongodb::bson::{self, Bson, doc, Document};
+use mongodb::options::{FindOptions, ReplaceOptions, UpdateOptions};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use web3::types::U256;
+
+use crate::mongo::{BulkResult, is_duplicate_key, MongoDB};
+use crate::mongo::model::{self, num};
+
+pub const SCHEMA_COLLECTION: &str = "schema_versions";
+const LOCK_ID: &str = "_lock";
+/// Lock of crashed instance is taken over after that. Every batch extends it.
+const LOCK_TTL_MS: i64 = 10 * 60 * 1000;
+const LOCK_POLL: Duration = Duration::from_secs(5);
+/// Documents read and rewritten at once
+const BATCH: i64 = 1000;
+/// Failures dry run logs per collection. The rest are only counted.
+const LOGGED_FAILURES: u64 = 10;
+/// Times documents changed meanwhile are migrated again before migration gives up
+const CONFLICT_RETRIES: u32 = 5;
+
+const BLOCK_INTS: &[&str] = &["number", "timestamp", "size"];
+const BLOCK_NUMBERS: &[&str] = &["gasUsed", "gasLimit", "difficulty", "totalDifficulty"];
+const TRX_INTS: &[&str] = &["timestamp", "nonce", "blockNumber", "transactionIndex"];
+const TRX_NUMBERS: &[&str] = &["value", "gasPrice", "gas"];
+const PENDING_INTS: &[&str] = &["nonce", "blockNumber"];
+
+/// Takes document and the chain documents of single chain versions belong to.
+type Transform = fn(Document, i64) -> Result<Document>;
+
+/// Rewrites documents of collection to `version`.
+pub struct Migration {
+    pub collection: &'static str,
+    pub version: i32,
+    pub description: &'static str,
+    /// Interrupted migration starts over, so documents already migrated must be left as they are.
+    pub up: Transform,
+    /// Reverts `up`. Migration without it can't be rolled back.
+    pub down: Option<Transform>,
+}
+
+/// Migrations in order they are applied. Shipped migrations are never changed, new ones get the next version.
+pub fn registry() -> Vec<Migration> {
+    vec![
+        Migration {
+            collection: model::Block::COLLECTION_NAME,
+            version: 1,
+            description: "hex numbers to integers and sortable decimals",
+            up: |d, _| store_numbers(d, BLOCK_INTS, BLOCK_NUMBERS),
+            down: Some(|d, _| hex_numbers(d, BLOCK_INTS, BLOCK_NUMBERS)),
+        },
+        Migration {
+            collection: model::Transaction::COLLECTION_NAME,
+            version: 1,
+            description: "hex numbers to integers and sortable decimals",
+            up: |d, _| store_numbers(d, TRX_INTS, TRX_NUMBERS),
+            down: Some(|d, _| hex_numbers(d, TRX_INTS, TRX_NUMBERS)),
+        },
+        Migration {
+            collection: model::PendingTransaction::COLLECTION_NAME,
+            version: 1,
+            description: "hex numbers to integers and sortable decimals",
+            up: |d, _| store_numbers(d, PENDING_INTS, TRX_NUMBERS),
+            down: Some(|d, _| hex_numbers(d, PENDING_INTS, TRX_NUMBERS)),
+        },
+        Migration {
+            collection: model::Contract::COLLECTION_NAME,
+            version: 2,
+            description: "address keys to chain and address",
+            up: |d, chain_id| rekey(d, chain_id, |id| contract_key(id, chain_id)),
+            down: None,
+        },
+        Migration {
+            collection: model::Coverage::COLLECTION_NAME,
+            version: 2,
+            description: "address keys to chain and address",
+            up: coverage_key,
+            down: None,
+        },
+        Migration {
+            collection: model::Block::COLLECTION_NAME,
+            version: 2,
+            description: "block number keys to chain and number",
+            up: |d, chain_id| rekey(d, chain_id, |id| Ok(chain_key(id, chain_id))),
+            down: None,
+        },
+        Migration {
+            collection: model::Transaction::COLLECTION_NAME,
+            version: 2,
+            description: "hash keys to chain and hash",
+            up: |d, chain_id| rekey(d, chain_id, |id| Ok(chain_key(id, chain_id))),
+            down: None,
+        },
+        Migration {
+            collection: model::PendingTransaction::COLLECTION_NAME,
+            version: 2,
+            description: "hash keys to chain and hash",
+            up: |d, chain_id| rekey(d, chain_id, |id| Ok(chain_key(id, chain_id))),
+            down: None,
+        },
+    ]
+}
+
+/// Schema version models read
+fn latest(registry: &[Migration]) -> i32 {
+    registry.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Migrations taking collection from version `from` to `to`, with `up` or `down` transforms in order to apply.
+fn plan<'a>(registry: &'a [Migration], collection: &str, from: i32, to: i32) -> Result<Vec<(&'a Migration, Transform)>> {
+    if from > latest(registry) {
+        bail!("{} is at schema version {}, this build knows up to {}", collection, from, latest(registry));
+    }
+
+    let migrations = registry.iter().filter(|m| m.collection == collection);
+
+    if to >= from {
+        return Ok(migrations
+            .filter(|m| m.version > from && m.version <= to)
+            .map(|m| (m, m.up))
+            .collect());
+    }
+
+    migrations.rev()
+        .filter(|m| m.version > to && m.version <= from)
+        .map(|m| match m.down {
+            Some(down) => Ok((m, down)),
+            None => bail!("{} migration to version {} can't be rolled back", collection, m.version),
+        })
+        .collect()
+}
+
+/// Fails if model of collection can't read document.
+fn check(collection: &str, doc: &Document) -> Result<()> {
+    fn read<T: DeserializeOwned>(doc: &Document) -> Result<()> {
+        bson::from_document::<T>(doc.clone())?;
+        Ok(())
+    }
+
+    match collection {
+        model::Contract::COLLECTION_NAME => read::<model::Contract>(doc),
+        model::WatchedAddress::COLLECTION_NAME => read::<model::WatchedAddress>(doc),
+        model::Block::COLLECTION_NAME => read::<model::Block>(doc),
+        model::Transaction::COLLECTION_NAME => read::<model::Transaction>(doc),
+        model::PendingTransaction::COLLECTION_NAME => read::<model::PendingTransaction>(doc),
+        model::Coverage::COLLECTION_NAME => read::<model::Coverage>(doc),
+        model::Job::COLLECTION_NAME => read::<model::Job>(doc),
+        model::Webhook::COLLECTION_NAME => read::<model::Webhook>(doc),
+        model::DeadLetter::COLLECTION_NAME => read::<model::DeadLetter>(doc),
+        model::Notification::COLLECTION_NAME => read::<model::Notification>(doc),
+        _ => Ok(()),
+    }
+}
+
+/// Collections dry run reads even without migrations, to find documents models can't read.
+const MODEL_COLLECTIONS: &[&str] = &[
+    model::Contract::COLLECTION_NAME,
+    model::WatchedAddress::COLLECTION_NAME,
+    model::Block::COLLECTION_NAME,
+    model::Transaction::COLLECTION_NAME,
+    model::PendingTransaction::COLLECTION_NAME,
+    model::Coverage::COLLECTION_NAME,
+    model::Job::COLLECTION_NAME,
+    model::Webhook::COLLECTION_NAME,
+    model::DeadLetter::COLLECTION_NAME,
+    model::Notification::COLLECTION_NAME,
+];
+
+/// Gives document of single chain version `_id` made by `key` out of its old one and `chain_id`.
+/// Documents having `chain_id` are left as they are.
+fn rekey(doc: Document, chain_id: i64, key: impl Fn(Bson) -> Result<Bson>) -> Result<Document> {
+    if doc.contains_key("chain_id") {
+        return Ok(doc);
+    }
+
+    let mut doc = doc;
+    let id = doc.remove("_id").context("Document without _id")?;
+
+    let mut keyed = doc! {
+        "_id": key(id)?,
+        "chain_id": chain_id,
+    };
+    keyed.extend(doc);
+
+    Ok(keyed)
+}
+
+/// `model::ChainKey`
+fn chain_key(id: Bson, chain_id: i64) -> Bson {
+    Bson::Document(doc! {
+        "chain_id": chain_id,
+        "key": id,
+    })
+}
+
+/// `model::Contract::key`
+fn contract_key(id: Bson, chain_id: i64) -> Result<Bson> {
+    match id {
+        Bson::String(address) => Ok(Bson::String(model::Contract::key(chain_id as u64, &address))),
+        id => bail!("Address expected as _id: {}", id),
+    }
+}
+
+/// Coverage was keyed by contract address, which it gets as a field too.
+fn coverage_key(doc: Document, chain_id: i64) -> Result<Document> {
+    let address = match doc.get("_id") {
+        Some(Bson::String(address)) if !doc.contains_key("chain_id") => address.to_lowercase(),
+        _ => return Ok(doc),
+    };
+
+    let mut doc = rekey(doc, chain_id, |id| contract_key(id, chain_id))?;
+    doc.insert("address", address);
+
+    Ok(doc)
+}
+
+/// Hex strings web3 wrote to the way `model::num` stores them
+fn store_numbers(doc: Document, ints: &[&str], numbers: &[&str]) -> Result<Document> {
+    map_fields(doc, |field, value| {
+        let hex = match value {
+            Bson::String(hex) if ints.contains(&field) || numbers.contains(&field) => hex,
+            value => return Ok(value),
+        };
+
+        let n: U256 = bson::from_bson(Bson::String(hex.clone()))
+            .with_context(|| format!("{} isn't a number: {}", field, hex))?;
+
+        if ints.contains(&field) {
+            Ok(Bson::Int64(num::to_i64(&n)?))
+        } else {
+            Ok(bson::to_bson(&num::Number::from(&n))?)
+        }
+    })
+}
+
+/// Reverts `store_numbers`
+fn hex_numbers(doc: Document, ints: &[&str], numbers: &[&str]) -> Result<Document> {
+    let hex = |n: i64| -> Result<Bson> { Ok(bson::to_bson(&U256::from(u64::try_from(n)?))?) };
+
+    map_fields(doc, |field, value| {
+        if !ints.contains(&field) && !numbers.contains(&field) {
+            return Ok(value);
+        }
+
+        match value {
+            Bson::Int32(n) => hex(i64::from(n)),
+            Bson::Int64(n) => hex(n),
+            Bson::Document(number) => number.get("hex").cloned()
+                .with_context(|| format!("{} has no hex: {:?}", field, number)),
+            value => Ok(value),
+        }
+    })
+}
+
+/// Document with values replaced by `f`, fields keep their order.
+fn map_fields(doc: Document, f: impl Fn(&str, Bson) -> Result<Bson>) -> Result<Document> {
+    let mut mapped = Document::new();
+
+    for (field, value) in doc {
+        let value = f(&field, value)?;
+        mapped.insert(field, value);
+    }
+
+    Ok(mapped)
+}
+
+/// Collections models read and ones migrations touch, every one of them gets a version.
+fn collections(registry: &[Migration]) -> Vec<&str> {
+    let mut collections: Vec<&str> = registry.iter().map(|m| m.collection).collect();
+    collections.extend(MODEL_COLLECTIONS);
+    collections.sort_unstable();
+    collections.dedup();
+    collections
+}
+
+fn id(doc: &Document) -> Bson {
+    doc.get("_id").cloned().unwrap_or(Bson::Null)
+}
+
+/// Filter matching document only as long as nobody changed it.
+fn unchanged(doc: &Document) -> Document {
+    doc! {
+        "_id": id(doc),
+        "$expr": { "$eq": ["$$ROOT", { "$literal": doc.clone() }] },
+    }
+}
+
+/// Transforms migration applies to every document of a collection.
+struct Steps<'a> {
+    collection: &'a str,
+    transforms: &'a [Transform],
+    chain_id: i64,
+    check_model: bool,
+}
+
+impl Steps<'_> {
+    fn apply(&self, doc: &Document) -> Result<Document> {
+        let migrated = self.transforms.iter()
+            .try_fold(doc.clone(), |d, t| t(d, self.chain_id))?;
+
+        if self.check_model {
+            check(self.collection, &migrated)?;
+        }
+
+        Ok(migrated)
+    }
+}
+
+/// What migration of collection did or, for dry run, would do.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MigrationReport {
+    pub collection: String,
+    pub from: i32,
+    pub to: i32,
+    /// Documents read
+    pub docs: u64,
+    /// Documents rewritten
+    pub changed: u64,
+    /// Documents migration or model failed on. Only dry run gets past them.
+    pub failed: u64,
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}: {} documents, {} changed, {} failed",
+               self.collection, self.from, self.to, self.docs, self.changed, self.failed)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SchemaVersion {
+    #[serde(rename = "_id")]
+    collection: String,
+    version: i32,
+    /// Unix time in millis
+    updated_at: i64,
+}
+
+impl MongoDB {
+    /// Migrates collections to schema version `to`, the latest if None. Collections ahead of it are rolled back.
+    /// Documents of single chain versions are given to `chain_id`.
+    /// Dry run doesn't write and reports every collection, including documents models can't read.
+    pub async fn migrate_schema(&self, to: Option<i32>, dry_run: bool, chain_id: u64) -> Result<Vec<MigrationReport>> {
+        let registry = registry();
+        let to = to.unwrap_or_else(|| latest(&registry));
+
+        if to < 0 || to > latest(&registry) {
+            bail!("Unknown schema version {}, the latest is {}", to, latest(&registry));
+        }
+
+        if dry_run {
+            return self.run_migrations(&registry, to, true, chain_id as i64, "").await;
+        }
+
+        if !self.is_behind(&registry, to).await? {
+            debug!("Schema is at version {}", to);
+            return Ok(vec![]);
+        }
+
+        let owner = format!("{}:{}", std::env::var("HOSTNAME").unwrap_or_default(), std::process::id());
+
+        self.lock(&owner).await?;
+        let res = self.run_migrations(&registry, to, false, chain_id as i64, &owner).await;
+        self.unlock(&owner).await?;
+
+        res
+    }
+
+    /// True if any collection isn't at version `to` yet.
+    async fn is_behind(&self, registry: &[Migration], to: i32) -> Result<bool> {
+        let versions = self.schema_versions().await?;
+
+        Ok(collections(registry).iter().any(|c| versions.get(*c).copied().unwrap_or(0) != to))
+    }
+
+    async fn run_migrations(&self, registry: &[Migration], to: i32, dry_run: bool, chain_id: i64, owner: &str) -> Result<Vec<MigrationReport>> {
+        let versions = self.schema_versions().await?;
+        let mut reports = vec![];
+
+        for collection in collections(registry) {
+            let from = versions.get(collection).copied().unwrap_or(0);
+            let steps = plan(registry, collection, from, to)?;
+
+            if steps.is_empty() && !dry_run {
+                // nothing to rewrite, the version is kept for migrations to come
+                if from != to {
+                    self.set_schema_version(collection, to).await?;
+                }
+                continue;
+            }
+
+            for (m, _) in steps.iter() {
+                info!("{} {} {}: {}", if to >= from { "Migrating" } else { "Rolling back" }, collection, m.version, m.description);
+            }
+
+            let transforms: Vec<Transform> = steps.iter().map(|(_, t)| *t).collect();
+            // models read the latest version only
+            let check_model = to == latest(registry);
+
+            let migration = Steps { collection, transforms: &transforms, chain_id, check_model };
+            let report = self.migrate_collection(&migration, from, to, dry_run, owner).await?;
+            info!("{}", report);
+
+            if !dry_run {
+                self.set_schema_version(collection, to).await?;
+            }
+
+            reports.push(report);
+        }
+
+        Ok(reports)
+    }
+
+    /// Goes over documents in a single cursor sorted by `_id`. Pages by `_id` would only reach documents
+    /// of the same `_id` type as the first one, while re-keyed collections hold several.
+    async fn migrate_collection(&self, migration: &Steps<'_>, from: i32, to: i32, dry_run: bool, owner: &str) -> Result<MigrationReport> {
+        let collection = migration.collection;
+
+        let mut report = MigrationReport {
+            collection: collection.to_string(),
+            from,
+            to,
+            ..Default::default()
+        };
+
+        let mut cursor = self.database.collection(collection).find(None, FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .batch_size(BATCH as u32)
+            .build()).await?;
+
+        let mut changed = vec![];
+
+        loop {
+            let doc = match cursor.next().await {
+                Some(doc) => Some(doc?),
+                None => None,
+            };
+
+            if let Some(doc) = doc.as_ref() {
+                report.docs += 1;
+
+                match migration.apply(doc) {
+                    Ok(migrated) if migrated != *doc => changed.push((doc.clone(), migrated)),
+                    Ok(_) => (),
+                    Err(e) if dry_run => {
+                        report.failed += 1;
+                        if report.failed <= LOGGED_FAILURES {
+                            warn!("{} {:?} would fail. {:?}", collection, doc.get("_id"), e);
+                        }
+                    }
+                    Err(e) => return Err(e.context(format!("Migration of {} {:?} failed", collection, doc.get("_id")))),
+                }
+            }
+
+            if changed.len() as i64 >= BATCH || (doc.is_none() && !changed.is_empty()) {
+                report.changed += changed.len() as u64;
+
+                if !dry_run {
+                    self.rewrite(migration, std::mem::take(&mut changed)).await?;
+                    self.extend_lock(owner).await?;
+                }
+                changed.clear();
+
+                debug!("{} of {} documents of {} changed", report.changed, report.docs, collection);
+            }
+
+            if doc.is_none() {
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Writes migrated documents, each only if its original is unchanged. Originals changed meanwhile
+    /// are migrated again as they are now, ones deleted meanwhile stay deleted.
+    async fn rewrite(&self, migration: &Steps<'_>, mut docs: Vec<(Document, Document)>) -> Result<()> {
+        for _ in 0..CONFLICT_RETRIES {
+            if self.write_unchanged(migration.collection, &docs).await? == docs.len() as u64 {
+                return Ok(());
+            }
+
+            let ids: Vec<Bson> = docs.iter().map(|(original, _)| id(original)).collect();
+            let current: Vec<Document> = self.database.collection(migration.collection)
+                .find(doc! { "_id": { "$in": ids } }, None).await?
+                .collect::<Vec<_>>().await
+                .into_iter()
+                .collect::<Result<_, _>>()?;
+
+            let mut conflicts = vec![];
+
+            for (original, migrated) in docs {
+                let now = match current.iter().find(|d| id(d) == id(&original)) {
+                    Some(now) => now,
+                    None => continue,
+                };
+
+                if *now != migrated {
+                    let again = migration.apply(now)
+                        .with_context(|| format!("Migration of {} {:?} failed", migration.collection, now.get("_id")))?;
+
+                    if again != *now {
+                        conflicts.push((now.clone(), again));
+                    }
+                }
+            }
+
+            if conflicts.is_empty() {
+                return Ok(());
+            }
+
+            warn!("{} documents of {} changed while migrated, migrating them again", conflicts.len(), migration.collection);
+            docs = conflicts;
+        }
+
+        bail!("Documents of {} keep changing while migrated", migration.collection)
+    }
+
+    /// Replaces originals that are still as they were read. Re-keyed document is written under its new `_id`
+    /// before the original is deleted, so interrupted migration still finds the original. Returns number written.
+    async fn write_unchanged(&self, collection: &str, docs: &[(Document, Document)]) -> Result<u64> {
+        let (rekeyed, replaced): (Vec<_>, Vec<_>) = docs.iter()
+            .partition(|(original, migrated)| id(original) != id(migrated));
+
+        let mut written = 0;
+
+        if !replaced.is_empty() {
+            let updates: Vec<Document> = replaced.iter()
+                .map(|(original, migrated)| doc! { "q": unchanged(original), "u": migrated.clone() })
+                .collect();
+
+            let response = self.database.run_command(doc! {
+                "update": collection,
+                "updates": updates,
+                "ordered": false,
+            }, None).await?;
+
+            written += BulkResult::from_response(&response)?.updated;
+        }
+
+        let docs = self.database.collection(collection);
+
+        for (original, migrated) in rekeyed {
+            docs.replace_one(doc! { "_id": id(migrated) }, migrated.clone(), ReplaceOptions::builder().upsert(true).build()).await?;
+
+            if docs.delete_one(unchanged(original), None).await?.deleted_count > 0 {
+                written += 1;
+            } else if docs.count_documents(doc! { "_id": id(original) }, None).await? == 0 {
+                // deleted meanwhile, so is its copy
+                docs.delete_one(doc! { "_id": id(migrated) }, None).await?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    async fn schema_versions(&self) -> Result<HashMap<String, i32>> {
+        let mut cursor = self.database.collection(SCHEMA_COLLECTION)
+            .find(doc! { "_id": { "$ne": LOCK_ID } }, None).await?;
+
+        let mut versions = HashMap::new();
+        while let Some(doc) = cursor.next().await {
+            let v: SchemaVersion = bson::from_document(doc?)?;
+            versions.insert(v.collection, v.version);
+        }
+
+        Ok(versions)
+    }
+
+    async fn set_schema_version(&self, collection: &str, version: i32) -> Result<()> {
+        let v = SchemaVersion {
+            collection: collection.to_string(),
+            version,
+            updated_at: chrono::Utc::now().timestamp_millis(),
+        };
+
+        self.database.collection(SCHEMA_COLLECTION).replace_one(doc! {
+                        "_id": collection
+                    }, bson::to_document(&v)?, ReplaceOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    /// Waits until lock is free or expired and takes it. Upsert of held lock fails on duplicate `_id`.
+    async fn lock(&self, owner: &str) -> Result<()> {
+        let collection = self.database.collection(SCHEMA_COLLECTION);
+
+        loop {
+            let now = chrono::Utc::now().timestamp_millis();
+
+            let res = collection.update_one(doc! {
+                "_id": LOCK_ID,
+                "expires_at": { "$lt": now },
+            }, doc! {
+                "$set": { "owner": owner, "expires_at": now + LOCK_TTL_MS }
+            }, UpdateOptions::builder().upsert(true).build()).await;
+
+            match res {
+                Ok(_) => return Ok(()),
+                Err(e) if is_duplicate_key(&e) => {
+                    info!("Waiting for schema migration of other instance");
+                    tokio::time::delay_for(LOCK_POLL).await;
+                }
+                Err(e) => bail!(e),
+            }
+        }
+    }
+
+    async fn extend_lock(&self, owner: &str) -> Result<()> {
+        let res = self.database.collection(SCHEMA_COLLECTION).update_one(doc! {
+            "_id": LOCK_ID,
+            "owner": owner,
+        }, doc! {
+            "$set": { "expires_at": chrono::Utc::now().timestamp_millis() + LOCK_TTL_MS }
+        }, None).await?;
+
+        if res.matched_count == 0 {
+            bail!("Schema migration lock expired and was taken by other instance");
+        }
+        Ok(())
+    }
+
+    async fn unlock(&self, owner: &str) -> Result<()> {
+        self.database.collection(SCHEMA_COLLECTION).delete_one(doc! {
+            "_id": LOCK_ID,
+            "owner": owner,
+        }, None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Bytes, H160, H256, Index};
+
+    use super::*;
+
+    fn stored_trx() -> Document {
+        let trx = model::Transaction::new(1, &web3::types::Transaction {
+            hash: H256::from_low_u64_be(1),
+            nonce: U256::from(9),
+            block_hash: Some(H256::from_low_u64_be(2)),
+            block_number: Some(5.into()),
+            transaction_index: Some(Index::from(3)),
+            from: H160::from_low_u64_be(1),
+            to: None,
+            value: U256::exp10(20),
+            gas_price: U256::from(1_000_000_000),
+            gas: U256::from(21_000),
+            input: Bytes(vec![]),
+            raw: None,
+        }, U256::from(1_600_000_000));
+
+        bson::to_document(&trx).unwrap()
+    }
+
+    #[test]
+    fn numbers_both_ways() -> Result<()> {
+        let registry = registry();
+        let migration = registry.iter().find(|m| m.collection == model::Transaction::COLLECTION_NAME).unwrap();
+
+        let stored = stored_trx();
+        let hex = (migration.down.unwrap())(stored.clone(), 1)?;
+
+        assert_eq!(hex.get_str("blockNumber")?, "0x5");
+        assert_eq!(hex.get_str("nonce")?, "0x9");
+        assert_eq!(hex.get_str("value")?, "0x56bc75e2d63100000");
+        assert_eq!(hex.get_str("transactionIndex")?, "0x3");
+
+        assert_eq!((migration.up)(hex, 1)?, stored);
+        // already migrated documents stay as they are
+        assert_eq!((migration.up)(stored.clone(), 1)?, stored);
+
+        check(model::Transaction::COLLECTION_NAME, &stored)
+    }
+
+    /// Document as single chain versions stored it, keyed by `id`
+    fn single_chain(mut doc: Document, id: Bson) -> Document {
+        doc.remove("chain_id");
+        doc.insert("_id", id);
+        doc
+    }
+
+    fn up(collection: &str, version: i32, doc: Document) -> Result<Document> {
+        let registry = registry();
+        let migration = registry.iter().find(|m| m.collection == collection && m.version == version).unwrap();
+
+        (migration.up)(doc, 2018)
+    }
+
+    #[test]
+    fn rekey_single_chain() -> Result<()> {
+        let trx = stored_trx();
+        let trx = doc! { "_id": { "chain_id": 2018i64, "key": trx.get_str("hash")? }, "chain_id": 2018i64 }.into_iter()
+            .chain(trx.into_iter().filter(|(k, _)| k != "_id" && k != "chain_id"))
+            .collect::<Document>();
+        let hash = Bson::String(trx.get_str("hash")?.to_string());
+
+        assert_eq!(up(model::Transaction::COLLECTION_NAME, 2, single_chain(trx.clone(), hash))?, trx);
+        assert_eq!(up(model::Transaction::COLLECTION_NAME, 2, trx.clone())?, trx);
+        check(model::Transaction::COLLECTION_NAME, &trx)?;
+
+        let address = "0x00000000000000000000000000000000000000c0";
+        let contract = bson::to_document(&model::Contract::new(2018, address, serde_json::from_str("[]")?))?;
+        let legacy = single_chain(contract.clone(), Bson::String(address.to_uppercase().replace("0X", "0x")));
+        let migrated = up(model::Contract::COLLECTION_NAME, 2, legacy)?;
+
+        assert_eq!(migrated.get_str("_id")?, "2018:0x00000000000000000000000000000000000000c0");
+        assert_eq!(migrated.get_i64("chain_id")?, 2018);
+        check(model::Contract::COLLECTION_NAME, &migrated)?;
+
+        let mut coverage = bson::to_document(&model::Coverage::new(2018, address))?;
+        coverage.remove("address");
+        let migrated = up(model::Coverage::COLLECTION_NAME, 2, single_chain(coverage, Bson::String(address.to_string())))?;
+
+        assert_eq!(migrated.get_str("_id")?, "2018:0x00000000000000000000000000000000000000c0");
+        assert_eq!(migrated.get_str("address")?, address);
+        check(model::Coverage::COLLECTION_NAME, &migrated)?;
+
+        assert!(up(model::Contract::COLLECTION_NAME, 2, doc! { "_id": 1 }).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_up_and_back() {
+        let up: Transform = |d, _| Ok(d);
+        let migration = |collection, version, down| Migration { collection, version, description: "", up, down };
+
+        let registry = vec![
+            migration("a", 1, Some(up)),
+            migration("b", 2, Some(up)),
+            migration("a", 3, None),
+            migration("a", 4, Some(up)),
+        ];
+        let versions = |steps: Vec<(&Migration, Transform)>| steps.iter().map(|(m, _)| m.version).collect::<Vec<_>>();
+
+        assert_eq!(versions(plan(&registry, "a", 0, 4).unwrap()), vec![1, 3, 4]);
+        assert_eq!(versions(plan(&registry, "a", 1, 3).unwrap()), vec![3]);
+        assert_eq!(versions(plan(&registry, "a", 4, 3).unwrap()), vec![4]);
+        assert_eq!(versions(plan(&registry, "b", 4, 0).unwrap()), vec![2]);
+        assert!(plan(&registry, "c", 0, 4).unwrap().is_empty());
+
+        assert!(plan(&registry, "a", 4, 0).is_err(), "3 can't be rolled back");
+        assert!(plan(&registry, "a", 5, 4).is_err(), "ahead of this build");
+    }
+
+    #[test]
+    fn versions_grow() {
+        let registry = registry();
+
+        for (i, m) in registry.iter().enumerate() {
+            assert!(registry[..i].iter().filter(|p| p.collection == m.collection).all(|p| p.version < m.version), "{}", m.version);
+        }
+    }
+}
@@ -1,48 +1,56 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::ops::Range;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
 use futures::StreamExt;
-use log::{debug, error, info, warn};
-use mongodb::{bson, bson::doc, bson::Document, Client, Cursor, Database};
-use mongodb::options::{ClientOptions, Credential, FindOneOptions, FindOptions, StreamAddress};
-use mongodb::results::{InsertManyResult, InsertOneResult, UpdateResult};
+use log::{debug, info, warn};
+use mongodb::{bson, bson::doc, bson::Document, Client, Database};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReplaceOptions, ReturnDocument};
+use serde::de::DeserializeOwned;
 use serde::Serialize;
+use web3::types::{H160, H2048, H256, U256};
 
-use crate::mongo::model::{ChainDataDO, Transaction};
+pub use crate::mongo::config::MongoConfig;
+use crate::mongo::indexes::IndexDiff;
+use crate::mongo::migrations::MigrationReport;
+use crate::mongo::model::{ChainKey, JobKind, JobStatus, num, PendingStatus, Transaction};
+use crate::storage::Storage;
 
+pub(crate) mod config;
+pub(crate) mod indexes;
+pub(crate) mod migrations;
 pub(crate) mod model;
 
+const DUPLICATE_KEY: i32 = 11000;
+/// Collections that large get their indexes built in background.
+const BACKGROUND_INDEX_DOCS: i64 = 100_000;
+/// Documents per bulk upsert command, far below 16MB command limit for blocks.
+const UPSERT_CHUNK: usize = 1000;
+
 #[derive(Debug)]
 pub struct MongoDB {
     database: Database
 }
 
 impl MongoDB {
-    pub fn new(url: &str) -> Self {
-        debug!("Connecting to Mongo [{}]", url);
-
-        let database = take_db(url);
-
-        MongoDB {
-            database
-        }
-    }
-
-    pub async fn get_contracts(&self) -> Result<Vec<model::Contract>> {
-        let result: Vec<model::Contract> = self.find_all(model::Contract::COLLECTION_NAME, None, FindOptions::builder()
-            .batch_size(100)
-            .build()).await?;
+    pub async fn connect(config: &MongoConfig) -> Result<Self> {
+        debug!("Connecting to Mongo {:?}", config);
 
-        debug!("Found {} contracts", result.len());
+        let client = Client::with_options(config.client_options().await?)?;
 
-        Ok(result)
+        Ok(MongoDB {
+            database: client.database(&config.db)
+        })
     }
 
     async fn find_all<D, FO, T>(&self, collection_name: &str, filter: D, find_opts: FO) -> Result<Vec<T>>
         where
             D: Into<Option<Document>> + Debug,
             FO: Into<Option<FindOptions>>,
-            T: From<Document>
+            T: DeserializeOwned
     {
         let collection = self.database.collection(collection_name);
 
@@ -54,37 +62,17 @@ impl MongoDB {
         let mut result = vec![];
         while let Some(doc) = cursor.next().await {
             debug!("{:?}", doc);
-            result.push(doc?.into());
+            result.push(read(doc?)?);
         }
         Ok(result)
     }
 
-    pub async fn _find_trx_to(&self, address: &str, batch_size: u32) -> Result<Cursor> {
-        let collection = self.database.collection(Transaction::COLLECTION_NAME);
-
-        match collection.find(doc! {
-            "to": address
-        }, FindOptions::builder()
-            .batch_size(batch_size)
-            .build(),
-        ).await {
-            Ok(r) => Ok(r),
-            Err(e) => {
-                error!("Fetching failed. {:?}", e);
-                return Err(e.into());
-            }
-        }
-    }
-
     pub async fn _find_item(&self, collection_name: &str,
                            filter: impl Into<Option<Document>>,
                            options: impl Into<Option<FindOneOptions>>) -> Option<Document> {
         let collection = self.database.collection(collection_name);
 
-        match collection.find_one(filter, options).await {
-            Ok(r) => r,
-            _ => None
-        }
+        collection.find_one(filter, options).await.unwrap_or_default()
     }
 
     pub async fn _get_last_block(&self) -> Option<model::Block> {
@@ -94,12 +82,9 @@ impl MongoDB {
                     }).build();
 
 
-        let doc = self._find_item(model::Block::COLLECTION_NAME, None, find_options).await;
-        if doc.is_none() {
-            return None;
-        }
+        let doc = self._find_item(model::Block::COLLECTION_NAME, None, find_options).await?;
 
-        match bson::from_document(doc.unwrap()) {
+        match bson::from_document(doc) {
             Ok(block) => Some(block),
             Err(e) => {
                 warn!("failed to parse last block request result. {:?}", e);
@@ -115,12 +100,9 @@ impl MongoDB {
                     }).build();
 
 
-        let doc = self._find_item(model::Block::COLLECTION_NAME, None, find_options).await;
-        if doc.is_none() {
-            return None;
-        }
+        let doc = self._find_item(model::Block::COLLECTION_NAME, None, find_options).await?;
 
-        match bson::from_document(doc.unwrap()) {
+        match bson::from_document(doc) {
             Ok(block) => Some(block),
             Err(e) => {
                 warn!("failed to parse last block request result. {:?}", e);
@@ -129,88 +111,122 @@ impl MongoDB {
         }
     }
 
-    pub async fn save_chain_data(&self, chain_data: &ChainDataDO) -> Result<()> {
-        if chain_data.blocks.is_empty() {
-            info!("Nothing to save. Blocks size 0");
-            return Ok(());
-        }
+    /// Unordered bulk upsert keyed by `_id`, so saving the same range twice is harmless.
+    /// Duplicate keys of concurrent upserts are counted, other write errors fail the whole call.
+    pub async fn upsert_many<'a, T: Serialize + 'a>(&self, collection_name: &str, items: impl IntoIterator<Item=&'a T>) -> Result<BulkResult> {
+        let updates = items.into_iter()
+            .map(upsert)
+            .collect::<Result<Vec<_>>>()?;
 
-        debug!("Saving: {}", chain_data);
+        let mut res = BulkResult::default();
 
-        let result = tokio::join!(
-            self.save_blocks(&chain_data.blocks),
-            self.save_transactions(&chain_data.transactions),
-        );
+        for chunk in updates.chunks(UPSERT_CHUNK) {
+            let response = self.database.run_command(doc! {
+                "update": collection_name,
+                "updates": chunk.to_vec(),
+                "ordered": false,
+            }, None).await?;
 
-        match result {
-            (Ok(_), Ok(_)) => Ok(()),
-            (Ok(_), Err(e)) => Err(e),
-            (Err(e), Ok(_)) => Err(e),
-            (Err(e), Err(_)) => Err(e),
+            res.add(&BulkResult::from_response(&response)?);
         }
-    }
 
-    pub async fn save_contract(&self, contract: &model::Contract) -> Result<InsertOneResult> {
-        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
-
-        debug!("Saving contract {:?}", contract);
+        debug!("Saved to {}: {:?}", collection_name, res);
 
-        match contracts.insert_one(bson::to_document(contract)?, None).await {
-            mongodb::error::Result::Ok(r) => Ok(r),
-            mongodb::error::Result::Err(e) => bail!(e)
-        }
+        Ok(res)
     }
 
-    pub async fn update_contract(&self, contract: &model::Contract) -> Result<UpdateResult> {
-        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
+    /// Creates missing indexes and recreates changed ones. Undeclared indexes are only reported.
+    /// Indexes of large collections are built in background, so start isn't held up.
+    pub async fn sync_indexes(&self) -> Result<Vec<IndexDiff>> {
+        let declared = indexes::declared();
 
-        debug!("Updating contract {:?}", contract);
+        let mut collections: Vec<_> = declared.iter().map(|s| s.collection).collect();
+        collections.sort();
+        collections.dedup();
 
-        match contracts.update_one(doc! {
-                        "_id": &contract.id
-                    }, bson::to_document(contract)?, None).await {
-            mongodb::error::Result::Ok(r) => Ok(r),
-            mongodb::error::Result::Err(e) => bail!(e)
-        }
-    }
+        let mut diffs = vec![];
 
-    pub async fn save_transactions(&self, transactions: &Vec<model::Transaction>) -> Result<Vec<InsertManyResult>> {
-        debug!("Saving {} trx", transactions.len());
-        self.insert_many(model::Transaction::COLLECTION_NAME, transactions.iter()).await
-    }
+        for collection in collections {
+            let existing = self.list_indexes(collection).await?;
+            let found = indexes::diff(collection, &declared, &existing);
+            let mut build = vec![];
+
+            for d in found.iter() {
+                match d {
+                    IndexDiff::Missing(spec) => {
+                        info!("Index {}.{} is missing", collection, spec.name);
+                        build.push(spec.to_owned());
+                    }
+                    IndexDiff::Changed { spec, existing } => {
+                        warn!("Index {}.{} has keys {:?}, declared {:?}. Recreating", collection, spec.name, existing, spec.keys);
+
+                        self.database.run_command(doc! { "dropIndexes": collection, "index": spec.name }, None).await?;
+                        build.push(spec.to_owned());
+                    }
+                    IndexDiff::Unknown { name, keys, .. } => info!("Index {}.{} {:?} isn't declared. Left as it is", collection, name, keys),
+                }
+            }
 
-    pub async fn save_blocks(&self, blocks: &Vec<model::Block>) -> Result<Vec<InsertManyResult>> {
-        debug!("Saving {} blocks", blocks.len());
-        self.insert_many(model::Block::COLLECTION_NAME, blocks.iter()).await
-    }
+            diffs.extend(found);
+
+            if build.is_empty() {
+                continue;
+            }
 
-    pub async fn insert_many<T: Serialize + 'static>(&self, collection_name: &str, items: impl IntoIterator<Item=&T>) -> Result<Vec<InsertManyResult>> {
-        let items: Vec<_> = items.into_iter()
-            .map(|v| { bson::to_document(v) })
-            .map(std::result::Result::unwrap)
-            .collect();
+            let docs = self.database.collection(collection).estimated_document_count(None).await?;
+            let background = docs >= BACKGROUND_INDEX_DOCS;
 
-        let chunks = items.chunks(20000);
+            let command = doc! {
+                "createIndexes": collection,
+                "indexes": build.iter().map(|s| s.to_document(background)).collect::<Vec<_>>(),
+            };
 
-        let collection = self.database.collection(collection_name);
-        let mut res = vec![];
-        for chunk in chunks {
-            res.push(match collection.insert_many(chunk.to_vec(), None).await {
-                mongodb::error::Result::Ok(r) => r,
-                mongodb::error::Result::Err(e) => bail!(e),
-            })
+            if background {
+                info!("Building {} indexes of {} with {} docs in background", build.len(), collection, docs);
+
+                let database = self.database.clone();
+                tokio::spawn(async move {
+                    match database.run_command(command, None).await {
+                        Ok(_) => info!("Indexes of {} built", collection),
+                        Err(e) => warn!("Failed to build indexes of {}. {:?}", collection, e),
+                    }
+                });
+            } else {
+                self.database.run_command(command, None).await?;
+                debug!("Indexes of {} built", collection);
+            }
         }
 
-        Ok(res)
+        Ok(diffs)
+    }
+
+    async fn list_indexes(&self, collection: &str) -> Result<Vec<Document>> {
+        let response = self.database.run_command(doc! { "listIndexes": collection }, None).await?;
+
+        Ok(response.get_document("cursor")?
+            .get_array("firstBatch")?
+            .iter()
+            .filter_map(|i| i.as_document().cloned())
+            .collect())
     }
+}
 
-    pub async fn init(&self) -> Result<()> {
+#[async_trait]
+impl Storage for MongoDB {
+    async fn init(&self) -> Result<()> {
         let existed_collections = self.database.list_collection_names(None).await?;
 
         let collections = vec![
             model::Contract::COLLECTION_NAME,
             model::Block::COLLECTION_NAME,
             model::Transaction::COLLECTION_NAME,
+            model::PendingTransaction::COLLECTION_NAME,
+            model::Coverage::COLLECTION_NAME,
+            model::Job::COLLECTION_NAME,
+            model::WatchedAddress::COLLECTION_NAME,
+            model::Webhook::COLLECTION_NAME,
+            model::DeadLetter::COLLECTION_NAME,
+            model::Notification::COLLECTION_NAME,
         ];
 
         debug!("Checking collections");
@@ -223,56 +239,537 @@ impl MongoDB {
             };
         }
 
+        self.sync_indexes().await?;
+
         Ok(())
     }
-}
 
-pub fn create_connection(url: &str) -> Result<Client> {
-    let options = ClientOptions::builder()
-        .direct_connection(true)
-        .credential(Credential::builder()
-            .username("admin".to_string())
-            .password("secret".to_string())
-            .build()
-        )
-        .hosts(vec![
-            StreamAddress {
-                hostname: url.into(),
-                port: Some(27017),
+    async fn migrate(&self, to: Option<i32>, dry_run: bool, chain_id: u64) -> Result<Vec<MigrationReport>> {
+        self.migrate_schema(to, dry_run, chain_id).await
+    }
+
+    async fn get_contracts(&self, chain_id: u64) -> Result<Vec<model::Contract>> {
+        let result: Vec<model::Contract> = self.find_all(model::Contract::COLLECTION_NAME, doc! {
+            "chain_id": chain_id as i64
+        }, FindOptions::builder()
+            .batch_size(100)
+            .build()).await?;
+
+        debug!("Found {} contracts", result.len());
+
+        Ok(result)
+    }
+
+    async fn get_contract(&self, chain_id: u64, address: &str) -> Result<Option<model::Contract>> {
+        let doc = self._find_item(model::Contract::COLLECTION_NAME, doc! {
+            "_id": model::Contract::key(chain_id, address)
+        }, None).await;
+
+        doc.map(read).transpose()
+    }
+
+    async fn save_contract(&self, contract: &model::Contract) -> Result<()> {
+        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
+
+        debug!("Saving contract {:?}", contract);
+
+        contracts.insert_one(bson::to_document(contract)?, None).await?;
+        Ok(())
+    }
+
+    async fn update_contract(&self, contract: &model::Contract) -> Result<()> {
+        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
+
+        debug!("Updating contract {} range: {:?}", contract.id, contract.processed_range);
+
+        contracts.update_one(doc! {
+                        "_id": &contract.id
+                    }, doc! {
+                        "$set": {
+                            "processed_range": bson::to_bson(&contract.processed_range)?,
+                            "backfilling": contract.backfilling,
+                        }
+                    }, None).await?;
+        Ok(())
+    }
+
+    async fn update_contract_info(&self, contract: &model::Contract) -> Result<()> {
+        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
+
+        debug!("Updating contract {} info", contract.id);
+
+        contracts.update_one(doc! {
+                        "_id": &contract.id
+                    }, doc! {
+                        "$set": {
+                            "labels": bson::to_bson(&contract.labels)?,
+                            "description": bson::to_bson(&contract.description)?,
+                            "paused": contract.paused,
+                            "rules": bson::to_bson(&contract.rules)?,
+                        }
+                    }, None).await?;
+        Ok(())
+    }
+
+    async fn update_abi(&self, contract: &model::Contract) -> Result<()> {
+        let contracts = self.database.collection(model::Contract::COLLECTION_NAME);
+
+        debug!("Updating contract {} ABI to version {}", contract.id, contract.abi_version());
+
+        contracts.update_one(doc! {
+                        "_id": &contract.id
+                    }, doc! {
+                        "$set": {
+                            "abi_json": bson::to_bson(&contract.abi_json)?,
+                            "abi_history": bson::to_bson(&contract.abi_history)?,
+                        }
+                    }, None).await?;
+        Ok(())
+    }
+
+    async fn delete_contract(&self, contract: &model::Contract) -> Result<()> {
+        self.database.collection(model::Job::COLLECTION_NAME).delete_many(doc! {
+            "chain_id": contract.chain_id,
+            "address": &contract.address
+        }, None).await?;
+
+        self.database.collection(model::Coverage::COLLECTION_NAME).delete_one(doc! {
+            "_id": &contract.id
+        }, None).await?;
+
+        self.database.collection(model::Webhook::COLLECTION_NAME).delete_many(doc! {
+            "chain_id": contract.chain_id,
+            "address": &contract.address
+        }, None).await?;
+
+        debug!("Deleting contract {}", contract.id);
+
+        self.database.collection(model::Contract::COLLECTION_NAME).delete_one(doc! {
+            "_id": &contract.id
+        }, None).await?;
+        Ok(())
+    }
+
+    async fn save_blocks(&self, blocks: &[model::Block]) -> Result<BulkResult> {
+        debug!("Saving {} blocks", blocks.len());
+        self.upsert_many(model::Block::COLLECTION_NAME, blocks.iter()).await
+    }
+
+    async fn get_blooms(&self, chain_id: u64, range: &Range<u64>) -> Result<BTreeMap<u64, H2048>> {
+        let blocks: Vec<model::Block> = self.find_all(model::Block::COLLECTION_NAME, doc! {
+            "chain_id": chain_id as i64,
+            "number": { "$gte": range.start as i64, "$lt": range.end as i64 }
+        }, None).await?;
+
+        Ok(blocks.into_iter()
+            .filter_map(|b| Some((b.number?.as_u64(), b.logs_bloom?)))
+            .collect())
+    }
+
+    async fn save_transactions(&self, transactions: &[model::Transaction]) -> Result<BulkResult> {
+        debug!("Saving {} trx", transactions.len());
+        self.upsert_many(model::Transaction::COLLECTION_NAME, transactions.iter()).await
+    }
+
+    async fn find_trx_to(&self, chain_id: u64, address: &str, after: Option<H256>, limit: u32) -> Result<Vec<model::Transaction>> {
+        let mut filter = doc! {
+            "chain_id": chain_id as i64,
+            "to": address.to_lowercase()
+        };
+
+        if let Some(after) = after {
+            filter.insert("hash", doc! { "$gt": bson::to_bson(&after)? });
+        }
+
+        self.find_all(Transaction::COLLECTION_NAME, filter, FindOptions::builder()
+            .sort(doc! { "hash": 1 })
+            .limit(limit as i64)
+            .batch_size(limit)
+            .build()).await
+    }
+
+    async fn count_trx_to(&self, chain_id: u64, address: &str, range: &Range<u64>) -> Result<i64> {
+        let collection = self.database.collection(Transaction::COLLECTION_NAME);
+
+        Ok(collection.count_documents(doc! {
+            "chain_id": chain_id as i64,
+            "to": address.to_lowercase(),
+            "blockNumber": { "$gte": range.start as i64, "$lt": range.end as i64 }
+        }, None).await?)
+    }
+
+    async fn delete_trx_to(&self, chain_id: u64, address: &str) -> Result<u64> {
+        let filter = doc! {
+            "chain_id": chain_id as i64,
+            "to": address.to_lowercase()
+        };
+
+        self.database.collection(model::PendingTransaction::COLLECTION_NAME).delete_many(filter.clone(), None).await?;
+
+        let res = self.database.collection(Transaction::COLLECTION_NAME).delete_many(filter, None).await?;
+        Ok(res.deleted_count as u64)
+    }
+
+    async fn get_watchlist(&self, chain_id: u64) -> Result<Vec<model::WatchedAddress>> {
+        self.find_all(model::WatchedAddress::COLLECTION_NAME, doc! {
+            "chain_id": chain_id as i64
+        }, None).await
+    }
+
+    async fn get_watched(&self, chain_id: u64, address: &str) -> Result<Option<model::WatchedAddress>> {
+        let doc = self._find_item(model::WatchedAddress::COLLECTION_NAME, doc! {
+            "_id": model::WatchedAddress::key(chain_id, address)
+        }, None).await;
+
+        doc.map(read).transpose()
+    }
+
+    async fn save_watched(&self, watched: &model::WatchedAddress) -> Result<()> {
+        let collection = self.database.collection(model::WatchedAddress::COLLECTION_NAME);
+
+        debug!("Saving watched address {:?}", watched);
+
+        collection.replace_one(doc! {
+                        "_id": &watched.id
+                    }, bson::to_document(watched)?, ReplaceOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    async fn delete_watched(&self, watched: &model::WatchedAddress) -> Result<()> {
+        self.database.collection(model::Coverage::COLLECTION_NAME).delete_one(doc! {
+            "_id": &watched.id
+        }, None).await?;
+
+        debug!("Deleting watched address {}", watched.id);
+
+        self.database.collection(model::WatchedAddress::COLLECTION_NAME).delete_one(doc! {
+            "_id": &watched.id
+        }, None).await?;
+        Ok(())
+    }
+
+    async fn get_webhooks(&self, chain_id: Option<u64>, address: Option<&str>) -> Result<Vec<model::Webhook>> {
+        let mut filter = doc! {};
+
+        if let Some(chain_id) = chain_id {
+            filter.insert("chain_id", chain_id as i64);
+        }
+        if let Some(address) = address {
+            filter.insert("address", address.to_lowercase());
+        }
+
+        self.find_all(model::Webhook::COLLECTION_NAME, filter, None).await
+    }
+
+    async fn save_webhook(&self, webhook: &model::Webhook) -> Result<()> {
+        let collection = self.database.collection(model::Webhook::COLLECTION_NAME);
+
+        collection.insert_one(bson::to_document(webhook)?, None).await?;
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, id: &str) -> Result<bool> {
+        let res = self.database.collection(model::Webhook::COLLECTION_NAME).delete_one(doc! {
+            "_id": id
+        }, None).await?;
+
+        self.database.collection(model::Notification::COLLECTION_NAME).delete_many(doc! {
+            "webhook_id": id
+        }, None).await?;
+
+        Ok(res.deleted_count > 0)
+    }
+
+    async fn save_dead_letter(&self, letter: &model::DeadLetter) -> Result<()> {
+        let collection = self.database.collection(model::DeadLetter::COLLECTION_NAME);
+
+        collection.replace_one(doc! {
+                        "_id": &letter.id
+                    }, bson::to_document(letter)?, ReplaceOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    async fn get_dead_letters(&self, webhook_id: Option<&str>) -> Result<Vec<model::DeadLetter>> {
+        let filter = webhook_id.map(|id| doc! { "webhook_id": id });
+
+        self.find_all(model::DeadLetter::COLLECTION_NAME, filter, FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .build()).await
+    }
+
+    async fn save_notification(&self, notification: &model::Notification) -> Result<()> {
+        let collection = self.database.collection(model::Notification::COLLECTION_NAME);
+
+        collection.replace_one(doc! {
+                        "_id": &notification.id
+                    }, bson::to_document(notification)?, ReplaceOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    async fn is_notified(&self, id: &str) -> Result<bool> {
+        let count = self.database.collection(model::Notification::COLLECTION_NAME).count_documents(doc! {
+            "_id": id
+        }, None).await?;
+
+        Ok(count > 0)
+    }
+
+    async fn find_coverage(&self, id: String, chain_id: u64, address: &str) -> Result<model::Coverage> {
+        let doc = self._find_item(model::Coverage::COLLECTION_NAME, doc! {
+            "_id": &id
+        }, None).await;
+
+        match doc {
+            Some(doc) => read(doc),
+            None => Ok(model::Coverage::with_id(id, chain_id, address)),
+        }
+    }
+
+    async fn save_coverage(&self, coverage: &model::Coverage) -> Result<()> {
+        let collection = self.database.collection(model::Coverage::COLLECTION_NAME);
+
+        collection.replace_one(doc! {
+                        "_id": &coverage.id
+                    }, bson::to_document(coverage)?, ReplaceOptions::builder().upsert(true).build()).await?;
+        Ok(())
+    }
+
+    async fn enqueue_job(&self, job: &model::Job) -> Result<bool> {
+        let collection = self.database.collection(model::Job::COLLECTION_NAME);
+
+        // unfinished job doesn't match, so upsert fails on its id
+        match collection.replace_one(doc! {
+            "_id": &job.id,
+            "status": { "$in": bson::to_bson(&JobStatus::FINISHED)? }
+        }, bson::to_document(job)?, ReplaceOptions::builder().upsert(true).build()).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => bail!(e)
+        }
+    }
+
+    async fn next_job(&self, chain_id: u64, exclude: &[JobKind]) -> Result<Option<model::Job>> {
+        let collection = self.database.collection(model::Job::COLLECTION_NAME);
+
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "priority": -1, "created_at": 1 })
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let doc = collection.find_one_and_update(doc! {
+            "chain_id": chain_id as i64,
+            "status": bson::to_bson(&JobStatus::Queued)?,
+            "kind": { "$nin": bson::to_bson(exclude)? }
+        }, doc! {
+            "$set": {
+                "status": bson::to_bson(&JobStatus::Running)?,
+                "updated_at": chrono::Utc::now().timestamp_millis(),
             }
-        ])
-        .build();
+        }, options).await?;
+
+        doc.map(read).transpose()
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<model::Job>> {
+        let doc = self._find_item(model::Job::COLLECTION_NAME, doc! {
+            "_id": id
+        }, None).await;
+
+        doc.map(read).transpose()
+    }
+
+    async fn get_jobs(&self, chain_id: Option<u64>, address: Option<&str>, status: Option<JobStatus>) -> Result<Vec<model::Job>> {
+        let mut filter = doc! {};
+
+        if let Some(chain_id) = chain_id {
+            filter.insert("chain_id", chain_id as i64);
+        }
+        if let Some(address) = address {
+            filter.insert("address", address.to_lowercase());
+        }
+        if let Some(status) = status {
+            filter.insert("status", bson::to_bson(&status)?);
+        }
+
+        self.find_all(model::Job::COLLECTION_NAME, filter, FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .build()).await
+    }
+
+    async fn requeue_running_jobs(&self, chain_id: u64) -> Result<u64> {
+        let collection = self.database.collection(model::Job::COLLECTION_NAME);
 
-    Ok(Client::with_options(options)?)
+        let res = collection.update_many(doc! {
+                        "chain_id": chain_id as i64,
+                        "status": bson::to_bson(&JobStatus::Running)?
+                    }, doc! {
+                        "$set": { "status": bson::to_bson(&JobStatus::Queued)? }
+                    }, None).await?;
+
+        Ok(res.modified_count as u64)
+    }
+
+    async fn update_running_job(&self, job: &model::Job) -> Result<bool> {
+        let collection = self.database.collection(model::Job::COLLECTION_NAME);
+
+        let res = collection.replace_one(doc! {
+                        "_id": &job.id,
+                        "status": bson::to_bson(&JobStatus::Running)?
+                    }, bson::to_document(job)?, None).await?;
+
+        Ok(res.matched_count == 1)
+    }
+
+    async fn set_job_status(&self, id: &str, from: &[JobStatus], to: JobStatus) -> Result<bool> {
+        let collection = self.database.collection(model::Job::COLLECTION_NAME);
+
+        let res = collection.update_one(doc! {
+                        "_id": id,
+                        "status": { "$in": bson::to_bson(from)? }
+                    }, doc! {
+                        "$set": {
+                            "status": bson::to_bson(&to)?,
+                            "updated_at": chrono::Utc::now().timestamp_millis(),
+                        }
+                    }, None).await?;
+
+        Ok(res.matched_count == 1)
+    }
+
+    async fn save_pending(&self, trx: &model::PendingTransaction) -> Result<()> {
+        let collection = self.database.collection(model::PendingTransaction::COLLECTION_NAME);
+
+        match collection.insert_one(bson::to_document(trx)?, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key(&e) => {
+                debug!("Pending trx {:?} already saved", trx.hash);
+                Ok(())
+            }
+            Err(e) => bail!(e)
+        }
+    }
+
+    async fn get_pending(&self, chain_id: u64) -> Result<Vec<model::PendingTransaction>> {
+        self.find_all(model::PendingTransaction::COLLECTION_NAME, doc! {
+            "chain_id": chain_id as i64,
+            "status": bson::to_bson(&PendingStatus::Pending)?
+        }, None).await
+    }
+
+    async fn update_pending(&self, trx: &model::PendingTransaction) -> Result<()> {
+        let collection = self.database.collection(model::PendingTransaction::COLLECTION_NAME);
+
+        collection.replace_one(doc! {
+                        "_id": bson::to_bson(&trx.id)?
+                    }, bson::to_document(trx)?, None).await?;
+        Ok(())
+    }
+
+    async fn replace_pending(&self, mined: &ChainKey<H256>, from: &H160, nonce: &U256) -> Result<u64> {
+        let collection = self.database.collection(model::PendingTransaction::COLLECTION_NAME);
+
+        let res = collection.update_many(doc! {
+                        "chain_id": mined.chain_id,
+                        "from": bson::to_bson(from)?,
+                        "nonce": num::to_i64(nonce)?,
+                        "status": bson::to_bson(&PendingStatus::Pending)?,
+                        "_id": { "$ne": bson::to_bson(mined)? },
+                    }, doc! {
+                        "$set": {
+                            "status": bson::to_bson(&PendingStatus::Replaced)?,
+                            "replaced_by": bson::to_bson(&mined.key)?,
+                        }
+                    }, None).await?;
+
+        Ok(res.modified_count as u64)
+    }
+}
+/// Model of stored document. Documents of older schema might not fit, so errors name the document.
+fn read<T: DeserializeOwned>(doc: Document) -> Result<T> {
+    let id = doc.get("_id").cloned();
+
+    bson::from_document(doc).with_context(|| format!("Can't read document {:?}. Check it with `migrate --dry_run`", id))
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::WriteError(WriteFailure::WriteError(we)) => we.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+/// Update statement replacing document by its `_id` or inserting it.
+fn upsert<T: Serialize>(item: &T) -> Result<Document> {
+    let item = bson::to_document(item)?;
+
+    let id = match item.get("_id") {
+        Some(id) => id.clone(),
+        None => bail!("Document without _id: {:?}", item),
+    };
+
+    Ok(doc! {
+        "q": { "_id": id },
+        "u": item,
+        "upsert": true,
+    })
 }
 
-pub fn take_db(url: &str) -> Database {
-    let client = create_connection(url).expect("failed to connect to mongo");
-    client.database("chain_scraper")
+/// Outcome of bulk upsert.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BulkResult {
+    pub inserted: u64,
+    pub updated: u64,
+    /// Documents upserted by somebody else at the same time
+    pub duplicates: u64,
+}
+
+impl BulkResult {
+    fn add(&mut self, other: &BulkResult) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.duplicates += other.duplicates;
+    }
+
+    /// Reads `update` command response.
+    fn from_response(response: &Document) -> Result<Self> {
+        let count = |key: &str| match response.get(key) {
+            Some(bson::Bson::Int32(n)) => *n as u64,
+            Some(bson::Bson::Int64(n)) => *n as u64,
+            _ => 0,
+        };
+
+        let inserted = response.get_array("upserted").map_or(0, |a| a.len() as u64);
+        let mut duplicates = 0;
+
+        if let Ok(errors) = response.get_array("writeErrors") {
+            for error in errors {
+                match error.as_document() {
+                    Some(e) if e.get_i32("code").ok() == Some(DUPLICATE_KEY) => duplicates += 1,
+                    Some(e) => bail!("Bulk upsert failed: {}", e.get_str("errmsg").unwrap_or("unknown error")),
+                    None => bail!("Bulk upsert failed: {:?}", error),
+                }
+            }
+        }
+
+        Ok(BulkResult {
+            inserted,
+            updated: count("n").saturating_sub(inserted),
+            duplicates,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::LowerHex;
-
-    use futures::stream::StreamExt;
     use log::info;
-    use mongodb::{bson, bson::doc, bson::Document};
-    use mongodb::options::{CreateCollectionOptions, DatabaseOptions, FindOneOptions, IndexOptionDefaults};
-
-    use crate::mongo::model::Contract;
-    use crate::parse::contract_abi::create_contract_abi;
 
     use super::*;
 
-    const CONTRACT: &str = r#"[{"inputs":[{"internalType":"address","name":"executorAddress","type":"address"},{"internalType":"address","name":"_buyer","type":"address"},{"internalType":"uint256","name":"_amount","type":"uint256"},{"internalType":"uint256","name":"_price","type":"uint256"}],"stateMutability":"nonpayable","type":"constructor"},{"inputs":[],"name":"buy","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[],"name":"getInfo","outputs":[{"internalType":"address","name":"","type":"address"},{"internalType":"uint256","name":"","type":"uint256"},{"internalType":"uint256","name":"","type":"uint256"},{"internalType":"bool","name":"","type":"bool"}],"stateMutability":"view","type":"function"}]"#;
-
     #[tokio::test]
     async fn get_last_block() -> Result<()> {
-        crate::error::setup_panic_handler();
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
-        let mongo_db = MongoDB::new("localhost");
+        let mongo_db = MongoDB::connect(&MongoConfig::default()).await?;
 
         let block = mongo_db._get_last_block().await.unwrap();
 
@@ -285,19 +782,18 @@ mod tests {
 
     #[tokio::test]
     async fn find_items() -> Result<()> {
-        crate::error::setup_panic_handler();
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
         let trx_to = "0xf12b5dd4ead5f743c6baa640b0216200e89b60da";
 
-        let mongo_db = MongoDB::new("localhost");
-        let mut items = mongo_db._find_trx_to(trx_to, 100).await?;
+        let mongo_db = MongoDB::connect(&MongoConfig::default()).await?;
+        let items = mongo_db.find_trx_to(1, trx_to, None, 3).await?;
 
         info!("Result: {:?}", items);
 
-        info!("Data: {:?}", items.next().await);
-        info!("Data: {:?}", items.next().await);
-        info!("Data: {:?}", items.next().await);
+        let next = mongo_db.find_trx_to(1, trx_to, items.last().map(|t| t.hash), 3).await?;
+
+        info!("Next: {:?}", next);
 
         // assert!(items.len() > 0);
         // assert!(items[0].to.is_some());
@@ -308,13 +804,10 @@ mod tests {
 
     #[tokio::test]
     async fn update_contract() -> Result<()> {
-        crate::error::setup_panic_handler();
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
-        let trx_to = "0xf12b5dd4ead5f743c6baa640b0216200e89b60da";
-
-        let mongo_db = MongoDB::new("localhost");
-        let mut contract = mongo_db.get_contracts().await.unwrap().remove(0);
+        let mongo_db = MongoDB::connect(&MongoConfig::default()).await?;
+        let mut contract = mongo_db.get_contracts(1).await.unwrap().remove(0);
 
         info!("Updating: {:?}", contract);
 
@@ -326,4 +819,44 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn upsert_by_id() -> Result<()> {
+        let block = doc! { "_id": { "chain_id": 1i64, "key": 7i64 }, "hash": "0x01" };
+
+        assert_eq!(upsert(&block)?, doc! {
+            "q": { "_id": { "chain_id": 1i64, "key": 7i64 } },
+            "u": block.clone(),
+            "upsert": true,
+        });
+        assert!(upsert(&doc! { "hash": "0x01" }).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_result_counts_duplicates() -> Result<()> {
+        let response = doc! {
+            "n": 3,
+            "nModified": 1,
+            "upserted": [{ "index": 0, "_id": 1 }, { "index": 2, "_id": 3 }],
+            "writeErrors": [{ "index": 3, "code": DUPLICATE_KEY, "errmsg": "E11000 duplicate key error" }],
+            "ok": 1.0,
+        };
+
+        assert_eq!(BulkResult::from_response(&response)?, BulkResult {
+            inserted: 2,
+            updated: 1,
+            duplicates: 1,
+        });
+
+        let failed = doc! {
+            "n": 0,
+            "writeErrors": [{ "index": 0, "code": 121, "errmsg": "Document failed validation" }],
+            "ok": 1.0,
+        };
+        assert!(BulkResult::from_response(&failed).is_err());
+
+        Ok(())
+    }
+}
@@ -1,48 +1,156 @@
 use std::fmt::{Display, Formatter};
 use std::ops::Range;
 
-use mongodb::bson;
-use mongodb::bson::Document;
 use serde::{Deserialize, Serialize};
 use web3::types::{Bytes, H160, H2048, H256, H64, Index, U256, U64};
 
 use crate::parse::contract_abi::ContractAbi;
-use crate::traversal::ChainData;
+use crate::parse::input_data::InputData;
+use crate::parse::rules::Rule;
+use crate::traversal::{ChainData, RangeSet, Role};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod num;
+
+/// `_id` of records unique within a chain only, like blocks and transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainKey<T> {
+    pub chain_id: i64,
+    pub key: T,
+}
+
+impl<T> ChainKey<T> {
+    pub fn new(chain_id: i64, key: T) -> Self {
+        ChainKey {
+            chain_id,
+            key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Contract {
+    /// `<chain_id>:<address>`
     #[serde(rename = "_id")]
     pub id: String,
+    pub chain_id: i64,
     pub address: String,
     pub create_block: Option<i64>,
     pub processed_range: Option<Range<i64>>,
+    /// Initial backfill is in progress. Live scraper picks the contract up once it is done, failed or cancelled.
+    #[serde(default)]
+    pub backfilling: bool,
     pub abi_json: ContractAbi,
+    /// Replaced ABIs, the oldest first
+    #[serde(default)]
+    pub abi_history: Vec<AbiVersion>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Not followed by live scraper while paused
+    #[serde(default)]
+    pub paused: bool,
+    /// Only decoded transactions matching any of rules are indexed. All of them if empty.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
 }
 
-impl From<Document> for Contract {
-    fn from(doc: Document) -> Self {
-        bson::from_document(doc).unwrap()
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbiVersion {
+    pub version: i64,
+    pub abi_json: ContractAbi,
+    /// Unix time in millis
+    pub replaced_at: i64,
 }
 
 impl Contract {
     pub const COLLECTION_NAME: &'static str = "contracts";
 
-    pub fn new(address: &str, abi_json: ContractAbi) -> Self {
+    pub fn new(chain_id: u64, address: &str, abi_json: ContractAbi) -> Self {
         Contract {
-            id: address.to_string().to_lowercase(),
+            id: Contract::key(chain_id, address),
+            chain_id: chain_id as i64,
             address: address.to_string().to_lowercase(),
             create_block: None,
             processed_range: None,
+            backfilling: true,
             abi_json,
+            abi_history: vec![],
+            labels: vec![],
+            description: None,
+            paused: false,
+            rules: vec![],
         }
     }
+
+    /// Current ABI version. The first uploaded ABI is version 1.
+    pub fn abi_version(&self) -> i64 {
+        self.abi_history.len() as i64 + 1
+    }
+
+    /// Replaces ABI keeping the current one in history.
+    pub fn update_abi(&mut self, abi_json: ContractAbi) {
+        let replaced = std::mem::replace(&mut self.abi_json, abi_json);
+
+        self.abi_history.push(AbiVersion {
+            version: self.abi_version(),
+            abi_json: replaced,
+            replaced_at: chrono::Utc::now().timestamp_millis(),
+        });
+    }
+
+    pub fn key(chain_id: u64, address: &str) -> String {
+        format!("{}:{}", chain_id, address.to_lowercase())
+    }
+}
+
+/// Address followed by sender, recipient or both, with no ABI needed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedAddress {
+    /// `watch:<chain_id>:<address>`. Prefixed, so coverage of the same address as contract is kept apart.
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub chain_id: i64,
+    pub address: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Scanning starts there. Chain head at the time live scraper first sees the address if None.
+    pub start_block: Option<i64>,
+    /// Unix time in millis
+    pub created_at: i64,
+}
+
+impl WatchedAddress {
+    pub const COLLECTION_NAME: &'static str = "watchlist";
+
+    pub fn new(chain_id: u64, address: &str, role: Role) -> Self {
+        WatchedAddress {
+            id: WatchedAddress::key(chain_id, address),
+            chain_id: chain_id as i64,
+            address: address.to_lowercase(),
+            role,
+            labels: vec![],
+            description: None,
+            start_block: None,
+            created_at: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    pub fn key(chain_id: u64, address: &str) -> String {
+        format!("watch:{}", Contract::key(chain_id, address))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Block {
+    /// Chain id and block number
     #[serde(rename = "_id")]
-    pub id: i64,
+    pub id: ChainKey<i64>,
+    pub chain_id: i64,
     pub hash: Option<H256>,
     /// Hash of the parent
     #[serde(rename = "parentHash")]
@@ -63,12 +171,13 @@ pub struct Block {
     #[serde(rename = "receiptsRoot")]
     pub receipts_root: H256,
     /// Block number. None if pending.
+    #[serde(with = "num::opt_int")]
     pub number: Option<U64>,
     /// Gas Used
-    #[serde(rename = "gasUsed")]
+    #[serde(rename = "gasUsed", with = "num::number")]
     pub gas_used: U256,
     /// Gas Limit
-    #[serde(rename = "gasLimit")]
+    #[serde(rename = "gasLimit", with = "num::number")]
     pub gas_limit: U256,
     /// Extra data
     #[serde(rename = "extraData")]
@@ -77,11 +186,13 @@ pub struct Block {
     #[serde(rename = "logsBloom")]
     pub logs_bloom: Option<H2048>,
     /// Timestamp
+    #[serde(with = "num::int")]
     pub timestamp: U256,
     /// Difficulty
+    #[serde(with = "num::number")]
     pub difficulty: U256,
     /// Total difficulty
-    #[serde(rename = "totalDifficulty")]
+    #[serde(rename = "totalDifficulty", with = "num::opt_number")]
     pub total_difficulty: Option<U256>,
     /// Seal fields
     #[serde(default, rename = "sealFields")]
@@ -93,6 +204,7 @@ pub struct Block {
     /// Transactions count
     pub transactions_count: i32,
     /// Size in bytes
+    #[serde(with = "num::opt_int")]
     pub size: Option<U256>,
     /// Mix Hash
     #[serde(rename = "mixHash")]
@@ -101,25 +213,14 @@ pub struct Block {
     pub nonce: Option<H64>,
 }
 
-impl From<Document> for Block {
-    fn from(doc: Document) -> Self {
-        bson::from_document(doc).unwrap()
-    }
-}
-
-impl From<&web3::types::Block<web3::types::Transaction>> for Block {
-    fn from(block: &web3::types::Block<web3::types::Transaction>) -> Self {
-        Block::new(block)
-    }
-}
-
 pub fn extract_transactions(block: &mut Block) -> Vec<Transaction> {
     let timestamp = block.timestamp;
+    let chain_id = block.chain_id as u64;
 
     // let transactions = block.transactions;
 
     let transactions: Vec<Transaction> = block.transactions.iter()
-        .map(|t| Transaction::new(t, timestamp))
+        .map(|t| Transaction::new(chain_id, t, timestamp))
         .collect();
 
     block.transactions = vec![];
@@ -130,10 +231,11 @@ pub fn extract_transactions(block: &mut Block) -> Vec<Transaction> {
 impl Block {
     pub const COLLECTION_NAME: &'static str = "blocks";
 
-    pub fn new(block: &web3::types::Block<web3::types::Transaction>) -> Self {
+    pub fn new(chain_id: u64, block: &web3::types::Block<web3::types::Transaction>) -> Self {
         let transactions_count = block.transactions.len() as i32;
         Block {
-            id: block.number.expect("Created block expected").as_u64() as i64,
+            id: ChainKey::new(chain_id as i64, block.number.expect("Created block expected").as_u64() as i64),
+            chain_id: chain_id as i64,
             hash: block.hash,
             parent_hash: block.parent_hash,
             uncles_hash: block.uncles_hash,
@@ -162,31 +264,37 @@ impl Block {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Transaction {
+    /// Chain id and hash. Transaction without replay protection might be mined on several chains.
     #[serde(rename = "_id")]
-    pub id: H256,
+    pub id: ChainKey<H256>,
+    pub chain_id: i64,
+    #[serde(with = "num::int")]
     pub timestamp: U256,
     pub hash: H256,
     /// Nonce
+    #[serde(with = "num::int")]
     pub nonce: U256,
     /// Block hash. None when pending.
     #[serde(rename = "blockHash")]
     pub block_hash: Option<H256>,
     /// Block number. None when pending.
-    #[serde(rename = "blockNumber")]
+    #[serde(rename = "blockNumber", with = "num::int")]
     pub block_number: U64,
     /// Transaction Index. None when pending.
-    #[serde(rename = "transactionIndex")]
+    #[serde(rename = "transactionIndex", with = "num::opt_int")]
     pub transaction_index: Option<Index>,
     /// Sender
     pub from: H160,
     /// Recipient (None when contract creation)
     pub to: Option<H160>,
     /// Transfered value
+    #[serde(with = "num::number")]
     pub value: U256,
     /// Gas Price
-    #[serde(rename = "gasPrice")]
+    #[serde(rename = "gasPrice", with = "num::number")]
     pub gas_price: U256,
     /// Gas amount
+    #[serde(with = "num::number")]
     pub gas: U256,
     /// Input data
     pub input: Bytes,
@@ -199,16 +307,11 @@ impl Transaction {
     pub const COLLECTION_NAME: &'static str = "transactions";
 }
 
-impl From<Document> for Transaction {
-    fn from(doc: Document) -> Self {
-        bson::from_document(doc).unwrap()
-    }
-}
-
 impl Transaction {
-    pub fn new(trx: &web3::types::Transaction, timestamp: U256) -> Self {
+    pub fn new(chain_id: u64, trx: &web3::types::Transaction, timestamp: U256) -> Self {
         Transaction {
-            id: trx.hash.clone(),
+            id: ChainKey::new(chain_id as i64, trx.hash),
+            chain_id: chain_id as i64,
             timestamp,
             hash: trx.hash,
             nonce: trx.nonce,
@@ -226,6 +329,336 @@ impl Transaction {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PendingStatus {
+    Pending,
+    Mined,
+    /// Other transaction with the same sender and nonce was mined
+    Replaced,
+    /// Left mempool without being mined
+    Dropped,
+}
+
+/// Transaction to a tracked contract seen in mempool
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    #[serde(rename = "_id")]
+    pub id: ChainKey<H256>,
+    pub chain_id: i64,
+    pub hash: H256,
+    #[serde(with = "num::int")]
+    pub nonce: U256,
+    pub from: H160,
+    pub to: Option<H160>,
+    #[serde(with = "num::number")]
+    pub value: U256,
+    #[serde(rename = "gasPrice", with = "num::number")]
+    pub gas_price: U256,
+    #[serde(with = "num::number")]
+    pub gas: U256,
+    pub input: Bytes,
+    pub input_data: Option<InputData>,
+    /// Unix time in millis the transaction was seen first
+    pub first_seen: i64,
+    pub status: PendingStatus,
+    /// Block number. Set once mined.
+    #[serde(rename = "blockNumber", with = "num::opt_int")]
+    pub block_number: Option<U64>,
+    /// Millis between first seen and block timestamp
+    pub inclusion_latency_ms: Option<i64>,
+    /// Hash of the transaction mined with the same sender and nonce
+    pub replaced_by: Option<H256>,
+}
+
+impl PendingTransaction {
+    pub const COLLECTION_NAME: &'static str = "pending_transactions";
+
+    pub fn new(chain_id: u64, trx: &web3::types::Transaction, input_data: Option<InputData>, first_seen: i64) -> Self {
+        PendingTransaction {
+            id: ChainKey::new(chain_id as i64, trx.hash),
+            chain_id: chain_id as i64,
+            hash: trx.hash,
+            nonce: trx.nonce,
+            from: trx.from,
+            to: trx.to,
+            value: trx.value,
+            gas_price: trx.gas_price,
+            gas: trx.gas,
+            input: trx.input.to_owned(),
+            input_data,
+            first_seen,
+            status: PendingStatus::Pending,
+            block_number: None,
+            inclusion_latency_ms: None,
+            replaced_by: None,
+        }
+    }
+}
+
+/// Webhook called for decoded transactions of contract mined after it was added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    /// `<chain_id>:<address>:<created_at>`
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub chain_id: i64,
+    pub address: String,
+    pub url: String,
+    /// Transactions matching any of rules are sent. All decoded ones if empty.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Key of `X-Signature-256` HMAC of payload. Unsigned if None.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Payload with `{{field}}` placeholders. Whole transaction is sent if None.
+    #[serde(default)]
+    pub template: Option<serde_json::Value>,
+    /// Unix time in millis
+    pub created_at: i64,
+}
+
+impl Webhook {
+    pub const COLLECTION_NAME: &'static str = "webhooks";
+
+    pub fn new(chain_id: u64, address: &str, url: &str) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Webhook {
+            id: format!("{}:{}", Contract::key(chain_id, address), now),
+            chain_id: chain_id as i64,
+            address: address.to_lowercase(),
+            url: url.to_string(),
+            rules: vec![],
+            secret: None,
+            template: None,
+            created_at: now,
+        }
+    }
+}
+
+/// Notification not delivered after all attempts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// `<webhook_id>:<trx hash>`
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub webhook_id: String,
+    pub chain_id: i64,
+    pub url: String,
+    pub hash: H256,
+    /// Body as it was sent
+    pub payload: String,
+    pub attempts: i64,
+    pub error: String,
+    /// Unix time in millis
+    pub created_at: i64,
+}
+
+impl DeadLetter {
+    pub const COLLECTION_NAME: &'static str = "dead_letters";
+}
+
+/// Transaction sent to webhook, delivered or dead-lettered. Decode jobs and rescans go over transactions
+/// notified before, they are not sent again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    /// `<webhook_id>:<trx hash>`
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub webhook_id: String,
+    pub chain_id: i64,
+    pub hash: H256,
+    pub delivered: bool,
+    /// Unix time in millis
+    pub created_at: i64,
+}
+
+impl Notification {
+    pub const COLLECTION_NAME: &'static str = "notifications";
+
+    pub fn key(webhook_id: &str, hash: &H256) -> String {
+        format!("{}:{:#x}", webhook_id, hash)
+    }
+}
+
+/// Block ranges scanned for a contract or watched address. `blocks` collection can't tell that as it keeps
+/// only blocks with matching transactions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Coverage {
+    /// Contract or watched address id
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub chain_id: i64,
+    pub address: String,
+    pub covered: RangeSet,
+    /// Unix time in millis
+    pub updated_at: i64,
+}
+
+impl Coverage {
+    pub const COLLECTION_NAME: &'static str = "coverage";
+
+    #[cfg(test)]
+    pub fn new(chain_id: u64, address: &str) -> Self {
+        Coverage::with_id(Contract::key(chain_id, address), chain_id, address)
+    }
+
+    pub fn with_id(id: String, chain_id: u64, address: &str) -> Self {
+        Coverage {
+            id,
+            chain_id: chain_id as i64,
+            address: address.to_lowercase(),
+            covered: RangeSet::new(),
+            updated_at: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Stopped by user. Continues where it stopped once resumed.
+    Paused,
+    Cancelled,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    /// Statuses a job never leaves by itself. Such job might be queued again under the same id.
+    pub const FINISHED: [JobStatus; 3] = [JobStatus::Cancelled, JobStatus::Done, JobStatus::Failed];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobKind {
+    /// Range requested by user
+    Backfill,
+    /// Hole in scanned ranges found by audit
+    GapFill,
+    /// History of just uploaded contract. Range end is the chain head at job start.
+    Initial,
+    /// Decodes stored transactions with current ABI
+    Redecode,
+    /// Drops indexed transactions of contract and indexes stored ones again
+    Reindex,
+}
+
+impl JobKind {
+    /// Higher runs first. Freshly uploaded contract is the one somebody waits for.
+    pub fn default_priority(&self) -> i32 {
+        match self {
+            JobKind::Initial => 100,
+            JobKind::Backfill | JobKind::Redecode | JobKind::Reindex => 50,
+            JobKind::GapFill => 10,
+        }
+    }
+
+    /// Kinds scanning the chain. The rest work on stored transactions.
+    pub fn is_traversal(&self) -> bool {
+        matches!(self, JobKind::Backfill | JobKind::GapFill | JobKind::Initial)
+    }
+}
+
+/// Unit of background work for a contract. Progress is in blocks for traversal kinds
+/// and in transactions for the rest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub chain_id: i64,
+    pub address: String,
+    /// Job of watched address rather than of contract
+    #[serde(default)]
+    pub watched: bool,
+    pub kind: JobKind,
+    pub priority: i32,
+    pub range: Range<i64>,
+    pub status: JobStatus,
+    pub total: i64,
+    pub done: i64,
+    /// `done` when the job was (re)started. ETA is estimated from progress since then.
+    pub started_done: i64,
+    /// Unix time in millis
+    pub started_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub const COLLECTION_NAME: &'static str = "jobs";
+
+    pub fn new(kind: JobKind, chain_id: u64, address: &str, range: Range<u64>) -> Self {
+        let now = chrono::Utc::now().timestamp_millis();
+
+        Job {
+            id: format!("{:?}:{}:{}-{}:{}", kind, Contract::key(chain_id, address), range.start, range.end, now).to_lowercase(),
+            chain_id: chain_id as i64,
+            address: address.to_lowercase(),
+            watched: false,
+            kind,
+            priority: kind.default_priority(),
+            range: range.start as i64..range.end as i64,
+            status: JobStatus::Queued,
+            total: 0,
+            done: 0,
+            started_done: 0,
+            started_at: None,
+            created_at: now,
+            updated_at: now,
+            error: None,
+        }
+    }
+
+    /// Job id is derived from contract and range, so the same hole is never queued twice at a time.
+    pub fn gap_fill(chain_id: u64, address: &str, range: Range<u64>) -> Self {
+        Job {
+            id: format!("gap:{}:{}-{}", Contract::key(chain_id, address), range.start, range.end),
+            ..Job::new(JobKind::GapFill, chain_id, address, range)
+        }
+    }
+
+    /// Gap fill of watched address. It is kept apart from the one of contract at the same address, as their coverage is.
+    pub fn gap_fill_watched(chain_id: u64, address: &str, range: Range<u64>) -> Self {
+        Job {
+            id: format!("gap:{}:{}-{}", WatchedAddress::key(chain_id, address), range.start, range.end),
+            watched: true,
+            ..Job::new(JobKind::GapFill, chain_id, address, range)
+        }
+    }
+
+    /// Backfill of uploaded contract from its creation block. One per contract.
+    pub fn initial(chain_id: u64, address: &str, create_block: u64) -> Self {
+        Job {
+            id: format!("initial:{}", Contract::key(chain_id, address)),
+            ..Job::new(JobKind::Initial, chain_id, address, create_block..create_block)
+        }
+    }
+
+    /// Percent of work done
+    pub fn progress(&self) -> f64 {
+        match self.total {
+            0 if self.status == JobStatus::Done => 100.0,
+            0 => 0.0,
+            total => (self.done as f64 / total as f64 * 100.0).min(100.0),
+        }
+    }
+
+    /// Millis left at the rate since the job was started, None until there is a rate.
+    pub fn eta_ms(&self, now: i64) -> Option<i64> {
+        let started_at = self.started_at.filter(|_| self.status == JobStatus::Running)?;
+        let done = self.done - self.started_done;
+
+        if done <= 0 {
+            return None;
+        }
+
+        let left = (self.total - self.done).max(0);
+        Some(((now - started_at) as f64 / done as f64 * left as f64) as i64)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChainDataDO {
     range: Range<u64>,
@@ -233,14 +666,14 @@ pub struct ChainDataDO {
     pub transactions: Vec<Transaction>,
 }
 
-impl From<&ChainData> for ChainDataDO {
-    fn from(cd: &ChainData) -> Self {
+impl ChainDataDO {
+    pub fn new(chain_id: u64, cd: &ChainData) -> Self {
         let mut blocks: Vec<Block> = cd.blocks.iter()
-            .map(|b| b.into())
+            .map(|b| Block::new(chain_id, b))
             .collect();
 
         let transactions = blocks.iter_mut()
-            .flat_map(|b| extract_transactions(b))
+            .flat_map(extract_transactions)
             .collect();
 
         ChainDataDO {
@@ -260,8 +693,48 @@ impl Display for ChainDataDO {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test() {
         println!("Hello");
     }
+
+    #[test]
+    fn update_abi_keeps_history() {
+        let abi = |json: &str| -> ContractAbi { serde_json::from_str(json).unwrap() };
+
+        let mut contract = Contract::new(1, "0x01", abi("[]"));
+        assert_eq!(contract.abi_version(), 1);
+
+        contract.update_abi(abi(r#"[{"type": "fallback"}]"#));
+        contract.update_abi(abi("[]"));
+
+        assert_eq!(contract.abi_version(), 3);
+        assert_eq!(contract.abi_history.iter().map(|v| v.version).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(contract.abi_history[1].abi_json.functions.len(), 1);
+        assert!(contract.abi_json.functions.is_empty());
+    }
+
+    #[test]
+    fn job_progress_and_eta() {
+        let mut job = Job::initial(1, "0xAB", 100);
+        assert_eq!(job.id, "initial:1:0xab");
+        assert_eq!(job.priority, 100);
+        assert_eq!(job.progress(), 0.0);
+        assert_eq!(job.eta_ms(0), None);
+
+        job.status = JobStatus::Running;
+        job.total = 1000;
+        job.done = 400;
+        job.started_done = 200;
+        job.started_at = Some(1_000);
+
+        assert_eq!(job.progress(), 40.0);
+        // 200 blocks in 2 seconds, 600 left
+        assert_eq!(job.eta_ms(3_000), Some(6_000));
+
+        job.status = JobStatus::Paused;
+        assert_eq!(job.eta_ms(3_000), None);
+    }
 }
@@ -0,0 +1,213 @@
+//! Stored representation of chain numbers. web3 writes them as hex strings, which Mongo compares
+//! as strings, so range queries and sorting by them don't work. Documents written in hex are still read.
+
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use web3::types::{U256, U64};
+
+/// Digits of `U256::MAX`
+const DEC_WIDTH: usize = 78;
+
+/// 256-bit value as zero-padded decimal, which sorts the way numbers do, with the original hex alongside.
+/// Ranges are queried on `dec`, e.g. `{"value.dec": {"$gt": sortable(&x)}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Number {
+    pub dec: String,
+    pub hex: U256,
+}
+
+impl From<&U256> for Number {
+    fn from(value: &U256) -> Self {
+        Number {
+            dec: sortable(value),
+            hex: *value,
+        }
+    }
+}
+
+pub fn sortable(value: &U256) -> String {
+    format!("{:0>width$}", value.to_string(), width = DEC_WIDTH)
+}
+
+/// Any representation a number was ever stored in
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Int(i64),
+    Number(Number),
+    Hex(U256),
+}
+
+impl Stored {
+    fn value(self) -> Option<U256> {
+        match self {
+            Stored::Int(n) => u64::try_from(n).ok().map(U256::from),
+            Stored::Number(n) => Some(n.hex),
+            Stored::Hex(n) => Some(n),
+        }
+    }
+}
+
+/// Numbers small enough to be stored as native integers, like block numbers, nonces and timestamps.
+pub trait Int: Sized {
+    fn to_i64(&self) -> Option<i64>;
+
+    fn from_u256(value: U256) -> Option<Self>;
+}
+
+impl Int for U64 {
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self.as_u64()).ok()
+    }
+
+    fn from_u256(value: U256) -> Option<Self> {
+        match value.bits() {
+            0..=64 => Some(U64::from(value.low_u64())),
+            _ => None,
+        }
+    }
+}
+
+impl Int for U256 {
+    fn to_i64(&self) -> Option<i64> {
+        match self.bits() {
+            0..=63 => Some(self.low_u64() as i64),
+            _ => None,
+        }
+    }
+
+    fn from_u256(value: U256) -> Option<Self> {
+        Some(value)
+    }
+}
+
+/// Stored integer of value, e.g. to query by it.
+pub fn to_i64<T: Int>(value: &T) -> anyhow::Result<i64> {
+    value.to_i64().ok_or_else(|| anyhow::anyhow!("Number doesn't fit stored integer"))
+}
+
+fn read<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+    Stored::deserialize(deserializer)?.value()
+        .ok_or_else(|| D::Error::custom("negative number"))
+}
+
+/// `#[serde(with = "num::int")]`
+pub mod int {
+    use super::*;
+
+    pub fn serialize<T: Int, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        match value.to_i64() {
+            Some(n) => serializer.serialize_i64(n),
+            None => Err(S::Error::custom("number doesn't fit stored integer")),
+        }
+    }
+
+    pub fn deserialize<'de, T: Int, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        T::from_u256(read(deserializer)?).ok_or_else(|| D::Error::custom("number doesn't fit"))
+    }
+}
+
+/// `#[serde(with = "num::opt_int")]`
+pub mod opt_int {
+    use super::*;
+
+    pub fn serialize<T: Int, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::int::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T: Int, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+        Option::<Stored>::deserialize(deserializer)?
+            .map(|s| s.value().and_then(T::from_u256).ok_or_else(|| D::Error::custom("number doesn't fit")))
+            .transpose()
+    }
+}
+
+/// `#[serde(with = "num::number")]`
+pub mod number {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        Number::from(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        read(deserializer)
+    }
+}
+
+/// `#[serde(with = "num::opt_number")]`
+pub mod opt_number {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.as_ref().map(Number::from).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<U256>, D::Error> {
+        Option::<Stored>::deserialize(deserializer)?
+            .map(|s| s.value().ok_or_else(|| D::Error::custom("negative number")))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{self, Bson, doc};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        #[serde(with = "int")]
+        block: U64,
+        #[serde(with = "int")]
+        nonce: U256,
+        #[serde(with = "number")]
+        value: U256,
+        #[serde(with = "opt_number")]
+        size: Option<U256>,
+    }
+
+    #[test]
+    fn stored_as_sortable_numbers() {
+        let stored = Sample {
+            block: U64::from(0x10),
+            nonce: U256::from(7),
+            value: U256::from(255),
+            size: None,
+        };
+
+        let doc = bson::to_document(&stored).unwrap();
+
+        assert_eq!(doc.get("block"), Some(&Bson::Int64(16)));
+        assert_eq!(doc.get("nonce"), Some(&Bson::Int64(7)));
+        assert_eq!(doc.get_document("value").unwrap().get_str("dec").unwrap(), format!("{:0>78}", 255));
+        assert_eq!(doc.get_document("value").unwrap().get_str("hex").unwrap(), "0xff");
+        assert_eq!(bson::from_document::<Sample>(doc).unwrap(), stored);
+
+        assert!(sortable(&U256::from(9)) < sortable(&U256::from(10)));
+        assert!(sortable(&U256::from(10)) < sortable(&U256::max_value()));
+        assert_eq!(sortable(&U256::max_value()).len(), DEC_WIDTH);
+    }
+
+    #[test]
+    fn reads_hex_written_before() {
+        let doc = doc! { "block": "0x10", "nonce": "0x7", "value": "0xff", "size": "0x200" };
+
+        assert_eq!(bson::from_document::<Sample>(doc).unwrap(), Sample {
+            block: U64::from(0x10),
+            nonce: U256::from(7),
+            value: U256::from(255),
+            size: Some(U256::from(512)),
+        });
+
+        let too_big = doc! { "block": "0x10", "nonce": format!("{:#x}", U256::max_value()), "value": "0x0", "size": Bson::Null };
+        assert!(bson::to_document(&bson::from_document::<Sample>(too_big).unwrap()).is_err());
+    }
+}
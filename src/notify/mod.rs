@@ -0,0 +1,548 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac, NewMac};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::mongo::model::{Contract, DeadLetter, Notification, Transaction, Webhook};
+use crate::mongo::model::num;
+use crate::parse::input_data::InputData;
+use crate::parse::rules::{self, TrxFields};
+use crate::shutdown::Shutdown;
+use crate::storage::Storage;
+
+/// Wait before the first retry. Doubles with every attempt.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often deliveries in flight are checked on shutdown.
+const STOP_POLL: Duration = Duration::from_millis(200);
+/// Deliveries sent at once. Ones beyond go to dead letters, indexing never waits for webhooks.
+const MAX_IN_FLIGHT: usize = 32;
+
+/// Notification of a single transaction to a single webhook.
+#[derive(Debug, Clone)]
+struct Delivery {
+    webhook: Webhook,
+    trx: Value,
+    body: String,
+}
+
+/// Sends decoded transactions to webhooks of their contracts. Delivery is at least once,
+/// notifications failed all attempts or finding every delivery slot taken go to dead letters. Each transaction goes to a webhook once,
+/// however many times it is decoded.
+///
+/// Only transaction calls are notified. Events need receipts, which traversal doesn't fetch.
+#[derive(Debug)]
+pub struct Notifier {
+    storage: Arc<dyn Storage>,
+    client: reqwest::Client,
+    max_attempts: u32,
+    in_flight: Arc<AtomicUsize>,
+    /// Notification ids being delivered
+    sending: Arc<Mutex<HashSet<String>>>,
+    slots: Arc<Semaphore>,
+    shutdown: Shutdown,
+}
+
+impl Notifier {
+    pub fn new(storage: Arc<dyn Storage>, max_attempts: u32, shutdown: Shutdown) -> Self {
+        Self {
+            storage,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("HTTP client"),
+            max_attempts: max_attempts.max(1),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            sending: Arc::new(Mutex::new(HashSet::new())),
+            slots: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            shutdown,
+        }
+    }
+
+    /// Completes on shutdown once deliveries in flight are sent or dead-lettered.
+    pub fn run(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            self.shutdown.requested().await;
+
+            while self.in_flight.load(Ordering::SeqCst) > 0 {
+                tokio::time::delay_for(STOP_POLL).await;
+            }
+
+            info!("Notifier stopped");
+        })
+    }
+
+    /// Queues notifications for transactions mined after webhook was added, so backfills of history
+    /// don't alert. Transactions sent to webhook before are skipped. Failures don't stop indexing.
+    pub async fn notify(&self, contract: &Contract, decoded: &[(&Transaction, InputData)]) {
+        if decoded.is_empty() {
+            return;
+        }
+
+        let webhooks = match self.storage.get_webhooks(Some(contract.chain_id as u64), Some(&contract.address)).await {
+            Ok(w) => w,
+            Err(e) => {
+                warn!("Failed to get webhooks of {}. {:?}", contract.id, e);
+                return;
+            }
+        };
+
+        for webhook in webhooks {
+            for (trx, input) in decoded {
+                let mined_at = match num::to_i64(&trx.timestamp) {
+                    Ok(t) => t.saturating_mul(1000),
+                    Err(e) => {
+                        warn!("Bad timestamp of trx {:?}. {:?}", trx.hash, e);
+                        continue;
+                    }
+                };
+
+                if mined_at < webhook.created_at || !rules::accepts(&webhook.rules, &TrxFields::new(input, trx)) {
+                    continue;
+                }
+
+                let id = Notification::key(&webhook.id, &trx.hash);
+
+                if !self.sending.lock().unwrap().insert(id.clone()) {
+                    continue;
+                }
+
+                match self.storage.is_notified(&id).await {
+                    Ok(false) => (),
+                    Ok(true) => {
+                        self.sending.lock().unwrap().remove(&id);
+                        continue;
+                    }
+                    Err(e) => warn!("Failed to check notification {}, sending it. {:?}", id, e),
+                }
+
+                let trx = payload(contract, trx, input);
+                let body = match &webhook.template {
+                    Some(template) => render(template, &trx),
+                    None => trx.clone(),
+                }.to_string();
+
+                self.spawn(id, Delivery {
+                    webhook: webhook.clone(),
+                    trx,
+                    body,
+                }).await;
+            }
+        }
+    }
+
+    /// Sends in background. Notification finding every slot taken goes to dead letters right away,
+    /// so slow webhooks never hold indexing up.
+    async fn spawn(&self, id: String, delivery: Delivery) {
+        let slot = match self.slots.clone().try_acquire_owned() {
+            Ok(slot) => slot,
+            Err(_) => {
+                let e = anyhow!("{} deliveries in flight already", MAX_IN_FLIGHT);
+                record(self.storage.as_ref(), &id, &delivery, Err((0, e))).await;
+                self.sending.lock().unwrap().remove(&id);
+                return;
+            }
+        };
+
+        let storage = self.storage.clone();
+        let client = self.client.clone();
+        let max_attempts = self.max_attempts;
+        let in_flight = self.in_flight.clone();
+        let sending = self.sending.clone();
+        let shutdown = self.shutdown.clone();
+
+        in_flight.fetch_add(1, Ordering::SeqCst);
+
+        tokio::spawn(async move {
+            let outcome = deliver(&client, &delivery, max_attempts, RETRY_BACKOFF, &shutdown).await;
+            record(storage.as_ref(), &id, &delivery, outcome).await;
+
+            sending.lock().unwrap().remove(&id);
+            drop(slot);
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Saves outcome of delivery, so transaction isn't sent again. Failed one goes to dead letters as well.
+async fn record(storage: &dyn Storage, id: &str, delivery: &Delivery, outcome: Result<(), (u32, anyhow::Error)>) {
+    let delivered = match outcome {
+        Ok(()) => true,
+        Err((attempts, e)) => {
+            warn!("Webhook {} failed after {} attempts. {:?}", delivery.webhook.id, attempts, e);
+
+            if let Err(e) = storage.save_dead_letter(&dead_letter(delivery, attempts, &e)).await {
+                warn!("Failed to save dead letter of webhook {}. {:?}", delivery.webhook.id, e);
+            }
+            false
+        }
+    };
+
+    let notification = Notification {
+        id: id.to_string(),
+        webhook_id: delivery.webhook.id.clone(),
+        chain_id: delivery.webhook.chain_id,
+        hash: serde_json::from_value(delivery.trx["hash"].clone()).unwrap_or_default(),
+        delivered,
+        created_at: chrono::Utc::now().timestamp_millis(),
+    };
+
+    if let Err(e) = storage.save_notification(&notification).await {
+        warn!("Failed to save notification {}. {:?}", id, e);
+    }
+}
+
+fn dead_letter(delivery: &Delivery, attempts: u32, error: &anyhow::Error) -> DeadLetter {
+    let hash = serde_json::from_value(delivery.trx["hash"].clone()).unwrap_or_default();
+
+    DeadLetter {
+        id: Notification::key(&delivery.webhook.id, &hash),
+        webhook_id: delivery.webhook.id.clone(),
+        chain_id: delivery.webhook.chain_id,
+        url: delivery.webhook.url.clone(),
+        hash,
+        payload: delivery.body.clone(),
+        attempts: attempts as i64,
+        error: format!("{:?}", error),
+        created_at: chrono::Utc::now().timestamp_millis(),
+    }
+}
+
+/// Retries with exponential backoff. Gives up early on shutdown. Error holds number of attempts made.
+async fn deliver(client: &reqwest::Client, delivery: &Delivery, max_attempts: u32, backoff: Duration,
+                 shutdown: &Shutdown) -> Result<(), (u32, anyhow::Error)> {
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let e = match send(client, delivery).await {
+            Ok(()) => {
+                debug!("Webhook {} delivered in {} attempts", delivery.webhook.id, attempt);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        if attempt >= max_attempts || shutdown.is_requested() {
+            return Err((attempt, e));
+        }
+
+        debug!("Webhook {} attempt {} failed. {:?}", delivery.webhook.id, attempt, e);
+
+        tokio::select! {
+            _ = tokio::time::delay_for(backoff * (1 << (attempt - 1))) => (),
+            _ = shutdown.requested() => return Err((attempt, e)),
+        }
+    }
+}
+
+async fn send(client: &reqwest::Client, delivery: &Delivery) -> Result<()> {
+    let mut request = client.post(&delivery.webhook.url)
+        .header("Content-Type", "application/json")
+        .body(delivery.body.clone());
+
+    if let Some(secret) = &delivery.webhook.secret {
+        request = request.header("X-Signature-256", sign(secret, &delivery.body));
+    }
+
+    request.send().await?.error_for_status()?;
+
+    Ok(())
+}
+
+/// `sha256=<hex HMAC of body>`
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC takes key of any size");
+    mac.update(body.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Transaction fields available to templates.
+fn payload(contract: &Contract, trx: &Transaction, input: &InputData) -> Value {
+    json!({
+        "chain_id": contract.chain_id,
+        "address": contract.address,
+        "labels": contract.labels,
+        "hash": trx.hash,
+        "block_number": trx.block_number.as_u64(),
+        "timestamp": num::to_i64(&trx.timestamp).ok(),
+        "from": trx.from,
+        "to": trx.to,
+        "value": trx.value.to_string(),
+        "method": input.method_name,
+        "args": input.args,
+    })
+}
+
+/// Replaces `{{path}}` placeholders in strings of template with fields of transaction.
+/// String consisting of a single placeholder takes field value as it is, numbers stay numbers.
+fn render(template: &Value, trx: &Value) -> Value {
+    match template {
+        Value::String(s) => {
+            let trimmed = s.trim();
+
+            if trimmed.starts_with("{{") && trimmed.ends_with("}}") && trimmed.matches("{{").count() == 1 {
+                return lookup(trx, &trimmed[2..trimmed.len() - 2]).cloned().unwrap_or(Value::Null);
+            }
+
+            let mut rendered = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+
+            while let Some(start) = rest.find("{{") {
+                let end = match rest[start..].find("}}") {
+                    Some(end) => start + end,
+                    None => break,
+                };
+
+                rendered.push_str(&rest[..start]);
+
+                match lookup(trx, &rest[start + 2..end]) {
+                    Some(Value::String(v)) => rendered.push_str(v),
+                    Some(v) => rendered.push_str(&v.to_string()),
+                    None => (),
+                }
+
+                rest = &rest[end + 2..];
+            }
+
+            rendered.push_str(rest);
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| render(v, trx)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render(v, trx))).collect()),
+        v => v.clone(),
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.trim()
+        .split('.')
+        .try_fold(value, |v, key| v.get(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::fixtures::storage;
+
+    use super::*;
+
+    /// Local HTTP stand-in answering with given statuses in turn. Collects requests it got.
+    async fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+
+                // headers and body of small requests
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let length = text.lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+
+                    if n == 0 || text.find("\r\n\r\n").is_some_and(|h| request.len() >= h + 4 + length) {
+                        break;
+                    }
+                }
+
+                received.lock().unwrap().push(String::from_utf8_lossy(&request).to_string());
+
+                let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn delivery(url: &str, secret: Option<&str>) -> Delivery {
+        let mut webhook = Webhook::new(1, "0x01", url);
+        webhook.secret = secret.map(String::from);
+
+        Delivery {
+            webhook,
+            trx: json!({ "hash": format!("{:#x}", web3::types::H256::from_low_u64_be(1)) }),
+            body: r#"{"method":"pause"}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn sign_body() {
+        assert_eq!(sign("key", "The quick brown fox jumps over the lazy dog"),
+                   "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn render_template() {
+        let trx = json!({
+            "hash": "0xab",
+            "value": "1000",
+            "method": "withdraw",
+            "args": { "amount": 1500 },
+        });
+        let template = json!({
+            "text": "{{method}} of {{args.amount}} in {{hash}}{{missing}}",
+            "amount": "{{ args.amount }}",
+            "fields": [{ "value": "{{value}}" }],
+            "urgent": true,
+        });
+
+        assert_eq!(render(&template, &trx), json!({
+            "text": "withdraw of 1500 in 0xab",
+            "amount": 1500,
+            "fields": [{ "value": "1000" }],
+            "urgent": true,
+        }));
+    }
+
+    #[tokio::test]
+    async fn retries_until_delivered() {
+        let (url, requests) = stand_in(vec![500, 503, 200]).await;
+        let client = reqwest::Client::new();
+
+        let res = deliver(&client, &delivery(&url, Some("secret")), 5, Duration::from_millis(10), &Shutdown::default()).await;
+        assert!(res.is_ok());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].starts_with("POST /hook"));
+        assert!(requests[0].ends_with(r#"{"method":"pause"}"#));
+        assert!(requests[0].to_lowercase().contains(&format!("x-signature-256: {}", sign("secret", r#"{"method":"pause"}"#))));
+    }
+
+    #[tokio::test]
+    async fn notifies_transaction_once() -> Result<()> {
+        let storage = storage().await?;
+
+        let (url, requests) = stand_in(vec![200]).await;
+        let mut webhook = Webhook::new(1, "0xc0", &url);
+        webhook.created_at = 0;
+        storage.save_webhook(&webhook).await?;
+
+        let contract = Contract::new(1, "0xc0", serde_json::from_str("[]")?);
+        let trx = Transaction::new(1, &web3::types::Transaction {
+            hash: web3::types::H256::from_low_u64_be(1),
+            block_number: Some(1.into()),
+            transaction_index: Some(0.into()),
+            ..Default::default()
+        }, 1.into());
+        let decoded = vec![(&trx, InputData::new("pause", Default::default()))];
+
+        let notifier = Notifier::new(storage.clone(), 1, Shutdown::default());
+        // rescan while delivery is in flight, then redecode after it is done
+        notifier.notify(&contract, &decoded).await;
+        notifier.notify(&contract, &decoded).await;
+
+        while notifier.in_flight.load(Ordering::SeqCst) > 0 {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+
+        notifier.notify(&contract, &decoded).await;
+        assert_eq!(notifier.in_flight.load(Ordering::SeqCst), 0);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(storage.is_notified(&Notification::key(&webhook.id, &trx.hash)).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skips_timestamp_out_of_range() -> Result<()> {
+        let storage = storage().await?;
+
+        let (url, requests) = stand_in(vec![200]).await;
+        let mut webhook = Webhook::new(1, "0xc0", &url);
+        webhook.created_at = 0;
+        storage.save_webhook(&webhook).await?;
+
+        let contract = Contract::new(1, "0xc0", serde_json::from_str("[]")?);
+        let trx = Transaction::new(1, &web3::types::Transaction {
+            hash: web3::types::H256::from_low_u64_be(1),
+            block_number: Some(1.into()),
+            transaction_index: Some(0.into()),
+            ..Default::default()
+        }, web3::types::U256::max_value());
+
+        let notifier = Notifier::new(storage.clone(), 1, Shutdown::default());
+        notifier.notify(&contract, &[(&trx, InputData::new("pause", Default::default()))]).await;
+
+        assert_eq!(notifier.in_flight.load(Ordering::SeqCst), 0);
+        assert!(requests.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dead_letters_when_slots_taken() -> Result<()> {
+        let storage = storage().await?;
+
+        let (url, requests) = stand_in(vec![200]).await;
+        let mut webhook = Webhook::new(1, "0xc0", &url);
+        webhook.created_at = 0;
+        storage.save_webhook(&webhook).await?;
+
+        let contract = Contract::new(1, "0xc0", serde_json::from_str("[]")?);
+        let trx = Transaction::new(1, &web3::types::Transaction {
+            hash: web3::types::H256::from_low_u64_be(1),
+            block_number: Some(1.into()),
+            transaction_index: Some(0.into()),
+            ..Default::default()
+        }, 1.into());
+
+        let mut notifier = Notifier::new(storage.clone(), 1, Shutdown::default());
+        // every slot is taken by slow webhooks
+        notifier.slots = Arc::new(Semaphore::new(0));
+        notifier.notify(&contract, &[(&trx, InputData::new("pause", Default::default()))]).await;
+
+        assert_eq!(notifier.in_flight.load(Ordering::SeqCst), 0);
+        assert!(requests.lock().unwrap().is_empty());
+        assert_eq!(storage.get_dead_letters(Some(&webhook.id)).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (url, requests) = stand_in(vec![500, 500, 500]).await;
+        let client = reqwest::Client::new();
+        let delivery = delivery(&url, None);
+
+        match deliver(&client, &delivery, 2, Duration::from_millis(10), &Shutdown::default()).await {
+            Err((attempts, e)) => {
+                assert_eq!(attempts, 2);
+
+                let letter = dead_letter(&delivery, attempts, &e);
+                assert_eq!(letter.payload, delivery.body);
+                assert_eq!(letter.id, format!("{}:{:#x}", delivery.webhook.id, web3::types::H256::from_low_u64_be(1)));
+            }
+            Ok(()) => panic!("Delivered to failing endpoint"),
+        }
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].to_lowercase().contains("x-signature-256"));
+    }
+}
@@ -10,13 +10,20 @@ pub fn _create_contract_abi(contract_json: &str) -> Result<ContractAbi> {
 }
 
 /// Contract JSON interface
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(transparent)]
 pub struct ContractAbi {
     pub functions: Vec<ContractFunction>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ContractAbi {
+    /// Whether contract declares any event. Contracts without events can't be found by logs.
+    pub fn has_events(&self) -> bool {
+        self.functions.iter().any(|f| f.r#type == FunctionType::EVENT)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ContractFunction {
     #[serde(default)]
@@ -30,24 +37,21 @@ pub struct ContractFunction {
     pub r#type: FunctionType,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum StateMutability {
     NONPAYABLE,
     PAYABLE,
     VIEW,
     PURE,
+    #[default]
     NONE,
 }
 
-impl Default for StateMutability {
-    fn default() -> Self {
-        StateMutability::NONE
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ValueType {
     // value types
     UINT8,
@@ -79,8 +83,9 @@ pub enum ValueType {
     STRING,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum FunctionType {
     FUNCTION,
     CONSTRUCTOR,
@@ -91,7 +96,7 @@ pub enum FunctionType {
     FALLBACK,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InOutType {
     pub name: String,
@@ -138,14 +143,14 @@ mod tests {
     fn create_contract_local() {
         let contract = match super::_create_contract_abi(BUY_CONTRACT) {
             Ok(c) => c,
-            Err(e) => panic!(e.to_string())
+            Err(e) => panic!("{}", e)
         };
 
         println!("C: {:?}", contract);
 
         assert_eq!(contract.functions.len(), 3);
 
-        let constructor = contract.functions.get(0).unwrap();
+        let constructor = contract.functions.first().unwrap();
 
         assert_eq!(constructor.r#type, FunctionType::CONSTRUCTOR);
         assert!(constructor.outputs.is_empty());
@@ -158,18 +163,34 @@ mod tests {
     fn create_contract_eth() {
         let contract = match super::_create_contract_abi(ETH) {
             Ok(c) => c,
-            Err(e) => panic!(e.to_string())
+            Err(e) => panic!("{}", e)
         };
 
-        // println!("C: {:?}", contract);
+        let count = |t: FunctionType| contract.functions.iter().filter(|f| f.r#type == t).count();
 
-        assert_eq!(contract.functions.len(), 19);
+        assert_eq!(contract.functions.len(), 44);
+        assert_eq!(count(FunctionType::FUNCTION), 32);
+        assert_eq!(count(FunctionType::CONSTRUCTOR), 1);
+        assert_eq!(count(FunctionType::EVENT), 11);
+        assert!(contract.has_events());
+    }
 
-        // let constructor = contract.functions.get(0).unwrap();
+    #[test]
+    fn create_contract_erc20() {
+        let contract = super::_create_contract_abi(ETH_4).unwrap();
 
-        // assert_eq!(constructor.r#type, FunctionType::CONSTRUCTOR);
-        // assert!(constructor.outputs.is_empty());
-        println!("{:?}", contract)
+        assert_eq!(contract.functions.len(), 15);
+
+        let transfer = contract.functions.iter().find(|f| f.name == "transfer").unwrap();
+
+        assert_eq!(transfer.r#type, FunctionType::FUNCTION);
+        assert_eq!(transfer.state_mutability, StateMutability::NONPAYABLE);
+        assert_eq!(transfer.inputs.iter().map(|i| i.r#type.clone()).collect::<Vec<_>>(), vec![ValueType::ADDRESS, ValueType::UINT256]);
+    }
+
+    #[test]
+    fn array_not_supported() {
+        assert!(super::_create_contract_abi(ETH_CONTRACT).is_err());
     }
 
     const BESU: &str = r###"[{"inputs":[{"internalType":"address","name":"_tokenAddress","type":"address"},{"internalType":"uint256","name":"_limit","type":"uint256"}],"stateMutability":"nonpayable","type":"constructor"},{"anonymous":false,"inputs":[{"indexed":false,"internalType":"string","name":"msg","type":"string"},{"indexed":false,"internalType":"bytes","name":"error","type":"bytes"}],"name":"ErrorMsg","type":"event"},{"inputs":[{"internalType":"address","name":"tokenHolder","type":"address"}],"name":"balance","outputs":[{"internalType":"uint256","name":"","type":"uint256"}],"stateMutability":"view","type":"function"},{"inputs":[{"internalType":"address","name":"buyer","type":"address"},{"internalType":"uint256","name":"amount","type":"uint256"},{"internalType":"bytes","name":"userData","type":"bytes"},{"internalType":"bytes","name":"operatorData","type":"bytes"}],"name":"buy","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[],"name":"disableContract","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[],"name":"disabled","outputs":[{"internalType":"bool","name":"","type":"bool"}],"stateMutability":"view","type":"function"},{"inputs":[],"name":"limit","outputs":[{"internalType":"uint256","name":"","type":"uint256"}],"stateMutability":"view","type":"function"},{"inputs":[],"name":"owner","outputs":[{"internalType":"address","name":"","type":"address"}],"stateMutability":"view","type":"function"},{"inputs":[{"internalType":"address","name":"seller","type":"address"},{"internalType":"uint256","name":"amount","type":"uint256"},{"internalType":"bytes","name":"userData","type":"bytes"},{"internalType":"bytes","name":"operatorData","type":"bytes"}],"name":"sell","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[{"internalType":"address","name":"seller","type":"address"},{"internalType":"address","name":"buyer","type":"address"},{"internalType":"uint256","name":"amount","type":"uint256"},{"internalType":"bytes","name":"userData","type":"bytes"},{"internalType":"bytes","name":"operatorData","type":"bytes"}],"name":"transfer","outputs":[],"stateMutability":"nonpayable","type":"function"}]"###;
@@ -178,14 +199,14 @@ mod tests {
     fn create_contract_besu() {
         let contract = match super::_create_contract_abi(BESU) {
             Ok(c) => c,
-            Err(e) => panic!(e.to_string())
+            Err(e) => panic!("{}", e)
         };
 
         // println!("C: {:?}", contract);
 
         assert_eq!(contract.functions.len(), 10);
 
-        // let constructor = contract.functions.get(0).unwrap();
+        // let constructor = contract.functions.first().unwrap();
 
         // assert_eq!(constructor.r#type, FunctionType::CONSTRUCTOR);
         // assert!(constructor.outputs.is_empty());
@@ -3,7 +3,7 @@ use std::fmt::Debug;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InputData {
     pub method_name: String,
     pub args: Map<String, Value>,
@@ -34,7 +34,7 @@ mod tests {
         args.insert("one".into(), Value::from(1));
         args.insert("one".into(), Value::from("Hello World"));
 
-        let mut orig_value: Map<String, Value> = serde_json::from_str(orig).expect("Orig value failed");
+        let orig_value: Map<String, Value> = serde_json::from_str(orig).expect("Orig value failed");
         println!("V: {:?}", orig_value);
 
         let id = InputData {
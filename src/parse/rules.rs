@@ -0,0 +1,250 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use web3::types::{H160, U256};
+
+use crate::parse::input_data::InputData;
+
+/// Comparison of a transaction field with rule value.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// `field` is one of `method`, `from`, `to`, `value` or `args.<name>` of decoded input.
+/// Only `value` and integer arguments are ordered, the rest are compared for equality.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Condition {
+    pub field: String,
+    pub op: Op,
+    pub value: Value,
+}
+
+/// Transaction matches rule if it calls `method` (any if omitted) and meets all of conditions.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Rule {
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// Fields of decoded transaction rules are evaluated against.
+#[derive(Debug)]
+pub struct TrxFields<'a> {
+    pub input: &'a InputData,
+    pub from: &'a H160,
+    pub to: Option<&'a H160>,
+    pub value: &'a U256,
+}
+
+impl<'a> TrxFields<'a> {
+    pub fn new(input: &'a InputData, trx: &'a crate::mongo::model::Transaction) -> Self {
+        TrxFields {
+            input,
+            from: &trx.from,
+            to: trx.to.as_ref(),
+            value: &trx.value,
+        }
+    }
+}
+
+/// Transaction passes if any of rules matches it. Everything passes without rules.
+pub fn accepts(rules: &[Rule], trx: &TrxFields) -> bool {
+    rules.is_empty() || rules.iter().any(|r| r.matches(trx))
+}
+
+impl Rule {
+    pub fn matches(&self, trx: &TrxFields) -> bool {
+        if let Some(method) = &self.method {
+            if method != &trx.input.method_name {
+                return false;
+            }
+        }
+
+        self.conditions.iter().all(|c| c.matches(trx))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for c in &self.conditions {
+            let ordering = !matches!(c.op, Op::Eq | Op::Ne);
+
+            match c.field.as_str() {
+                "method" | "from" | "to" if ordering => bail!("{:?} of {} isn't supported, it isn't a number", c.op, c.field),
+                "method" | "from" | "to" | "value" => (),
+                f if f.starts_with("args.") && f.len() > 5 => (),
+                "status" => bail!("Receipts aren't fetched, so transactions can't be filtered by status"),
+                f => bail!("Unknown field {}", f),
+            }
+
+            if ordering && number(&c.value).is_none() {
+                bail!("{:?} of {} requires a number, got {}", c.op, c.field, c.value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Condition {
+    fn matches(&self, trx: &TrxFields) -> bool {
+        // integer arguments are decoded to JSON numbers, addresses and the rest never are
+        let (actual, numeric) = match self.field.as_str() {
+            "method" => (Value::from(trx.input.method_name.as_str()), false),
+            "from" => (Value::from(format!("{:#x}", trx.from)), false),
+            "to" => match trx.to {
+                Some(to) => (Value::from(format!("{:#x}", to)), false),
+                None => (Value::Null, false),
+            },
+            "value" => (Value::from(trx.value.to_string()), true),
+            f => match f.strip_prefix("args.").and_then(|arg| trx.input.args.get(arg)) {
+                Some(v) => (v.clone(), v.is_number()),
+                None => return false,
+            },
+        };
+
+        match compare(&actual, &self.value, numeric) {
+            Some(ordering) => match self.op {
+                Op::Eq => ordering == Ordering::Equal,
+                Op::Ne => ordering != Ordering::Equal,
+                Op::Gt => ordering == Ordering::Greater,
+                Op::Gte => ordering != Ordering::Less,
+                Op::Lt => ordering == Ordering::Less,
+                Op::Lte => ordering != Ordering::Greater,
+            },
+            None => self.op == Op::Ne,
+        }
+    }
+}
+
+/// Integers given as JSON numbers, decimal or `0x` prefixed hex strings. Values above u64 are strings.
+fn number(value: &Value) -> Option<U256> {
+    match value {
+        Value::Number(n) => n.as_u64().map(U256::from),
+        Value::String(s) if s.starts_with("0x") => U256::from_str(&s[2..]).ok(),
+        Value::String(s) => U256::from_dec_str(s).ok(),
+        _ => None,
+    }
+}
+
+/// Numeric fields compare by value, strings case insensitive and regardless of `0x` prefix,
+/// as decoded address arguments have none. None if values can't be compared.
+fn compare(actual: &Value, expected: &Value, numeric: bool) -> Option<Ordering> {
+    if numeric {
+        return Some(number(actual)?.cmp(&number(expected)?));
+    }
+
+    match (actual, expected) {
+        (Value::String(a), Value::String(e)) => {
+            let a = a.trim_start_matches("0x").to_lowercase();
+            let e = e.trim_start_matches("0x").to_lowercase();
+
+            if a == e { Some(Ordering::Equal) } else { None }
+        }
+        (a, e) if a == e => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::*;
+
+    fn rules(value: Value) -> Vec<Rule> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn matches_method_args_and_value() {
+        let mut args = Map::new();
+        args.insert("to".into(), Value::from("7001ea1ca8c28aa90a0d2e8b034aa56319ff0a7e"));
+        args.insert("amount".into(), Value::from(1500));
+
+        let transfer = InputData::new("transfer", args);
+        let set_owner = InputData::new("setOwner", Map::new());
+        let from = H160::from_low_u64_be(1);
+        let value = U256::exp10(18);
+
+        let fields = |input| TrxFields { input, from: &from, to: None, value: &value };
+
+        let large_transfers = rules(json!([
+            { "method": "transfer", "conditions": [{ "field": "args.amount", "op": "gt", "value": 1000 }] },
+            { "method": "setOwner" }
+        ]));
+
+        assert!(accepts(&[], &fields(&transfer)));
+        assert!(accepts(&large_transfers, &fields(&transfer)));
+        assert!(accepts(&large_transfers, &fields(&set_owner)));
+
+        let small_transfers = rules(json!([
+            { "method": "transfer", "conditions": [{ "field": "args.amount", "op": "lte", "value": "1000" }] }
+        ]));
+        assert!(!accepts(&small_transfers, &fields(&transfer)));
+        assert!(!accepts(&small_transfers, &fields(&set_owner)));
+
+        let by_recipient_and_value = rules(json!([
+            { "conditions": [
+                { "field": "args.to", "op": "eq", "value": "0x7001EA1CA8C28AA90A0D2E8B034AA56319FF0A7E" },
+                { "field": "value", "op": "gte", "value": "1000000000000000000" },
+                { "field": "from", "op": "eq", "value": format!("{:#x}", from) },
+            ] }
+        ]));
+        assert!(accepts(&by_recipient_and_value, &fields(&transfer)));
+
+        // missing argument never matches
+        let by_missing = rules(json!([{ "conditions": [{ "field": "args.spender", "op": "ne", "value": 1 }] }]));
+        assert!(!accepts(&by_missing, &fields(&transfer)));
+    }
+
+    #[test]
+    fn validate() {
+        let valid = rules(json!([{ "conditions": [{ "field": "value", "op": "gt", "value": "0x10" }] }]));
+        assert!(valid[0].validate().is_ok());
+
+        let unknown = rules(json!([{ "conditions": [{ "field": "gas", "op": "eq", "value": 1 }] }]));
+        assert!(unknown[0].validate().is_err());
+
+        let not_number = rules(json!([{ "conditions": [{ "field": "args.to", "op": "lt", "value": "abc" }] }]));
+        assert!(not_number[0].validate().is_err());
+
+        let ordered_sender = rules(json!([{ "conditions": [{ "field": "from", "op": "gt", "value": 1 }] }]));
+        assert!(ordered_sender[0].validate().is_err());
+
+        let status = rules(json!([{ "conditions": [{ "field": "status", "op": "eq", "value": 1 }] }]));
+        assert!(status[0].validate().is_err());
+    }
+
+    #[test]
+    fn digit_only_address_is_not_a_number() {
+        let mut args = Map::new();
+        args.insert("to".into(), Value::from("0000000000000000000000000000000000000010"));
+        args.insert("amount".into(), Value::from(16));
+
+        let input = InputData::new("transfer", args);
+        let from = H160::from_low_u64_be(1);
+        let value = U256::zero();
+        let fields = TrxFields { input: &input, from: &from, to: None, value: &value };
+
+        let to_10 = rules(json!([{ "conditions": [{ "field": "args.to", "op": "eq", "value": "10" }] }]));
+        assert!(!accepts(&to_10, &fields));
+
+        let to_address = rules(json!([{ "conditions": [
+            { "field": "args.to", "op": "eq", "value": "0x0000000000000000000000000000000000000010" }
+        ] }]));
+        assert!(accepts(&to_address, &fields));
+
+        let amount_hex = rules(json!([{ "conditions": [{ "field": "args.amount", "op": "eq", "value": "0x10" }] }]));
+        assert!(accepts(&amount_hex, &fields));
+    }
+}
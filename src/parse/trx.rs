@@ -57,10 +57,7 @@ pub fn parse_trx(id_method: &HashMap<String, &ContractFunction>, trx_raw_input:
     let method_id = &trx_raw_input[offset..8];
     offset += 8;
 
-    let function = match id_method.get(&method_id.to_string()) {
-        Some(f) => f,
-        None => return None,
-    };
+    let function = id_method.get(&method_id.to_string())?;
     // .expect(&format!("method expected: {}", method_id));
 
     debug!("Method: {:?}", function);
@@ -90,7 +87,7 @@ fn build_method_sig(function: &ContractFunction) -> Option<String> {
     }
 
     let mut sig = function.name.clone();
-    sig.push_str("(");
+    sig.push('(');
     for i in &function.inputs {
         sig.push_str(&i.r#type.name());
         sig.push(',');
@@ -100,7 +97,7 @@ fn build_method_sig(function: &ContractFunction) -> Option<String> {
         sig.remove(sig.len() - 1);
     }
 
-    sig.push_str(")");
+    sig.push(')');
     Some(sig)
 }
 
@@ -123,14 +120,8 @@ pub fn create_id_method_map(contract: &ContractAbi) -> HashMap<String, &Contract
 
 #[cfg(test)]
 mod tests {
-    use std::collections::hash_map::RandomState;
-    use std::collections::HashMap;
-    use std::str::FromStr;
     use log::info;
-
-    use super::*;
-
-    const BUY_CONTRACT: &str = r###"[{"inputs":[{"internalType":"address","name":"executorAddress","type":"address"},{"internalType":"address","name":"_buyer","type":"address"},{"internalType":"uint256","name":"_amount","type":"uint256"},{"internalType":"uint256","name":"_price","type":"uint256"}],"stateMutability":"nonpayable","type":"constructor"},{"inputs":[],"name":"buy","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[],"name":"getInfo","outputs":[{"internalType":"address","name":"","type":"address"},{"internalType":"uint256","name":"","type":"uint256"},{"internalType":"uint256","name":"","type":"uint256"},{"internalType":"bool","name":"","type":"bool"}],"stateMutability":"view","type":"function"}]"###;
+    use serde_json::json;
 
     const SCRAPER_TESTING_CONTRACT: &str = r#"[{"inputs":[{"internalType":"uint256","name":"_amount","type":"uint256"},{"internalType":"uint256","name":"_price","type":"uint256"}],"stateMutability":"nonpayable","type":"constructor"},{"inputs":[],"name":"getInfo","outputs":[{"internalType":"uint256","name":"","type":"uint256"},{"internalType":"uint256","name":"","type":"uint256"}],"stateMutability":"view","type":"function"},{"inputs":[{"internalType":"uint256","name":"_amount","type":"uint256"}],"name":"newAmount","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[{"internalType":"uint256","name":"_price","type":"uint256"}],"name":"newPrice","outputs":[],"stateMutability":"nonpayable","type":"function"},{"inputs":[{"internalType":"string","name":"userData","type":"string"},{"internalType":"bytes","name":"clientData","type":"bytes"}],"name":"submit","outputs":[],"stateMutability":"nonpayable","type":"function"}] "#;
 
@@ -156,11 +147,9 @@ mod tests {
 
     #[test]
     fn build_method_sig() {
-        crate::error::setup_panic_handler();
-
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
-        let con = crate::parse::contract_abi::create_contract_abi(SCRAPER_TESTING_CONTRACT).unwrap();
+        let con = crate::parse::contract_abi::_create_contract_abi(SCRAPER_TESTING_CONTRACT).unwrap();
 
         let submit = &con.functions[4];
 
@@ -179,11 +168,9 @@ mod tests {
 
     #[test]
     fn parse_trx() {
-        crate::error::setup_panic_handler();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
-
-        let con = crate::parse::contract_abi::create_contract_abi(SCRAPER_TESTING_CONTRACT)
+        let con = crate::parse::contract_abi::_create_contract_abi(SCRAPER_TESTING_CONTRACT)
             .unwrap();
 
         let id_method = super::create_id_method_map(&con);
@@ -194,21 +181,19 @@ mod tests {
         info!("Result: {:?}", input_data);
 
         assert_eq!("submit", input_data.method_name);
-        assert_eq!(format!("{:?}", input_data.args.get("clientData").unwrap()), r#"{"tax":132,"number":"UUID-1234"}"#);
-        assert_eq!(format!("{:?}", input_data.args.get("userData").unwrap()), r#"{"id":132,"name":"Alex"}"#);
+        assert_eq!(input_data.args["clientData"], json!({"tax": 132, "number": "UUID-1234"}));
+        assert_eq!(input_data.args["userData"], json!({"id": 132, "name": "Alex"}));
     }
 
-    const ETH: &str = r###"[{"constant":false,"inputs":[{"name":"newImplementation","type":"address"}],"name":"upgradeTo","outputs":[],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":false,"inputs":[{"name":"newImplementation","type":"address"},{"name":"data","type":"bytes"}],"name":"upgradeToAndCall","outputs":[],"payable":true,"stateMutability":"payable","type":"function"},{"constant":true,"inputs":[],"name":"implementation","outputs":[{"name":"","type":"address"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":false,"inputs":[{"name":"newAdmin","type":"address"}],"name":"changeAdmin","outputs":[],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[],"name":"admin","outputs":[{"name":"","type":"address"}],"payable":false,"stateMutability":"view","type":"function"},{"inputs":[{"name":"_implementation","type":"address"}],"payable":false,"stateMutability":"nonpayable","type":"constructor"},{"payable":true,"stateMutability":"payable","type":"fallback"},{"anonymous":false,"inputs":[{"indexed":false,"name":"previousAdmin","type":"address"},{"indexed":false,"name":"newAdmin","type":"address"}],"name":"AdminChanged","type":"event"},{"anonymous":false,"inputs":[{"indexed":false,"name":"implementation","type":"address"}],"name":"Upgraded","type":"event"}]"###;
+    const ERC20: &str = r###"[{"constant":true,"inputs":[],"name":"name","outputs":[{"name":"","type":"string"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":false,"inputs":[{"name":"spender","type":"address"},{"name":"value","type":"uint256"}],"name":"approve","outputs":[{"name":"","type":"bool"}],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[],"name":"totalSupply","outputs":[{"name":"","type":"uint256"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":false,"inputs":[{"name":"sender","type":"address"},{"name":"recipient","type":"address"},{"name":"amount","type":"uint256"}],"name":"transferFrom","outputs":[{"name":"","type":"bool"}],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[],"name":"decimals","outputs":[{"name":"","type":"uint8"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":false,"inputs":[{"name":"spender","type":"address"},{"name":"addedValue","type":"uint256"}],"name":"increaseAllowance","outputs":[{"name":"","type":"bool"}],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":false,"inputs":[{"name":"value","type":"uint256"}],"name":"burn","outputs":[],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[{"name":"account","type":"address"}],"name":"balanceOf","outputs":[{"name":"","type":"uint256"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":true,"inputs":[],"name":"symbol","outputs":[{"name":"","type":"string"}],"payable":false,"stateMutability":"view","type":"function"},{"constant":false,"inputs":[{"name":"spender","type":"address"},{"name":"subtractedValue","type":"uint256"}],"name":"decreaseAllowance","outputs":[{"name":"","type":"bool"}],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":false,"inputs":[{"name":"recipient","type":"address"},{"name":"amount","type":"uint256"}],"name":"transfer","outputs":[{"name":"","type":"bool"}],"payable":false,"stateMutability":"nonpayable","type":"function"},{"constant":true,"inputs":[{"name":"owner","type":"address"},{"name":"spender","type":"address"}],"name":"allowance","outputs":[{"name":"","type":"uint256"}],"payable":false,"stateMutability":"view","type":"function"},{"inputs":[{"name":"name","type":"string"},{"name":"symbol","type":"string"},{"name":"decimals","type":"uint8"},{"name":"totalSupply","type":"uint256"},{"name":"feeReceiver","type":"address"},{"name":"tokenOwnerAddress","type":"address"}],"payable":true,"stateMutability":"payable","type":"constructor"},{"anonymous":false,"inputs":[{"indexed":true,"name":"from","type":"address"},{"indexed":true,"name":"to","type":"address"},{"indexed":false,"name":"value","type":"uint256"}],"name":"Transfer","type":"event"},{"anonymous":false,"inputs":[{"indexed":true,"name":"owner","type":"address"},{"indexed":true,"name":"spender","type":"address"},{"indexed":false,"name":"value","type":"uint256"}],"name":"Approval","type":"event"}]"###;
 
     const ETH_TRX_INPUT: &str = r###"0xa9059cbb000000000000000000000000c4fbcea825fb3e4f052004df1b5cb9f2e26c791a0000000000000000000000000000000000000000000000000000000246a750c4"###;
 
     #[test]
     fn parse_trx_eth() {
-        crate::error::setup_panic_handler();
-
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
-        let con = crate::parse::contract_abi::create_contract_abi(ETH)
+        let con = crate::parse::contract_abi::_create_contract_abi(ERC20)
             .unwrap();
 
         info!("ABI created: {:?}", con);
@@ -220,8 +205,8 @@ mod tests {
         let input_data = super::parse_trx(&id_method, ETH_TRX_INPUT).unwrap();
         info!("Result: {:?}", input_data);
 
-        // assert_eq!("submit", input_data.method_name);
-        // assert_eq!(format!("{:?}", input_data.args.get("clientData").unwrap()), r#"{"tax":132,"number":"UUID-1234"}"#);
-        // assert_eq!(format!("{:?}", input_data.args.get("userData").unwrap()), r#"{"id":132,"name":"Alex"}"#);
+        assert_eq!("transfer", input_data.method_name);
+        assert_eq!(input_data.args["recipient"], json!("c4fbcea825fb3e4f052004df1b5cb9f2e26c791a"));
+        assert_eq!(input_data.args["amount"], json!(0x246a750c4_i64));
     }
 }
\ No newline at end of file
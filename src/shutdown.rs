@@ -0,0 +1,79 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use log::{info, warn};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Flips to true once. Long running tasks stop taking new work then and finish
+/// the sub-range they work on, so saved progress always matches saved data.
+/// Every task is handed a clone, so tasks of one service never stop ones of another.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+    /// How long tasks are waited for to finish in-flight work.
+    grace: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new(Duration::from_secs(30))
+    }
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        Shutdown {
+            sender: Arc::new(sender),
+            receiver,
+            grace,
+        }
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.grace
+    }
+
+    pub fn request(&self) {
+        if !self.is_requested() {
+            info!("Shutdown requested. Finishing in-flight work");
+            let _ = self.sender.broadcast(true);
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Completes once shutdown is requested.
+    pub async fn requested(&self) {
+        let mut receiver = self.receiver.clone();
+
+        while !*receiver.borrow() {
+            if receiver.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Waits for tasks to stop, at most grace period. Returns false if some of them didn't make it.
+    pub async fn wait(&self, handles: Vec<JoinHandle<()>>) -> bool {
+        match tokio::time::timeout(self.grace, join_all(handles)).await {
+            Ok(results) => {
+                // panic of a task ends that task only, it is reported with backtrace by panic hook
+                for e in results.into_iter().filter_map(|r| r.err()) {
+                    warn!("Task ended abnormally. {:?}", e);
+                }
+                true
+            }
+            Err(_) => {
+                warn!("Tasks didn't stop in {:?}. Their in-flight work is lost", self.grace);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, info};
+use structopt::StructOpt;
+use web3::types::{H160, H2048, H256, U256};
+
+use crate::mongo::{BulkResult, MongoConfig, MongoDB};
+use crate::mongo::config::redact;
+use crate::mongo::migrations::MigrationReport;
+use crate::mongo::model::{self, ChainDataDO, ChainKey, JobKind, JobStatus};
+
+pub(crate) use sql::SqlStorage;
+
+mod sql;
+
+#[derive(Clone, Default, StructOpt)]
+pub struct StorageConfig {
+    /// `postgres://` or `sqlite://` database to use instead of Mongo, e.g. `sqlite://scraper.db?mode=rwc`.
+    /// `sqlite::memory:` keeps everything in memory until exit.
+    #[structopt(long = "storage_url", env = "STORAGE_URL", hide_env_values = true)]
+    pub url: Option<String>,
+}
+
+/// Url might hold password
+impl Debug for StorageConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageConfig")
+            .field("url", &self.url.as_deref().map(redact))
+            .finish()
+    }
+}
+
+/// Everything scraper keeps between runs: contracts, chain data, coverage and jobs.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    /// Creates missing collections or tables with their indexes.
+    async fn init(&self) -> Result<()>;
+
+    /// Migrates stored documents to schema version `to`, the latest if None, rolling back ones ahead of it.
+    /// Documents of single chain versions are given to `chain_id`. Dry run only reports what would change.
+    async fn migrate(&self, to: Option<i32>, dry_run: bool, chain_id: u64) -> Result<Vec<MigrationReport>>;
+
+    async fn get_contracts(&self, chain_id: u64) -> Result<Vec<model::Contract>>;
+
+    async fn get_contract(&self, chain_id: u64, address: &str) -> Result<Option<model::Contract>>;
+
+    async fn save_contract(&self, contract: &model::Contract) -> Result<()>;
+
+    /// Saves scraping progress of contract. Scrapers hold contracts for long,
+    /// so their copy of ABI might be outdated and is never written here.
+    async fn update_contract(&self, contract: &model::Contract) -> Result<()>;
+
+    /// Saves labels, description, paused state and filter rules of contract.
+    async fn update_contract_info(&self, contract: &model::Contract) -> Result<()>;
+
+    async fn update_abi(&self, contract: &model::Contract) -> Result<()>;
+
+    /// Removes contract with its coverage, webhooks and jobs, so uploaded again it starts afresh.
+    /// Running jobs stop after the chunk they work on.
+    async fn delete_contract(&self, contract: &model::Contract) -> Result<()>;
+
+    /// Upserts by id, so saving the same range twice is harmless.
+    async fn save_blocks(&self, blocks: &[model::Block]) -> Result<BulkResult>;
+
+    /// Logs bloom of stored blocks in range by block number.
+    async fn get_blooms(&self, chain_id: u64, range: &Range<u64>) -> Result<BTreeMap<u64, H2048>>;
+
+    /// Upserts by id, so saving the same range twice is harmless.
+    async fn save_transactions(&self, transactions: &[model::Transaction]) -> Result<BulkResult>;
+
+    async fn save_chain_data(&self, chain_data: &ChainDataDO) -> Result<()> {
+        if chain_data.blocks.is_empty() {
+            info!("Nothing to save. Blocks size 0");
+            return Ok(());
+        }
+
+        debug!("Saving: {}", chain_data);
+
+        let (blocks, transactions) = tokio::join!(
+            self.save_blocks(&chain_data.blocks),
+            self.save_transactions(&chain_data.transactions),
+        );
+
+        blocks?;
+        transactions?;
+        Ok(())
+    }
+
+    /// Stored transactions sent to address ordered by hash, starting after `after`.
+    async fn find_trx_to(&self, chain_id: u64, address: &str, after: Option<H256>, limit: u32) -> Result<Vec<model::Transaction>>;
+
+    /// Transactions sent to address mined in block range
+    async fn count_trx_to(&self, chain_id: u64, address: &str, range: &Range<u64>) -> Result<i64>;
+
+    /// Removes stored and pending transactions sent to contract. Blocks are shared by contracts and stay.
+    /// Returns number of stored transactions removed.
+    async fn delete_trx_to(&self, chain_id: u64, address: &str) -> Result<u64>;
+
+    async fn get_watchlist(&self, chain_id: u64) -> Result<Vec<model::WatchedAddress>>;
+
+    async fn get_watched(&self, chain_id: u64, address: &str) -> Result<Option<model::WatchedAddress>>;
+
+    /// Adds address to watchlist or replaces its entry.
+    async fn save_watched(&self, watched: &model::WatchedAddress) -> Result<()>;
+
+    /// Removes address from watchlist with its coverage. Stored transactions stay.
+    async fn delete_watched(&self, watched: &model::WatchedAddress) -> Result<()>;
+
+    async fn get_webhooks(&self, chain_id: Option<u64>, address: Option<&str>) -> Result<Vec<model::Webhook>>;
+
+    async fn save_webhook(&self, webhook: &model::Webhook) -> Result<()>;
+
+    /// False if there is no such webhook. Notifications of webhook go with it.
+    async fn delete_webhook(&self, id: &str) -> Result<bool>;
+
+    async fn save_dead_letter(&self, letter: &model::DeadLetter) -> Result<()>;
+
+    /// Dead letters of webhook or all of them, the latest first.
+    async fn get_dead_letters(&self, webhook_id: Option<&str>) -> Result<Vec<model::DeadLetter>>;
+
+    async fn save_notification(&self, notification: &model::Notification) -> Result<()>;
+
+    /// True if transaction was sent to webhook before, see `model::Notification::key`.
+    async fn is_notified(&self, id: &str) -> Result<bool>;
+
+    /// Coverage by id. Empty one if nothing is scanned yet.
+    async fn find_coverage(&self, id: String, chain_id: u64, address: &str) -> Result<model::Coverage>;
+
+    async fn get_coverage(&self, chain_id: u64, address: &str) -> Result<model::Coverage> {
+        self.find_coverage(model::Contract::key(chain_id, address), chain_id, address).await
+    }
+
+    async fn get_watch_coverage(&self, chain_id: u64, address: &str) -> Result<model::Coverage> {
+        self.find_coverage(model::WatchedAddress::key(chain_id, address), chain_id, address).await
+    }
+
+    /// Saved as ranges get scanned. Traversal resumes from coverage, there are no separate checkpoints.
+    async fn save_coverage(&self, coverage: &model::Coverage) -> Result<()>;
+
+    /// Queues job, replacing a finished one of the same id. Returns false if the job with the same id is still unfinished.
+    async fn enqueue_job(&self, job: &model::Job) -> Result<bool>;
+
+    /// Takes queued job of chain with the highest priority and marks it running.
+    /// Jobs of `exclude` kinds are left in queue.
+    async fn next_job(&self, chain_id: u64, exclude: &[JobKind]) -> Result<Option<model::Job>>;
+
+    async fn get_job(&self, id: &str) -> Result<Option<model::Job>>;
+
+    /// Jobs matching all of given criteria, the newest first.
+    async fn get_jobs(&self, chain_id: Option<u64>, address: Option<&str>, status: Option<JobStatus>) -> Result<Vec<model::Job>>;
+
+    /// Returns number of jobs queued again.
+    async fn requeue_running_jobs(&self, chain_id: u64) -> Result<u64>;
+
+    /// Saves job while it is still running. Returns false if it was paused or cancelled meanwhile.
+    async fn update_running_job(&self, job: &model::Job) -> Result<bool>;
+
+    /// Moves job to `to` status if it is in one of `from`. Returns false otherwise.
+    async fn set_job_status(&self, id: &str, from: &[JobStatus], to: JobStatus) -> Result<bool>;
+
+    /// Saves transaction seen in mempool. Already known ones are ignored.
+    async fn save_pending(&self, trx: &model::PendingTransaction) -> Result<()>;
+
+    /// Transactions of chain still in mempool.
+    async fn get_pending(&self, chain_id: u64) -> Result<Vec<model::PendingTransaction>>;
+
+    async fn update_pending(&self, trx: &model::PendingTransaction) -> Result<()>;
+
+    /// Marks pending transactions having the same sender and nonce as the mined one as replaced.
+    /// Returns number of them.
+    async fn replace_pending(&self, mined: &ChainKey<H256>, from: &H160, nonce: &U256) -> Result<u64>;
+}
+
+/// Storage by url, ready to use. Mongo configured by `mongo` is used if url is omitted.
+pub async fn connect(config: &StorageConfig, mongo: &MongoConfig) -> Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match config.url.as_deref() {
+        None => Arc::new(MongoDB::connect(mongo).await?),
+        Some(u) if u.starts_with("postgres://") || u.starts_with("postgresql://") || u.starts_with("sqlite:") => {
+            Arc::new(SqlStorage::connect(u).await?)
+        }
+        Some(u) => bail!("Unsupported storage: {}", redact(u)),
+    };
+
+    storage.init().await?;
+
+    Ok(storage)
+}
@@ -0,0 +1,1097 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Any, Row};
+use sqlx::any::{AnyArguments, AnyPool, AnyPoolOptions, AnyRow};
+use sqlx::query::Query;
+use web3::types::{H160, H2048, H256, U256};
+
+use crate::mongo::BulkResult;
+use crate::mongo::migrations::MigrationReport;
+use crate::mongo::model::{self, ChainKey, JobKind, JobStatus, num, PendingStatus};
+use crate::storage::Storage;
+
+/// Connections to Postgres. SQLite gets a single one, see `SqlStorage::connect`.
+const POOL_SIZE: u32 = 10;
+/// Rows per upsert transaction
+const UPSERT_CHUNK: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Text,
+}
+
+impl Kind {
+    fn sql(self) -> &'static str {
+        match self {
+            Kind::Int => "BIGINT",
+            Kind::Text => "TEXT",
+        }
+    }
+}
+
+/// Column copied out of document for queries to filter and sort by.
+struct Column {
+    name: &'static str,
+    /// Document field
+    field: &'static str,
+    kind: Kind,
+}
+
+const fn int(name: &'static str, field: &'static str) -> Column {
+    Column { name, field, kind: Kind::Int }
+}
+
+const fn text(name: &'static str, field: &'static str) -> Column {
+    Column { name, field, kind: Kind::Text }
+}
+
+/// Documents are stored as JSON in `doc` column keyed by their `_id`, so models are shared with Mongo.
+struct Table {
+    name: &'static str,
+    columns: &'static [Column],
+    /// Index name and its columns
+    indexes: &'static [(&'static str, &'static str)],
+}
+
+impl Table {
+    fn create_sql(&self) -> String {
+        let columns: String = self.columns.iter()
+            .map(|c| format!("{} {}, ", c.name, c.kind.sql()))
+            .collect();
+
+        format!("CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY, {}doc TEXT NOT NULL)", self.name, columns)
+    }
+
+    /// Index names are global in Postgres, so they are prefixed with table name.
+    fn index_sql(&self) -> Vec<String> {
+        self.indexes.iter()
+            .map(|(name, columns)| format!("CREATE INDEX IF NOT EXISTS {}_{} ON {} ({})", self.name, name, self.name, columns))
+            .collect()
+    }
+
+    /// Statements dropping indexes on column, as SQLite can't drop an indexed column.
+    fn drop_index_sql(&self, column: &str) -> Vec<String> {
+        self.indexes.iter()
+            .filter(|(_, columns)| columns.split(',').any(|c| c.trim() == column))
+            .map(|(name, _)| format!("DROP INDEX IF EXISTS {}_{}", self.name, name))
+            .collect()
+    }
+
+    /// Insert of a row, replacing the one of the same id if `replace`, ignored otherwise.
+    fn insert_sql(&self, replace: bool) -> String {
+        let mut columns = vec!["id"];
+        columns.extend(self.columns.iter().map(|c| c.name));
+        columns.push("doc");
+
+        let on_conflict = if replace {
+            let set: Vec<_> = columns[1..].iter().map(|c| format!("{} = excluded.{}", c, c)).collect();
+            format!("DO UPDATE SET {}", set.join(", "))
+        } else {
+            "DO NOTHING".to_string()
+        };
+
+        format!("INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (id) {}",
+                self.name, columns.join(", "), vec!["?"; columns.len()].join(", "), on_conflict)
+    }
+
+    /// Params of `insert_sql`
+    fn row<T: Serialize>(&self, item: &T) -> Result<Vec<Param>> {
+        let doc = serde_json::to_value(item)?;
+
+        let id = match doc.get("_id") {
+            Some(id) => key(id),
+            None => bail!("Document without _id: {}", doc),
+        };
+
+        let mut params = vec![Param::Text(id)];
+        params.extend(self.columns.iter().map(|c| Param::of(&doc[c.field])));
+        params.push(Param::Text(doc.to_string()));
+
+        Ok(params)
+    }
+}
+
+const CONTRACTS: Table = Table {
+    name: model::Contract::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), text("address", "address")],
+    indexes: &[("chain", "chain_id")],
+};
+
+const WATCHLIST: Table = Table {
+    name: model::WatchedAddress::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id")],
+    indexes: &[("chain", "chain_id")],
+};
+
+const BLOCKS: Table = Table {
+    name: model::Block::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), int("number", "number")],
+    indexes: &[("chain_number", "chain_id, number")],
+};
+
+const TRANSACTIONS: Table = Table {
+    name: model::Transaction::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), text("to_address", "to"), text("hash", "hash"), int("block_number", "blockNumber")],
+    indexes: &[("chain_to_hash", "chain_id, to_address, hash")],
+};
+
+const PENDING: Table = Table {
+    name: model::PendingTransaction::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), text("to_address", "to"), text("from_address", "from"),
+        int("nonce", "nonce"), text("status", "status")],
+    indexes: &[("chain_status", "chain_id, status"), ("chain_from_nonce", "chain_id, from_address, nonce")],
+};
+
+const COVERAGE: Table = Table {
+    name: model::Coverage::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id")],
+    indexes: &[],
+};
+
+const JOBS: Table = Table {
+    name: model::Job::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), text("address", "address"), text("kind", "kind"), text("status", "status"),
+        int("priority", "priority"), int("created_at", "created_at")],
+    indexes: &[("chain_status_priority", "chain_id, status, priority, created_at"), ("chain_address", "chain_id, address")],
+};
+
+const WEBHOOKS: Table = Table {
+    name: model::Webhook::COLLECTION_NAME,
+    columns: &[int("chain_id", "chain_id"), text("address", "address")],
+    indexes: &[("chain_address", "chain_id, address")],
+};
+
+const DEAD_LETTERS: Table = Table {
+    name: model::DeadLetter::COLLECTION_NAME,
+    columns: &[text("webhook_id", "webhook_id"), int("created_at", "created_at")],
+    indexes: &[("webhook_created", "webhook_id, created_at")],
+};
+
+const NOTIFICATIONS: Table = Table {
+    name: model::Notification::COLLECTION_NAME,
+    columns: &[text("webhook_id", "webhook_id")],
+    indexes: &[("webhook", "webhook_id")],
+};
+
+const TABLES: &[&Table] = &[&CONTRACTS, &WATCHLIST, &BLOCKS, &TRANSACTIONS, &PENDING, &COVERAGE, &JOBS, &WEBHOOKS, &DEAD_LETTERS,
+    &NOTIFICATIONS];
+
+/// Row id of document `_id`. Chain keys are stored as their JSON.
+fn key(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        id => id.to_string(),
+    }
+}
+
+/// Numbers `?` placeholders as `$1, $2...`. Postgres requires numbered ones,
+/// SQLite numbers `$N` by order of appearance, so they must go in order.
+/// Null params are written in place, as `Any` driver skips bound `None` and the rest would shift.
+fn number(sql: &str, params: &[Param]) -> String {
+    let mut params = params.iter();
+    let mut n = 0;
+
+    sql.chars()
+        .map(|c| match c {
+            '?' => match params.next() {
+                Some(Param::Null) => "NULL".to_string(),
+                _ => {
+                    n += 1;
+                    format!("${}", n)
+                }
+            },
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Param {
+    Int(i64),
+    Text(String),
+    Null,
+}
+
+impl Param {
+    /// Column value of document field
+    fn of(value: &Value) -> Param {
+        match value {
+            Value::Null => Param::Null,
+            Value::Bool(b) => Param::Int(*b as i64),
+            Value::Number(n) => n.as_i64().map_or_else(|| Param::Text(n.to_string()), Param::Int),
+            Value::String(s) => Param::Text(s.clone()),
+            v => Param::Text(v.to_string()),
+        }
+    }
+
+    /// Value as it is stored in document, e.g. status or hash
+    fn serialized<T: Serialize>(value: &T) -> Result<Param> {
+        Ok(Param::of(&serde_json::to_value(value)?))
+    }
+}
+
+/// Binds params of SQL numbered with `number`
+fn bind_all<'q>(sql: &'q str, params: &[Param]) -> Query<'q, Any, AnyArguments<'q>> {
+    params.iter().fold(sqlx::query(sql), |q, p| match p {
+        Param::Int(n) => q.bind(*n),
+        Param::Text(s) => q.bind(s.clone()),
+        Param::Null => q,
+    })
+}
+
+/// WHERE clause with its params
+#[derive(Debug, Default)]
+struct Filter {
+    clauses: Vec<String>,
+    params: Vec<Param>,
+}
+
+impl Filter {
+    fn new() -> Self {
+        Filter::default()
+    }
+
+    fn cmp(mut self, column: &str, op: &str, value: Param) -> Self {
+        self.clauses.push(format!("{} {} ?", column, op));
+        self.params.push(value);
+        self
+    }
+
+    fn eq(self, column: &str, value: Param) -> Self {
+        self.cmp(column, "=", value)
+    }
+
+    fn eq_opt(self, column: &str, value: Option<Param>) -> Self {
+        match value {
+            Some(value) => self.eq(column, value),
+            None => self,
+        }
+    }
+
+    fn one_of(mut self, column: &str, values: Vec<Param>, negate: bool) -> Self {
+        match (values.is_empty(), negate) {
+            (true, true) => (),
+            (true, false) => self.clauses.push("1 = 0".to_string()),
+            _ => {
+                self.clauses.push(format!("{} {}IN ({})", column, if negate { "NOT " } else { "" }, vec!["?"; values.len()].join(", ")));
+                self.params.extend(values);
+            }
+        }
+        self
+    }
+
+    fn sql(&self) -> String {
+        match self.clauses.is_empty() {
+            true => String::new(),
+            false => format!(" WHERE {}", self.clauses.join(" AND ")),
+        }
+    }
+}
+
+fn docs(rows: Vec<AnyRow>) -> Result<Vec<String>> {
+    rows.iter()
+        .map(|r| Ok(r.try_get::<String, _>("doc")?))
+        .collect()
+}
+
+fn parse<T: DeserializeOwned>(docs: Vec<String>) -> Result<Vec<T>> {
+    docs.iter()
+        .map(|d| Ok(serde_json::from_str(d)?))
+        .collect()
+}
+
+fn chain(chain_id: u64) -> Param {
+    Param::Int(chain_id as i64)
+}
+
+fn addr(address: &str) -> Param {
+    Param::Text(address.to_lowercase())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+/// Storage in PostgreSQL or embedded SQLite database. Both are spoken to in the SQL they share.
+#[derive(Debug)]
+pub struct SqlStorage {
+    pool: AnyPool,
+    dialect: Dialect,
+}
+
+impl SqlStorage {
+    /// SQLite gets a single connection that never expires: writes are serialized anyway
+    /// and `sqlite::memory:` database lives as long as its connection.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let dialect = match url.starts_with("sqlite:") {
+            true => Dialect::Sqlite,
+            false => Dialect::Postgres,
+        };
+
+        let options = match dialect {
+            Dialect::Postgres => AnyPoolOptions::new().max_connections(POOL_SIZE),
+            Dialect::Sqlite => AnyPoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None),
+        };
+
+        debug!("Connecting to {:?} storage", dialect);
+
+        // url isn't printed, it might hold password
+        let pool = options.connect(url).await
+            .with_context(|| format!("Can't connect to {:?} storage", dialect))?;
+
+        Ok(SqlStorage {
+            pool,
+            dialect,
+        })
+    }
+
+    /// Row lock of read-modify-write. SQLite has a single connection, so there is nobody to lock out.
+    fn lock(&self) -> &'static str {
+        match self.dialect {
+            Dialect::Postgres => " FOR UPDATE",
+            Dialect::Sqlite => "",
+        }
+    }
+
+    /// Names and types of columns table has in database.
+    async fn existing_columns(&self, table: &Table) -> Result<Vec<(String, String)>> {
+        let sql = match self.dialect {
+            Dialect::Postgres => "SELECT column_name::TEXT AS name, data_type::TEXT AS type FROM information_schema.columns \
+                WHERE table_schema = current_schema() AND table_name = $1",
+            Dialect::Sqlite => "SELECT name, type FROM pragma_table_info($1)",
+        };
+
+        sqlx::query(sql).bind(table.name).fetch_all(&self.pool).await?
+            .iter()
+            .map(|r| Ok((r.try_get::<String, _>("name")?, r.try_get::<String, _>("type")?)))
+            .collect()
+    }
+
+    /// Brings columns of table created by an earlier version up to date: adds missing ones and recreates ones of
+    /// another type. `CREATE TABLE IF NOT EXISTS` leaves existing tables as they are.
+    async fn upgrade(&self, table: &Table) -> Result<()> {
+        let existing = self.existing_columns(table).await?;
+        let mut changed = false;
+
+        for column in table.columns {
+            match existing.iter().find(|(name, _)| name == column.name) {
+                Some((_, kind)) if kind.eq_ignore_ascii_case(column.kind.sql()) => continue,
+                Some((_, kind)) => {
+                    info!("Changing column {}.{} from {} to {}", table.name, column.name, kind, column.kind.sql());
+
+                    for sql in table.drop_index_sql(column.name) {
+                        sqlx::query(&sql).execute(&self.pool).await?;
+                    }
+
+                    sqlx::query(&format!("ALTER TABLE {} DROP COLUMN {}", table.name, column.name)).execute(&self.pool).await?;
+                }
+                None => info!("Adding column {}.{}", table.name, column.name),
+            }
+
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table.name, column.name, column.kind.sql())).execute(&self.pool).await?;
+            changed = true;
+        }
+
+        if changed {
+            self.refill(table).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies columns out of documents again, a chunk of rows at a time.
+    async fn refill(&self, table: &Table) -> Result<()> {
+        let mut after = None;
+        let mut count = 0;
+
+        loop {
+            let filter = match after {
+                Some(id) => Filter::new().cmp("id", ">", Param::Text(id)),
+                None => Filter::new(),
+            };
+
+            let docs: Vec<Value> = self.find(table, filter, &format!(" ORDER BY id LIMIT {}", UPSERT_CHUNK)).await?;
+
+            after = match docs.last() {
+                Some(doc) => Some(key(&doc["_id"])),
+                None => break,
+            };
+
+            self.put_many(table, &docs).await?;
+            count += docs.len();
+        }
+
+        info!("Refilled columns of {} rows in {}", count, table.name);
+
+        Ok(())
+    }
+
+    /// Documents matching filter. `tail` is ORDER BY and LIMIT clauses.
+    async fn find<T: DeserializeOwned>(&self, table: &Table, filter: Filter, tail: &str) -> Result<Vec<T>> {
+        let sql = number(&format!("SELECT doc FROM {}{}{}", table.name, filter.sql(), tail), &filter.params);
+        let rows = bind_all(&sql, &filter.params).fetch_all(&self.pool).await?;
+
+        parse(docs(rows)?)
+    }
+
+    async fn find_one<T: DeserializeOwned>(&self, table: &Table, id: &str) -> Result<Option<T>> {
+        Ok(self.find(table, Filter::new().eq("id", Param::Text(id.to_string())), " LIMIT 1").await?.pop())
+    }
+
+    async fn count(&self, table: &Table, filter: Filter) -> Result<i64> {
+        let sql = number(&format!("SELECT COUNT(*) AS n FROM {}{}", table.name, filter.sql()), &filter.params);
+        let row = bind_all(&sql, &filter.params).fetch_one(&self.pool).await?;
+
+        Ok(row.try_get::<i64, _>("n")?)
+    }
+
+    async fn delete(&self, table: &Table, filter: Filter) -> Result<u64> {
+        let sql = number(&format!("DELETE FROM {}{}", table.name, filter.sql()), &filter.params);
+
+        Ok(bind_all(&sql, &filter.params).execute(&self.pool).await?.rows_affected())
+    }
+
+    /// Inserts document or replaces the one of the same id if `replace`. Returns false if nothing is written.
+    async fn put<T: Serialize>(&self, table: &Table, item: &T, replace: bool) -> Result<bool> {
+        let params = table.row(item)?;
+        let sql = number(&table.insert_sql(replace), &params);
+
+        Ok(bind_all(&sql, &params).execute(&self.pool).await?.rows_affected() > 0)
+    }
+
+    /// Upserts documents by chunks, each in a transaction. Ids present before are counted as updated.
+    async fn put_many<T: Serialize>(&self, table: &Table, items: &[T]) -> Result<BulkResult> {
+        let sql = table.insert_sql(true);
+        let mut res = BulkResult::default();
+
+        for chunk in items.chunks(UPSERT_CHUNK) {
+            let rows = chunk.iter()
+                .map(|i| table.row(i))
+                .collect::<Result<Vec<_>>>()?;
+
+            let ids = rows.iter().map(|r| r[0].clone()).collect();
+            let existing = Filter::new().one_of("id", ids, false);
+            let existing_sql = number(&format!("SELECT COUNT(*) AS n FROM {}{}", table.name, existing.sql()), &existing.params);
+
+            let mut tx = self.pool.begin().await?;
+
+            let updated = bind_all(&existing_sql, &existing.params).fetch_one(&mut tx).await?
+                .try_get::<i64, _>("n")? as u64;
+
+            for row in rows.iter() {
+                bind_all(&number(&sql, row), row).execute(&mut tx).await?;
+            }
+
+            tx.commit().await?;
+
+            res.updated += updated;
+            res.inserted += rows.len() as u64 - updated;
+        }
+
+        debug!("Saved to {}: {:?}", table.name, res);
+
+        Ok(res)
+    }
+
+    /// Applies `change` to documents matching filter in a transaction. Returns number of them.
+    async fn modify<T, F>(&self, table: &Table, filter: Filter, tail: &str, mut change: F) -> Result<u64>
+        where
+            T: Serialize + DeserializeOwned + Send,
+            F: FnMut(&mut T) + Send
+    {
+        let select = number(&format!("SELECT doc FROM {}{}{}{}", table.name, filter.sql(), tail, self.lock()), &filter.params);
+        let insert = table.insert_sql(true);
+
+        let mut tx = self.pool.begin().await?;
+
+        let docs = docs(bind_all(&select, &filter.params).fetch_all(&mut tx).await?)?;
+
+        for doc in docs.iter() {
+            let mut item: T = serde_json::from_str(doc)?;
+            change(&mut item);
+
+            let row = table.row(&item)?;
+            bind_all(&number(&insert, &row), &row).execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(docs.len() as u64)
+    }
+}
+
+#[async_trait]
+impl Storage for SqlStorage {
+    async fn init(&self) -> Result<()> {
+        for table in TABLES {
+            debug!("Checking table: {}", table.name);
+
+            sqlx::query(&table.create_sql()).execute(&self.pool).await?;
+            self.upgrade(table).await?;
+
+            for index in table.index_sql() {
+                sqlx::query(&index).execute(&self.pool).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Columns are brought up to date by `init`, documents have no migrations yet.
+    async fn migrate(&self, to: Option<i32>, _dry_run: bool, _chain_id: u64) -> Result<Vec<MigrationReport>> {
+        if let Some(to) = to {
+            bail!("SQL storage has no schema versions to migrate to {}", to);
+        }
+        Ok(vec![])
+    }
+
+    async fn get_contracts(&self, chain_id: u64) -> Result<Vec<model::Contract>> {
+        let result: Vec<model::Contract> = self.find(&CONTRACTS, Filter::new().eq("chain_id", chain(chain_id)), "").await?;
+
+        debug!("Found {} contracts", result.len());
+
+        Ok(result)
+    }
+
+    async fn get_contract(&self, chain_id: u64, address: &str) -> Result<Option<model::Contract>> {
+        self.find_one(&CONTRACTS, &model::Contract::key(chain_id, address)).await
+    }
+
+    async fn save_contract(&self, contract: &model::Contract) -> Result<()> {
+        debug!("Saving contract {:?}", contract);
+
+        if !self.put(&CONTRACTS, contract, false).await? {
+            bail!("Contract {} exists", contract.id);
+        }
+        Ok(())
+    }
+
+    async fn update_contract(&self, contract: &model::Contract) -> Result<()> {
+        debug!("Updating contract {} range: {:?}", contract.id, contract.processed_range);
+
+        self.modify(&CONTRACTS, Filter::new().eq("id", Param::Text(contract.id.clone())), "", |c: &mut model::Contract| {
+            c.processed_range = contract.processed_range.clone();
+            c.backfilling = contract.backfilling;
+        }).await?;
+        Ok(())
+    }
+
+    async fn update_contract_info(&self, contract: &model::Contract) -> Result<()> {
+        debug!("Updating contract {} info", contract.id);
+
+        self.modify(&CONTRACTS, Filter::new().eq("id", Param::Text(contract.id.clone())), "", |c: &mut model::Contract| {
+            c.labels = contract.labels.clone();
+            c.description = contract.description.clone();
+            c.paused = contract.paused;
+            c.rules = contract.rules.clone();
+        }).await?;
+        Ok(())
+    }
+
+    async fn update_abi(&self, contract: &model::Contract) -> Result<()> {
+        debug!("Updating contract {} ABI to version {}", contract.id, contract.abi_version());
+
+        self.modify(&CONTRACTS, Filter::new().eq("id", Param::Text(contract.id.clone())), "", |c: &mut model::Contract| {
+            c.abi_json = contract.abi_json.clone();
+            c.abi_history = contract.abi_history.clone();
+        }).await?;
+        Ok(())
+    }
+
+    async fn delete_contract(&self, contract: &model::Contract) -> Result<()> {
+        self.delete(&JOBS, Filter::new()
+            .eq("chain_id", Param::Int(contract.chain_id))
+            .eq("address", addr(&contract.address))).await?;
+
+        self.delete(&COVERAGE, Filter::new().eq("id", Param::Text(contract.id.clone()))).await?;
+
+        self.delete(&WEBHOOKS, Filter::new()
+            .eq("chain_id", Param::Int(contract.chain_id))
+            .eq("address", addr(&contract.address))).await?;
+
+        debug!("Deleting contract {}", contract.id);
+
+        self.delete(&CONTRACTS, Filter::new().eq("id", Param::Text(contract.id.clone()))).await?;
+        Ok(())
+    }
+
+    async fn save_blocks(&self, blocks: &[model::Block]) -> Result<BulkResult> {
+        debug!("Saving {} blocks", blocks.len());
+        self.put_many(&BLOCKS, blocks).await
+    }
+
+    async fn get_blooms(&self, chain_id: u64, range: &Range<u64>) -> Result<BTreeMap<u64, H2048>> {
+        let filter = Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .cmp("number", ">=", Param::Int(range.start as i64))
+            .cmp("number", "<", Param::Int(range.end as i64));
+        let blocks: Vec<model::Block> = self.find(&BLOCKS, filter, "").await?;
+
+        Ok(blocks.into_iter()
+            .filter_map(|b| Some((b.number?.as_u64(), b.logs_bloom?)))
+            .collect())
+    }
+
+    async fn save_transactions(&self, transactions: &[model::Transaction]) -> Result<BulkResult> {
+        debug!("Saving {} trx", transactions.len());
+        self.put_many(&TRANSACTIONS, transactions).await
+    }
+
+    async fn find_trx_to(&self, chain_id: u64, address: &str, after: Option<H256>, limit: u32) -> Result<Vec<model::Transaction>> {
+        let mut filter = Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("to_address", addr(address));
+
+        if let Some(after) = after {
+            filter = filter.cmp("hash", ">", Param::serialized(&after)?);
+        }
+
+        self.find(&TRANSACTIONS, filter, &format!(" ORDER BY hash LIMIT {}", limit)).await
+    }
+
+    async fn count_trx_to(&self, chain_id: u64, address: &str, range: &Range<u64>) -> Result<i64> {
+        self.count(&TRANSACTIONS, Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("to_address", addr(address))
+            .cmp("block_number", ">=", Param::Int(range.start as i64))
+            .cmp("block_number", "<", Param::Int(range.end as i64))).await
+    }
+
+    async fn delete_trx_to(&self, chain_id: u64, address: &str) -> Result<u64> {
+        let filter = || Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("to_address", addr(address));
+
+        self.delete(&PENDING, filter()).await?;
+        self.delete(&TRANSACTIONS, filter()).await
+    }
+
+    async fn get_watchlist(&self, chain_id: u64) -> Result<Vec<model::WatchedAddress>> {
+        self.find(&WATCHLIST, Filter::new().eq("chain_id", chain(chain_id)), "").await
+    }
+
+    async fn get_watched(&self, chain_id: u64, address: &str) -> Result<Option<model::WatchedAddress>> {
+        self.find_one(&WATCHLIST, &model::WatchedAddress::key(chain_id, address)).await
+    }
+
+    async fn save_watched(&self, watched: &model::WatchedAddress) -> Result<()> {
+        debug!("Saving watched address {:?}", watched);
+
+        self.put(&WATCHLIST, watched, true).await?;
+        Ok(())
+    }
+
+    async fn delete_watched(&self, watched: &model::WatchedAddress) -> Result<()> {
+        self.delete(&COVERAGE, Filter::new().eq("id", Param::Text(watched.id.clone()))).await?;
+
+        debug!("Deleting watched address {}", watched.id);
+
+        self.delete(&WATCHLIST, Filter::new().eq("id", Param::Text(watched.id.clone()))).await?;
+        Ok(())
+    }
+
+    async fn get_webhooks(&self, chain_id: Option<u64>, address: Option<&str>) -> Result<Vec<model::Webhook>> {
+        self.find(&WEBHOOKS, Filter::new()
+            .eq_opt("chain_id", chain_id.map(chain))
+            .eq_opt("address", address.map(addr)), "").await
+    }
+
+    async fn save_webhook(&self, webhook: &model::Webhook) -> Result<()> {
+        if !self.put(&WEBHOOKS, webhook, false).await? {
+            bail!("Webhook {} exists", webhook.id);
+        }
+        Ok(())
+    }
+
+    async fn delete_webhook(&self, id: &str) -> Result<bool> {
+        self.delete(&NOTIFICATIONS, Filter::new().eq("webhook_id", Param::Text(id.to_string()))).await?;
+        Ok(self.delete(&WEBHOOKS, Filter::new().eq("id", Param::Text(id.to_string()))).await? > 0)
+    }
+
+    async fn save_dead_letter(&self, letter: &model::DeadLetter) -> Result<()> {
+        self.put(&DEAD_LETTERS, letter, true).await?;
+        Ok(())
+    }
+
+    async fn get_dead_letters(&self, webhook_id: Option<&str>) -> Result<Vec<model::DeadLetter>> {
+        self.find(&DEAD_LETTERS, Filter::new()
+            .eq_opt("webhook_id", webhook_id.map(|id| Param::Text(id.to_string()))), " ORDER BY created_at DESC").await
+    }
+
+    async fn save_notification(&self, notification: &model::Notification) -> Result<()> {
+        self.put(&NOTIFICATIONS, notification, true).await?;
+        Ok(())
+    }
+
+    async fn is_notified(&self, id: &str) -> Result<bool> {
+        Ok(self.find_one::<model::Notification>(&NOTIFICATIONS, id).await?.is_some())
+    }
+
+    async fn find_coverage(&self, id: String, chain_id: u64, address: &str) -> Result<model::Coverage> {
+        match self.find_one(&COVERAGE, &id).await? {
+            Some(coverage) => Ok(coverage),
+            None => Ok(model::Coverage::with_id(id, chain_id, address)),
+        }
+    }
+
+    async fn save_coverage(&self, coverage: &model::Coverage) -> Result<()> {
+        self.put(&COVERAGE, coverage, true).await?;
+        Ok(())
+    }
+
+    async fn enqueue_job(&self, job: &model::Job) -> Result<bool> {
+        let finished = JobStatus::FINISHED.iter()
+            .map(Param::serialized)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.delete(&JOBS, Filter::new()
+            .eq("id", Param::Text(job.id.clone()))
+            .one_of("status", finished, false)).await?;
+
+        self.put(&JOBS, job, false).await
+    }
+
+    async fn next_job(&self, chain_id: u64, exclude: &[JobKind]) -> Result<Option<model::Job>> {
+        let exclude = exclude.iter()
+            .map(Param::serialized)
+            .collect::<Result<Vec<_>>>()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut taken = None;
+
+        self.modify(&JOBS, Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("status", Param::serialized(&JobStatus::Queued)?)
+            .one_of("kind", exclude, true), " ORDER BY priority DESC, created_at LIMIT 1", |j: &mut model::Job| {
+            j.status = JobStatus::Running;
+            j.updated_at = now;
+            taken = Some(j.clone());
+        }).await?;
+
+        Ok(taken)
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<model::Job>> {
+        self.find_one(&JOBS, id).await
+    }
+
+    async fn get_jobs(&self, chain_id: Option<u64>, address: Option<&str>, status: Option<JobStatus>) -> Result<Vec<model::Job>> {
+        self.find(&JOBS, Filter::new()
+            .eq_opt("chain_id", chain_id.map(chain))
+            .eq_opt("address", address.map(addr))
+            .eq_opt("status", status.as_ref().map(Param::serialized).transpose()?), " ORDER BY created_at DESC").await
+    }
+
+    async fn requeue_running_jobs(&self, chain_id: u64) -> Result<u64> {
+        self.modify(&JOBS, Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("status", Param::serialized(&JobStatus::Running)?), "", |j: &mut model::Job| {
+            j.status = JobStatus::Queued;
+        }).await
+    }
+
+    async fn update_running_job(&self, job: &model::Job) -> Result<bool> {
+        let updated = self.modify(&JOBS, Filter::new()
+            .eq("id", Param::Text(job.id.clone()))
+            .eq("status", Param::serialized(&JobStatus::Running)?), "", |j: &mut model::Job| {
+            *j = job.clone();
+        }).await?;
+
+        Ok(updated == 1)
+    }
+
+    async fn set_job_status(&self, id: &str, from: &[JobStatus], to: JobStatus) -> Result<bool> {
+        let from = from.iter()
+            .map(Param::serialized)
+            .collect::<Result<Vec<_>>>()?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let updated = self.modify(&JOBS, Filter::new()
+            .eq("id", Param::Text(id.to_string()))
+            .one_of("status", from, false), "", |j: &mut model::Job| {
+            j.status = to;
+            j.updated_at = now;
+        }).await?;
+
+        Ok(updated == 1)
+    }
+
+    async fn save_pending(&self, trx: &model::PendingTransaction) -> Result<()> {
+        if !self.put(&PENDING, trx, false).await? {
+            debug!("Pending trx {:?} already saved", trx.hash);
+        }
+        Ok(())
+    }
+
+    async fn get_pending(&self, chain_id: u64) -> Result<Vec<model::PendingTransaction>> {
+        self.find(&PENDING, Filter::new()
+            .eq("chain_id", chain(chain_id))
+            .eq("status", Param::serialized(&PendingStatus::Pending)?), "").await
+    }
+
+    async fn update_pending(&self, trx: &model::PendingTransaction) -> Result<()> {
+        self.put(&PENDING, trx, true).await?;
+        Ok(())
+    }
+
+    async fn replace_pending(&self, mined: &ChainKey<H256>, from: &H160, nonce: &U256) -> Result<u64> {
+        let mined_id = key(&serde_json::to_value(mined)?);
+        let replaced_by = mined.key;
+
+        self.modify(&PENDING, Filter::new()
+            .eq("chain_id", Param::Int(mined.chain_id))
+            .eq("from_address", Param::serialized(from)?)
+            .eq("nonce", Param::Int(num::to_i64(nonce)?))
+            .eq("status", Param::serialized(&PendingStatus::Pending)?)
+            .cmp("id", "<>", Param::Text(mined_id)), "", |p: &mut model::PendingTransaction| {
+            p.status = PendingStatus::Replaced;
+            p.replaced_by = Some(replaced_by);
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Bytes, Index};
+
+    use crate::mongo::model::{Contract, Job, Transaction};
+
+    use super::*;
+
+    async fn memory() -> Result<SqlStorage> {
+        let storage = SqlStorage::connect("sqlite::memory:").await?;
+        storage.init().await?;
+        Ok(storage)
+    }
+
+    fn trx(n: u64, to: H160) -> Transaction {
+        Transaction::new(1, &web3::types::Transaction {
+            hash: H256::from_low_u64_be(n),
+            nonce: U256::zero(),
+            block_hash: Some(H256::from_low_u64_be(1000 + n)),
+            block_number: Some(n.into()),
+            transaction_index: Some(Index::from(0)),
+            from: H160::from_low_u64_be(1),
+            to: Some(to),
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas: U256::zero(),
+            input: Bytes(vec![]),
+            raw: None,
+        }, U256::zero())
+    }
+
+    #[test]
+    fn numbers_placeholders() {
+        let filter = Filter::new()
+            .eq("chain_id", Param::Int(1))
+            .one_of("status", vec![Param::Text("Queued".to_string()), Param::Text("Running".to_string())], true)
+            .one_of("kind", vec![], true);
+
+        assert_eq!(number(&format!("SELECT doc FROM jobs{}", filter.sql()), &filter.params),
+                   "SELECT doc FROM jobs WHERE chain_id = $1 AND status NOT IN ($2, $3)");
+        assert_eq!(filter.params.len(), 3);
+
+        let params = vec![Param::Text("1:0xc0".to_string()), Param::Int(1), Param::Null, Param::Text("{}".to_string())];
+        assert_eq!(number(&CONTRACTS.insert_sql(false), &params),
+                   "INSERT INTO contracts (id, chain_id, address, doc) VALUES ($1, $2, NULL, $3) ON CONFLICT (id) DO NOTHING");
+    }
+
+    #[tokio::test]
+    async fn contracts_and_coverage() -> Result<()> {
+        let storage = memory().await?;
+        let address = "0x00000000000000000000000000000000000000c0";
+
+        let mut contract = Contract::new(1, address, serde_json::from_str("[]")?);
+        storage.save_contract(&contract).await?;
+        assert!(storage.save_contract(&contract).await.is_err());
+
+        contract.processed_range = Some(10..20);
+        contract.labels = vec!["dex".to_string()];
+        storage.update_contract(&contract).await?;
+
+        let stored = storage.get_contract(1, address).await?.unwrap();
+        assert_eq!(stored.processed_range, Some(10..20));
+        // info isn't saved with progress
+        assert!(stored.labels.is_empty());
+
+        assert_eq!(storage.get_contracts(1).await?.len(), 1);
+        assert!(storage.get_contracts(2).await?.is_empty());
+
+        let mut coverage = storage.get_coverage(1, address).await?;
+        assert!(coverage.covered.ranges().is_empty());
+
+        coverage.covered.insert(10..20);
+        storage.save_coverage(&coverage).await?;
+        assert_eq!(storage.get_coverage(1, address).await?.covered.ranges(), vec![10..20]);
+
+        let initial = Job::initial(1, address, 0);
+        storage.enqueue_job(&initial).await?;
+
+        contract.paused = true;
+        storage.update_contract_info(&contract).await?;
+        assert!(storage.get_contract(1, address).await?.unwrap().paused);
+
+        contract.paused = false;
+        storage.update_contract_info(&contract).await?;
+        assert!(!storage.get_contract(1, address).await?.unwrap().paused);
+
+        storage.delete_contract(&contract).await?;
+        assert!(storage.get_contract(1, address).await?.is_none());
+        assert!(storage.get_coverage(1, address).await?.covered.ranges().is_empty());
+        assert!(storage.get_job(&initial.id).await?.is_none());
+
+        // uploaded again
+        storage.save_contract(&contract).await?;
+        assert!(storage.enqueue_job(&initial).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transactions_by_pages() -> Result<()> {
+        let storage = memory().await?;
+        let to = H160::from_low_u64_be(0xc0);
+        let address = format!("{:#x}", to);
+
+        let transactions: Vec<_> = (1..=5).map(|n| trx(n, to)).collect();
+
+        assert_eq!(storage.save_transactions(&transactions).await?, BulkResult { inserted: 5, ..Default::default() });
+        assert_eq!(storage.save_transactions(&transactions[..2]).await?, BulkResult { updated: 2, ..Default::default() });
+        storage.save_transactions(&[trx(6, H160::from_low_u64_be(0xc1))]).await?;
+
+        assert_eq!(storage.count_trx_to(1, &address, &(0..i64::MAX as u64)).await?, 5);
+        assert_eq!(storage.count_trx_to(1, &address, &(2..4)).await?, 2);
+
+        let first = storage.find_trx_to(1, &address, None, 3).await?;
+        let rest = storage.find_trx_to(1, &address, first.last().map(|t| t.hash), 3).await?;
+
+        let pages: Vec<_> = first.iter().chain(rest.iter()).map(|t| t.block_number.as_u64()).collect();
+        assert_eq!(pages, vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(storage.delete_trx_to(1, &address).await?, 5);
+        assert_eq!(storage.count_trx_to(1, &address, &(0..i64::MAX as u64)).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_queue() -> Result<()> {
+        let storage = memory().await?;
+
+        let initial = Job::initial(1, "0xc0", 100);
+        let redecode = Job::new(JobKind::Redecode, 1, "0xc0", 0..10);
+
+        assert!(storage.enqueue_job(&redecode).await?);
+        assert!(storage.enqueue_job(&initial).await?);
+        assert!(!storage.enqueue_job(&initial).await?);
+
+        let gap = Job::gap_fill(1, "0xc0", 10..20);
+        assert!(storage.enqueue_job(&gap).await?);
+        assert!(storage.set_job_status(&gap.id, &[JobStatus::Queued], JobStatus::Failed).await?);
+        assert!(storage.enqueue_job(&gap).await?);
+        assert_eq!(storage.get_job(&gap.id).await?.unwrap().status, JobStatus::Queued);
+        assert!(storage.set_job_status(&gap.id, &[JobStatus::Queued], JobStatus::Cancelled).await?);
+
+        let next = storage.next_job(1, &[]).await?.unwrap();
+        assert_eq!(next.id, initial.id);
+        assert_eq!(next.status, JobStatus::Running);
+
+        assert!(storage.next_job(1, &[JobKind::Redecode]).await?.is_none());
+
+        assert!(storage.set_job_status(&next.id, &[JobStatus::Running], JobStatus::Paused).await?);
+        assert!(!storage.update_running_job(&next).await?);
+
+        assert!(storage.next_job(1, &[]).await?.is_some());
+        assert_eq!(storage.requeue_running_jobs(1).await?, 1);
+        assert_eq!(storage.get_jobs(Some(1), None, Some(JobStatus::Queued)).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tables_of_earlier_version_upgraded() -> Result<()> {
+        let storage = SqlStorage::connect("sqlite::memory:").await?;
+        let to = H160::from_low_u64_be(0xc0);
+        let from = H160::from_low_u64_be(1);
+
+        // no block_number column, nonce as text
+        for sql in [
+            format!("CREATE TABLE {} (id TEXT PRIMARY KEY, chain_id BIGINT, to_address TEXT, hash TEXT, doc TEXT NOT NULL)", TRANSACTIONS.name),
+            format!("CREATE TABLE {} (id TEXT PRIMARY KEY, chain_id BIGINT, to_address TEXT, from_address TEXT, nonce TEXT, status TEXT, \
+                doc TEXT NOT NULL)", PENDING.name),
+            format!("CREATE INDEX {0}_chain_from_nonce ON {0} (chain_id, from_address, nonce)", PENDING.name),
+        ].iter() {
+            sqlx::query(sql).execute(&storage.pool).await?;
+        }
+
+        let pending = |n: u64| model::PendingTransaction::new(1, &web3::types::Transaction {
+            hash: H256::from_low_u64_be(n),
+            nonce: 7.into(),
+            from,
+            ..Default::default()
+        }, None, 0);
+
+        let rows = (1..=5).map(|n| (&TRANSACTIONS, serde_json::to_value(trx(n, to))))
+            .chain((1..=2).map(|n| (&PENDING, serde_json::to_value(pending(n)))));
+
+        for (table, doc) in rows {
+            let doc = doc?;
+            sqlx::query(&format!("INSERT INTO {} (id, doc) VALUES ($1, $2)", table.name))
+                .bind(key(&doc["_id"]))
+                .bind(doc.to_string())
+                .execute(&storage.pool).await?;
+        }
+
+        storage.init().await?;
+
+        let nonce = storage.existing_columns(&PENDING).await?.into_iter().find(|(name, _)| name == "nonce");
+        assert_eq!(nonce, Some(("nonce".to_string(), "BIGINT".to_string())));
+
+        assert_eq!(storage.count_trx_to(1, &format!("{:#x}", to), &(2..4)).await?, 2);
+        assert_eq!(storage.replace_pending(&pending(2).id, &from, &7.into()).await?, 1);
+
+        // nothing left to change
+        storage.init().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pending_replaced_by_mined() -> Result<()> {
+        let storage = memory().await?;
+        let from = H160::from_low_u64_be(1);
+
+        let pending = |n: u64, nonce: u64| model::PendingTransaction::new(1, &web3::types::Transaction {
+            hash: H256::from_low_u64_be(n),
+            nonce: nonce.into(),
+            from,
+            ..Default::default()
+        }, None, 0);
+
+        for trx in [pending(1, 7), pending(2, 7), pending(3, 8)].iter() {
+            storage.save_pending(trx).await?;
+        }
+
+        let mined = pending(2, 7);
+        assert_eq!(storage.replace_pending(&mined.id, &from, &mined.nonce).await?, 1);
+
+        let stored = storage.get_pending(1).await?;
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|p| p.hash != H256::from_low_u64_be(1)));
+
+        Ok(())
+    }
+}
@@ -1,34 +1,65 @@
+use std::collections::{BTreeMap, HashSet};
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use futures::Future;
-use log::{debug, info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use tokio::stream::Stream;
-use web3::futures::TryFutureExt;
-use web3::types::{Block, Transaction, U64};
-use web3::Web3;
+use web3::types::{Block, H160, H2048, Transaction};
 
-use crate::traversal::ChainData;
-use crate::traversal::connection::Transport;
+use crate::traversal::{ChainData, RangeSet, TrxFilter};
+use crate::traversal::config::TraversalStrategy;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::logs;
+use crate::traversal::source::ChainSource;
 
 lazy_static! {
-    pub static ref TRAVERSE_IN_PROGRESS: Mutex<bool> = Mutex::new(false);
+    /// Chains being traversed
+    static ref TRAVERSE_IN_PROGRESS: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
 }
 
-async fn join_parallel<T: Send + 'static>(futures: impl IntoIterator<Item=impl Future<Output=Vec<T>> + Send + 'static>) -> Vec<T> {
+/// Allows single traversal per chain at a time. Released on drop, so early returns can't leave it locked.
+#[derive(Debug)]
+pub struct TraverseGuard {
+    chain_id: u64,
+}
+
+impl TraverseGuard {
+    pub fn acquire(chain_id: u64) -> Option<TraverseGuard> {
+        if !TRAVERSE_IN_PROGRESS.lock().unwrap().insert(chain_id) {
+            return None;
+        }
+
+        Some(TraverseGuard { chain_id })
+    }
+}
+
+impl Drop for TraverseGuard {
+    fn drop(&mut self) {
+        TRAVERSE_IN_PROGRESS.lock().unwrap().remove(&self.chain_id);
+    }
+}
+
+/// Result of a single sub-range. `covered` is shorter than requested range if chain end reached.
+#[derive(Debug)]
+struct SubRange {
+    covered: Range<u64>,
+    blocks: Vec<Block<Transaction>>,
+}
+
+async fn join_parallel<T: Send + 'static>(futures: impl IntoIterator<Item=impl Future<Output=T> + Send + 'static>) -> Vec<T> {
     let tasks: Vec<_> = futures.into_iter().map(tokio::spawn).collect();
 
     futures::future::join_all(tasks)
         .await
         .into_iter()
         .map(Result::unwrap)
-        .flat_map(|x| { x.into_iter() })
         .collect()
 }
 
-fn create_ranges(range: &Range<u64>, batch_size: u64) -> Vec<Range<u64>> {
+pub(crate) fn create_ranges(range: &Range<u64>, batch_size: u64) -> Vec<Range<u64>> {
     let mut start_pos = range.start;
     let mut batches = vec![];
 
@@ -45,18 +76,15 @@ fn create_ranges(range: &Range<u64>, batch_size: u64) -> Vec<Range<u64>> {
     batches
 }
 
-pub async fn traversal(web3: Arc<Web3<Transport>>, to_addresses: Vec<String>, mut range: &mut Range<u64>, batch_size: u64) -> Option<impl Stream<Item=ChainData>> {
-    if *TRAVERSE_IN_PROGRESS.lock().unwrap() {
-        info!("Travers in progress");
-        return None;
-    } else {
-        *TRAVERSE_IN_PROGRESS.lock().unwrap() = true;
-    }
-
-    let last_block = web3.eth().block_number().into_future().await.expect("last block result").as_u64();
+/// Streams chain data for range. Sub-ranges already `completed` are skipped.
+/// `blooms` of stored blocks save header requests of `Bloom` strategy.
+/// Caller is expected to hold `TraverseGuard`. Fails if chain head can't be fetched, so the caller tries again later.
+pub async fn traversal(source: Arc<dyn ChainSource>, filter: TrxFilter, range: &mut Range<u64>, throttle: Arc<Throttle>,
+                       strategy: TraversalStrategy, completed: &RangeSet, blooms: BTreeMap<u64, H2048>) -> Result<Option<impl Stream<Item=ChainData>>> {
+    let last_block = source.block_number().await?;
 
     if range.start > last_block {
-        return None;
+        return Ok(None);
     }
 
     if range.end > last_block {
@@ -64,77 +92,106 @@ pub async fn traversal(web3: Arc<Web3<Transport>>, to_addresses: Vec<String>, mu
         debug!("Range changed to align last block in chain. {:?}", range);
     }
 
-    Some(traversal_parallel(web3, to_addresses, range, batch_size).await)
+    Ok(Some(traversal_parallel(source, filter, range, throttle, strategy, completed.clone(), Arc::new(blooms)).await))
 }
 
-async fn traversal_parallel(web3: Arc<Web3<Transport>>, to_addresses: Vec<String>, init_range: &Range<u64>, batch_size: u64) -> impl Stream<Item=ChainData> {
-    let size = 30_000;
-    let mut ranges = create_ranges(&init_range, size);
+async fn traversal_parallel(source: Arc<dyn ChainSource>, filter: TrxFilter, init_range: &Range<u64>, throttle: Arc<Throttle>,
+                            strategy: TraversalStrategy, completed: RangeSet, blooms: Arc<BTreeMap<u64, H2048>>) -> impl Stream<Item=ChainData> {
+    let size = throttle.config.range_size;
+    let mut ranges = create_ranges(init_range, size);
     ranges.reverse();
 
-    info!("Range: {:?}. {} ranges started with size: {}. Sub range size: {}", init_range, ranges.len(), size, batch_size);
-    debug!("Looking for contracts related trxs: {:?}", to_addresses);
+    info!("Range: {:?}. {} ranges started with size: {}. Sub range size: {}. Strategy: {:?}", init_range, ranges.len(), size, throttle.batch_size(), strategy);
+    debug!("Looking for trxs matching: {:?}", filter);
+
+    let addresses: Arc<Vec<H160>> = Arc::new(filter.to_addresses());
 
     async_stream::stream! {
         for range in ranges {
-            let web3 = web3.clone();
+            let source = source.clone();
             let range_start_time = Instant::now();
 
-            let sub_ranges = create_ranges(&range, batch_size);
+            let sub_range_size = match strategy {
+                TraversalStrategy::Logs => logs::LOGS_RANGE_SIZE,
+                _ => throttle.batch_size(),
+            };
+
+            let sub_ranges: Vec<_> = completed.missing(&range).iter()
+                .flat_map(|r| create_ranges(r, sub_range_size))
+                .collect();
             let sub_ranges_len = sub_ranges.len();
 
+            if sub_ranges.is_empty() {
+                debug!("Range {:?} already completed. Skipping", range);
+                continue;
+            }
+
+            let job_throttle = throttle.clone();
+            let job_addresses = addresses.clone();
+            let job_blooms = blooms.clone();
             let jobs: Vec<_> = sub_ranges.into_iter()
                 .map(move |range| {
-                    process_range(range, web3.clone())
+                    process_range(range, strategy, job_addresses.clone(), source.clone(), job_throttle.clone(), job_blooms.clone())
                 }).collect();
 
-            let blocks = join_parallel(jobs.into_iter()).await;
-
-            let blocks: Vec<_> = blocks.into_iter()
-                .filter(|b| {
-                    b.transactions.iter()
-                        .any(|t| {
-                            if t.to.is_some() {
-                                let to = crate::parse::h160_to_address(t.to.as_ref());
-                                // info!("Trx: {:?}", to);
-                                return to_addresses.contains(&to.to_lowercase());
-                            }
-                            return false;
-                        })
-
-                })
+            let results = join_parallel(jobs.into_iter()).await;
+
+            let covered: Vec<_> = results.iter()
+                .map(|r| r.covered.clone())
+                .filter(|r| !r.is_empty())
+                .collect();
+
+            let blocks: Vec<_> = results.into_iter()
+                .flat_map(|r| r.blocks.into_iter())
+                .filter(|b| b.transactions.iter().any(|t| filter.matches(&t.from, t.to.as_ref())))
                 .collect();
 
             info!("Range {:?} finished. {} sub-ranges processed in {}ms. Blocks found : {}", range, sub_ranges_len, (Instant::now() - range_start_time).as_millis(), blocks.len());
 
-            yield ChainData::new(range, blocks);
+            throttle.adjust_batch_size();
+
+            yield ChainData::new(range, blocks, covered);
         }
     }
 }
 
-async fn process_range(range: Range<u64>, web3: Arc<Web3<Transport>>) -> Vec<Block<Transaction>> {
+async fn process_range(range: Range<u64>, strategy: TraversalStrategy, addresses: Arc<Vec<H160>>,
+                       source: Arc<dyn ChainSource>, throttle: Arc<Throttle>, blooms: Arc<BTreeMap<u64, H2048>>) -> SubRange {
     let mut blocks = vec![];
+    let mut covered_end = range.end;
 
-    debug!("Starting range: {:?}", range);
-
-    for block_id in range.clone() {
-        let b: U64 = block_id.into();
-
-        let web3_clone = web3.clone();
-
-        let block = tokio::task::spawn_blocking(move || {
-            futures::executor::block_on(web3_clone.eth().block_with_txs(b.into())).expect("Block expected")
-        }).await;
+    let _permit = throttle.acquire_task().await;
 
-        let block = block.expect("Block expected");
-
-        if block.is_none() {
-            warn!("Block is None {}", block_id);
-
-            break;
-        }
+    debug!("Starting range: {:?}", range);
 
-        let block = block.unwrap();
+    let candidates = match strategy {
+        TraversalStrategy::Logs => logs::blocks_with_logs(source.clone(), &addresses, &range).await,
+        TraversalStrategy::Bloom => logs::blocks_by_bloom(source.clone(), &blooms, &addresses, &range).await,
+        _ => Ok(range.clone().collect()),
+    };
+
+    let candidates: Vec<u64> = candidates.unwrap_or_else(|e| {
+        warn!("Failed to find candidate blocks in {:?}. Falling back to full scan. {:?}", range, e);
+        range.clone().collect()
+    });
+
+    for block_id in candidates {
+        let block = match fetch_block(block_id, source.clone(), throttle.clone()).await {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                warn!("Block is None {}", block_id);
+
+                covered_end = block_id;
+                break;
+            }
+            // rest of range stays uncovered, so it's picked up again as a hole
+            Err(e) => {
+                error!("Block {} not fetched in {} attempts. Range {:?} covered till it. {:?}", block_id, throttle.config.max_retries + 1, range, e);
+
+                covered_end = block_id;
+                break;
+            }
+        };
 
         if !block.transactions.is_empty() {
             trace!("Found block [{}] with {} trx", block.number.unwrap(), block.transactions.len());
@@ -144,18 +201,94 @@ async fn process_range(range: Range<u64>, web3: Arc<Web3<Transport>>) -> Vec<Blo
 
     debug!("Finished range: {:?} found {} blocks", range, blocks.len());
 
-    blocks
+    SubRange {
+        covered: range.start..covered_end,
+        blocks,
+    }
+}
+
+async fn fetch_block(block_id: u64, source: Arc<dyn ChainSource>, throttle: Arc<Throttle>) -> Result<Option<Block<Transaction>>> {
+    let mut attempt = 0;
+
+    loop {
+        let start_time = Instant::now();
+        let block = source.block_with_txs(block_id).await;
+
+        throttle.record(Instant::now() - start_time, block.is_err());
+
+        match block {
+            Ok(block) => return Ok(block),
+            Err(e) if attempt < throttle.config.max_retries => {
+                attempt += 1;
+                warn!("Failed to fetch block {}. Attempt {}. {:?}", block_id, attempt, e);
+                tokio::time::delay_for(Duration::from_millis(100 << attempt)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use mongodb::results::InsertManyResult;
+    use anyhow::bail;
+    use async_trait::async_trait;
+    use futures_util::stream::StreamExt;
+    use web3::types::{Bytes, CallRequest, H256, Log, TransactionReceipt, U256};
 
-    use crate::mongo::MongoDB;
-    use crate::traversal::ChainData;
+    use crate::traversal::{ChainData, Role};
+    use crate::traversal::source::{ChainBuilder, MemoryChainSource};
 
     use super::*;
 
+    /// Chain whose single block can never be fetched. So is the head if `head` is false.
+    #[derive(Debug)]
+    struct Unreachable {
+        chain: MemoryChainSource,
+        block: u64,
+        head: bool,
+    }
+
+    #[async_trait]
+    impl ChainSource for Unreachable {
+        async fn block_number(&self) -> Result<u64> {
+            if !self.head {
+                bail!("Connection refused");
+            }
+            self.chain.block_number().await
+        }
+
+        async fn block(&self, number: u64) -> Result<Option<Block<H256>>> {
+            self.chain.block(number).await
+        }
+
+        async fn block_with_txs(&self, number: u64) -> Result<Option<Block<Transaction>>> {
+            if number == self.block {
+                bail!("Connection reset");
+            }
+            self.chain.block_with_txs(number).await
+        }
+
+        async fn transaction(&self, hash: H256) -> Result<Option<Transaction>> {
+            self.chain.transaction(hash).await
+        }
+
+        async fn transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
+            self.chain.transaction_receipt(hash).await
+        }
+
+        async fn transaction_count(&self, address: H160) -> Result<U256> {
+            self.chain.transaction_count(address).await
+        }
+
+        async fn logs(&self, addresses: &[H160], range: Range<u64>) -> Result<Vec<Log>> {
+            self.chain.logs(addresses, range).await
+        }
+
+        async fn call(&self, request: CallRequest) -> Result<Bytes> {
+            self.chain.call(request).await
+        }
+    }
+
     #[test]
     fn create_batches() {
         let ranges = create_ranges(&(0..3303), 100);
@@ -166,22 +299,135 @@ mod tests {
 
     #[tokio::test]
     async fn traversal_parallel() -> Result<()> {
-        crate::error::setup_panic_handler();
-
-        log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
+        log4rs::init_file("config/log4rs.yml", Default::default()).ok();
 
         let mut range = 170000..173000;
-        let batch_size = 100;
+        let throttle = Arc::new(Throttle::new(crate::traversal::config::TraversalConfig {
+            batch_size: 100,
+            ..Default::default()
+        }));
 
         let start_time = std::time::Instant::now();
-        let mongo_db = Arc::new(MongoDB::new("localhost"));
 
         // todo: think on streaming instead of bulk op
-        let web3 = Arc::new(crate::traversal::connection::create_web3("ws://localhost:8546").await);
-        let cd = super::traversal(web3, vec![], &mut range, batch_size).await.unwrap();
+        let source = crate::traversal::source::create_source("ws://localhost:8546", throttle.clone()).await?;
+        let chain_data: Vec<ChainData> = super::traversal(source, TrxFilter::new(), &mut range, throttle, TraversalStrategy::Full, &RangeSet::new(), BTreeMap::new()).await?.unwrap()
+            .collect().await;
 
-        println!("Total time: {:?}", (std::time::Instant::now() - start_time).as_secs());
+        println!("Total time: {:?}. Chunks: {}", (std::time::Instant::now() - start_time).as_secs(), chain_data.len());
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn traversal_memory_chain() -> Result<()> {
+        let contract = H160::from_low_u64_be(0xc0);
+        let other = H160::from_low_u64_be(0xc1);
+        let sender = H160::from_low_u64_be(1);
+
+        let source: Arc<dyn ChainSource> = Arc::new(ChainBuilder::new()
+            .empty_blocks(20)
+            .transaction(sender, Some(contract), vec![])
+            .log(vec![H256::from_low_u64_be(1)], vec![])
+            .mine()
+            .transaction(sender, Some(other), vec![])
+            .mine()
+            .empty_blocks(20)
+            .transaction(sender, Some(contract), vec![])
+            .log(vec![H256::from_low_u64_be(1)], vec![])
+            .mine()
+            .empty_blocks(10)
+            .build());
+
+        let filter = TrxFilter::new().watch(&format!("{:#x}", contract), Role::To);
+
+        for strategy in [TraversalStrategy::Full, TraversalStrategy::Logs, TraversalStrategy::Bloom] {
+            let throttle = Arc::new(Throttle::new(crate::traversal::config::TraversalConfig {
+                range_size: 16,
+                batch_size: 4,
+                ..Default::default()
+            }));
+
+            let mut range = 0..100;
+            let stream = super::traversal(source.clone(), filter.clone(), &mut range, throttle, strategy, &RangeSet::new(), BTreeMap::new()).await?.unwrap();
+            let chunks: Vec<ChainData> = stream.collect().await;
+
+            let mut found: Vec<_> = chunks.iter()
+                .flat_map(|c| c.blocks.iter())
+                .map(|b| b.number.unwrap().as_u64())
+                .collect();
+            found.sort();
+
+            assert_eq!(range, 0..52, "{:?}", strategy);
+            assert_eq!(found, vec![20, 42], "{:?}", strategy);
+        }
+
+        // sender watched regardless of recipient
+        let throttle = Arc::new(Throttle::new(crate::traversal::config::TraversalConfig {
+            range_size: 16,
+            batch_size: 4,
+            ..Default::default()
+        }));
+        let filter = TrxFilter::new().watch(&format!("{:#x}", sender), Role::From);
+
+        let mut range = 0..100;
+        let stream = super::traversal(source, filter, &mut range, throttle, TraversalStrategy::Full, &RangeSet::new(), BTreeMap::new()).await?.unwrap();
+        let chunks: Vec<ChainData> = stream.collect().await;
+
+        let mut found: Vec<_> = chunks.iter()
+            .flat_map(|c| c.blocks.iter())
+            .map(|b| b.number.unwrap().as_u64())
+            .collect();
+        found.sort();
+
+        assert_eq!(found, vec![20, 21, 42]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_block_leaves_hole() -> Result<()> {
+        let contract = H160::from_low_u64_be(0xc0);
+        let chain = ChainBuilder::new()
+            .empty_blocks(10)
+            .transaction(H160::from_low_u64_be(1), Some(contract), vec![])
+            .mine()
+            .empty_blocks(10)
+            .build();
+        let source: Arc<dyn ChainSource> = Arc::new(Unreachable { chain, block: 5, head: true });
+
+        let throttle = Arc::new(Throttle::new(crate::traversal::config::TraversalConfig {
+            range_size: 8,
+            batch_size: 4,
+            min_batch_size: 4,
+            max_batch_size: 4,
+            max_retries: 1,
+            ..Default::default()
+        }));
+        let filter = TrxFilter::new().watch(&format!("{:#x}", contract), Role::To);
+
+        let mut range = 0..30;
+        let stream = super::traversal(source, filter, &mut range, throttle, TraversalStrategy::Full, &RangeSet::new(), BTreeMap::new()).await?.unwrap();
+        let chunks: Vec<ChainData> = stream.collect().await;
+
+        let mut covered = RangeSet::new();
+        chunks.iter().flat_map(|c| c.covered.iter()).for_each(|r| covered.insert(r.clone()));
+
+        assert_eq!(covered.missing(&range), vec![5..8]);
+        assert_eq!(chunks.iter().map(|c| c.blocks.len()).sum::<usize>(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreachable_head_is_error() {
+        let source: Arc<dyn ChainSource> = Arc::new(Unreachable { chain: ChainBuilder::new().empty_blocks(10).build(), block: 5, head: false });
+        let throttle = Arc::new(Throttle::new(Default::default()));
+
+        let mut range = 0..10;
+        let stream = super::traversal(source, TrxFilter::new(), &mut range, throttle, TraversalStrategy::Full, &RangeSet::new(), BTreeMap::new()).await;
+
+        assert!(stream.is_err());
+        assert_eq!(range, 0..10);
+    }
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use structopt::StructOpt;
+
+/// How blocks worth downloading are chosen.
+/// Log based ones skip transactions emitting no logs, e.g. reverted calls, so they are opt-in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraversalStrategy {
+    /// Every block downloaded with full transactions.
+    Full,
+    /// Candidate blocks found with `eth_getLogs` by contract addresses.
+    Logs,
+    /// Candidate blocks found by logs bloom of stored blocks or block headers.
+    Bloom,
+}
+
+impl FromStr for TraversalStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "full" => TraversalStrategy::Full,
+            "logs" => TraversalStrategy::Logs,
+            "bloom" => TraversalStrategy::Bloom,
+            _ => bail!("Unknown traversal strategy: {}", s),
+        })
+    }
+}
+
+/// Traversal tuning. Defaults are safe for a shared/throttled provider,
+/// local dev nodes can be pushed much harder.
+#[derive(Debug, Clone, PartialEq, StructOpt)]
+pub struct TraversalConfig {
+    /// Blocks processed per outer range. Results are yielded once per range.
+    #[structopt(long = "range_size", default_value = "30000")]
+    pub range_size: u64,
+
+    /// Initial amount of blocks fetched sequentially by a single task.
+    #[structopt(long = "batch_size", default_value = "10")]
+    pub batch_size: u64,
+
+    #[structopt(long = "min_batch_size", default_value = "5")]
+    pub min_batch_size: u64,
+
+    #[structopt(long = "max_batch_size", default_value = "500")]
+    pub max_batch_size: u64,
+
+    /// Max sub-ranges fetched at the same time.
+    #[structopt(long = "max_concurrency", default_value = "16")]
+    pub max_concurrency: usize,
+
+    /// Max RPC requests per second per endpoint. 0 - unlimited.
+    #[structopt(long = "requests_per_sec", default_value = "50")]
+    pub requests_per_sec: u32,
+
+    /// Block fetch latency the batch size is adapted to.
+    #[structopt(long = "target_latency_ms", default_value = "200")]
+    pub target_latency_ms: u64,
+
+    /// Error rate (0..1) above which batch size is decreased.
+    #[structopt(long = "max_error_rate", default_value = "0.05")]
+    pub max_error_rate: f64,
+
+    /// Attempts to fetch a single block before giving up.
+    #[structopt(long = "max_retries", default_value = "5")]
+    pub max_retries: u32,
+
+    /// full | logs | bloom
+    #[structopt(long = "strategy", default_value = "full")]
+    pub strategy: TraversalStrategy,
+
+    /// Records every RPC request and response to gzipped NDJSON cassette, overwritten every run. Replayed with `replay://<path>` url.
+    #[structopt(long = "record_rpc")]
+    pub record_rpc: Option<String>,
+}
+
+impl Default for TraversalConfig {
+    fn default() -> Self {
+        TraversalConfig {
+            range_size: 30_000,
+            batch_size: 10,
+            min_batch_size: 5,
+            max_batch_size: 500,
+            max_concurrency: 16,
+            requests_per_sec: 50,
+            target_latency_ms: 200,
+            max_error_rate: 0.05,
+            max_retries: 5,
+            strategy: TraversalStrategy::Full,
+            record_rpc: None,
+        }
+    }
+}
@@ -1,14 +1,35 @@
-use log::debug;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use futures::future::{LocalBoxFuture, Ready};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use jsonrpc_core as rpc;
+use web3::RequestId;
 
 pub type Transport = web3::transports::Either<web3::transports::WebSocket, web3::transports::Http>;
 
+lazy_static! {
+    /// Cassettes are shared by path, so transports recording to the same file don't interleave writes.
+    static ref CASSETTES: Mutex<HashMap<PathBuf, Weak<Mutex<CassetteWriter>>>> = Mutex::new(HashMap::new());
+}
 
-pub async fn create_web3(url: &str) -> web3::Web3<Transport> {
-    let transport = create_transport(url).await;
-    web3::Web3::new(transport)
+/// Raw WebSocket transport. Required for subscriptions.
+pub async fn create_ws(url: &str) -> Result<web3::transports::WebSocket> {
+    debug!("Creating ws connection for [{}]", url);
+    Ok(web3::transports::WebSocket::new(url).await?)
 }
 
-async fn create_transport(url: &str) -> Transport {
+pub(crate) async fn create_transport(url: &str) -> Transport {
     match url {
         u if u.starts_with("http") => {
             debug!("Creating http connection for [{}]", url);
@@ -22,4 +43,290 @@ async fn create_transport(url: &str) -> Transport {
         }
         _ => panic!("Unsupported transport")
     }
-}
\ No newline at end of file
+}
+
+/// Single request and its outcome. A line of cassette.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Interaction {
+    fn key(&self) -> String {
+        request_key(&self.method, &self.params)
+    }
+}
+
+/// Request ids differ between runs, so requests are matched by method and params only.
+fn request_key(method: &str, params: &Value) -> String {
+    format!("{}:{}", method, params)
+}
+
+fn call_parts(call: &rpc::Call) -> (String, Value) {
+    match call {
+        rpc::Call::MethodCall(c) => (c.method.clone(), serde_json::to_value(&c.params).unwrap_or(Value::Null)),
+        rpc::Call::Notification(n) => (n.method.clone(), serde_json::to_value(&n.params).unwrap_or(Value::Null)),
+        rpc::Call::Invalid { .. } => ("invalid".into(), Value::Null),
+    }
+}
+
+#[derive(Debug)]
+struct CassetteWriter {
+    path: PathBuf,
+    out: GzEncoder<File>,
+}
+
+impl CassetteWriter {
+    /// Starts cassette afresh every run. Appending after a member left truncated by a killed process
+    /// would hide everything recorded later, as gzip decoding stops at the damaged member.
+    fn open(path: &Path) -> Result<Arc<Mutex<CassetteWriter>>> {
+        let mut cassettes = CASSETTES.lock().unwrap();
+
+        if let Some(writer) = cassettes.get(path).and_then(Weak::upgrade) {
+            return Ok(writer);
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Can't open cassette {:?}", path))?;
+
+        info!("Recording RPC traffic to {:?}", path);
+
+        let writer = Arc::new(Mutex::new(CassetteWriter {
+            path: path.to_path_buf(),
+            out: GzEncoder::new(file, Compression::default()),
+        }));
+        cassettes.insert(path.to_path_buf(), Arc::downgrade(&writer));
+
+        Ok(writer)
+    }
+
+    /// Flushed per interaction, so cassette of a crashed process is still readable.
+    fn write(&mut self, interaction: &Interaction) -> Result<()> {
+        serde_json::to_writer(&mut self.out, interaction)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for CassetteWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.try_finish() {
+            warn!("Failed to finish cassette {:?}. {:?}", self.path, e);
+        }
+    }
+}
+
+/// Passes requests to inner transport and records every request/response pair to cassette.
+#[derive(Debug, Clone)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    cassette: Arc<Mutex<CassetteWriter>>,
+}
+
+impl<T: web3::Transport> RecordingTransport<T> {
+    pub fn new<P: AsRef<Path>>(inner: T, path: P) -> Result<Self> {
+        Ok(RecordingTransport {
+            inner,
+            cassette: CassetteWriter::open(path.as_ref())?,
+        })
+    }
+}
+
+impl<T> web3::Transport for RecordingTransport<T>
+    where
+        T: web3::Transport,
+        T::Out: 'static,
+{
+    type Out = LocalBoxFuture<'static, web3::error::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = call_parts(&request);
+        let cassette = self.cassette.clone();
+        let response = self.inner.send(id, request);
+
+        Box::pin(async move {
+            let response = response.await;
+
+            let interaction = Interaction {
+                method,
+                params,
+                result: response.as_ref().ok().cloned(),
+                error: response.as_ref().err().map(|e| format!("{}", e)),
+            };
+
+            if let Err(e) = cassette.lock().unwrap().write(&interaction) {
+                warn!("Failed to record {} to cassette. {:?}", interaction.method, e);
+            }
+
+            response
+        })
+    }
+}
+
+/// Serves responses from cassette written by `RecordingTransport`. Never touches network.
+/// Repeated requests get recorded responses in order, the last one is repeated once exhausted.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    responses: Arc<Mutex<HashMap<String, VecDeque<Interaction>>>>,
+    id: Arc<AtomicUsize>,
+}
+
+impl ReplayTransport {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Can't open cassette {:?}", path))?;
+
+        let mut responses: HashMap<String, VecDeque<Interaction>> = HashMap::new();
+        let mut count = 0;
+
+        for line in BufReader::new(MultiGzDecoder::new(file)).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    // recording process was killed before gzip trailer got written
+                    warn!("Cassette {:?} is truncated after {} interactions. {:?}", path, count, e);
+                    break;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let interaction: Interaction = serde_json::from_str(&line)
+                .with_context(|| format!("Bad interaction {} in {:?}", count + 1, path))?;
+
+            responses.entry(interaction.key()).or_default().push_back(interaction);
+            count += 1;
+        }
+
+        info!("Replaying {} interactions from {:?}", count, path);
+
+        Ok(ReplayTransport {
+            responses: Arc::new(Mutex::new(responses)),
+            id: Arc::new(AtomicUsize::new(1)),
+        })
+    }
+
+    fn respond(&self, method: &str, params: &Value) -> web3::error::Result<Value> {
+        let mut responses = self.responses.lock().unwrap();
+
+        let queue = responses.get_mut(&request_key(method, params))
+            .ok_or_else(|| web3::Error::Transport(format!("No recorded response for {} {}", method, params)))?;
+
+        let interaction = match queue.len() {
+            1 => queue[0].clone(),
+            _ => queue.pop_front().unwrap(),
+        };
+
+        match interaction.error {
+            Some(e) => Err(web3::Error::Transport(e)),
+            None => Ok(interaction.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+impl web3::Transport for ReplayTransport {
+    type Out = Ready<web3::error::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        (id, web3::helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = call_parts(&request);
+        futures::future::ready(self.respond(&method, &params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::Web3;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn record_and_replay() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let original = dir.join("chain_scraper_original.ndjson.gz");
+        let recorded = dir.join("chain_scraper_recorded.ndjson.gz");
+        let _ = std::fs::remove_file(&original);
+        let _ = std::fs::remove_file(&recorded);
+
+        {
+            let cassette = CassetteWriter::open(&original)?;
+            let mut cassette = cassette.lock().unwrap();
+
+            for head in ["0x10", "0x11"] {
+                cassette.write(&Interaction {
+                    method: "eth_blockNumber".into(),
+                    params: Value::Array(vec![]),
+                    result: Some(Value::String(head.into())),
+                    error: None,
+                })?;
+            }
+        }
+
+        // re-recording replayed traffic must give the same cassette
+        {
+            let transport = RecordingTransport::new(ReplayTransport::open(&original)?, &recorded)?;
+            let web3 = Web3::new(transport);
+
+            assert_eq!(web3.eth().block_number().await?.as_u64(), 0x10);
+            assert_eq!(web3.eth().block_number().await?.as_u64(), 0x11);
+        }
+
+        let web3 = Web3::new(ReplayTransport::open(&recorded)?);
+
+        assert_eq!(web3.eth().block_number().await?.as_u64(), 0x10);
+        assert_eq!(web3.eth().block_number().await?.as_u64(), 0x11);
+        assert_eq!(web3.eth().block_number().await?.as_u64(), 0x11);
+        assert!(web3.eth().gas_price().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recording_replaces_truncated_cassette() -> Result<()> {
+        let path = std::env::temp_dir().join("chain_scraper_truncated.ndjson.gz");
+
+        // member of a killed run without trailer
+        {
+            let mut out = GzEncoder::new(File::create(&path)?, Compression::default());
+            out.write_all(b"{\"method\":\"eth_blockNumber\",\"params\":[],\"result\":\"0x1\"}\n")?;
+            out.flush()?;
+            std::mem::forget(out);
+        }
+
+        {
+            let cassette = CassetteWriter::open(&path)?;
+            cassette.lock().unwrap().write(&Interaction {
+                method: "eth_blockNumber".into(),
+                params: Value::Array(vec![]),
+                result: Some(Value::String("0x2".into())),
+                error: None,
+            })?;
+        }
+
+        let web3 = Web3::new(ReplayTransport::open(&path)?);
+
+        assert_eq!(web3.eth().block_number().await?.as_u64(), 0x2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use web3::types::H160;
+
+use crate::traversal::logs;
+
+/// Side of transaction a watched address is matched on.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    From,
+    To,
+    #[default]
+    Either,
+}
+
+/// Transactions of interest by sender and recipient addresses.
+#[derive(Debug, Clone, Default)]
+pub struct TrxFilter {
+    to: HashSet<H160>,
+    from: HashSet<H160>,
+}
+
+impl TrxFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds address matched on given side. Malformed addresses can't match anything and are skipped.
+    pub fn watch(mut self, address: &str, role: Role) -> Self {
+        if let Ok(address) = logs::to_h160(address) {
+            if role != Role::From {
+                self.to.insert(address);
+            }
+            if role != Role::To {
+                self.from.insert(address);
+            }
+        }
+
+        self
+    }
+
+    pub fn matches(&self, from: &H160, to: Option<&H160>) -> bool {
+        self.from.contains(from) || to.is_some_and(|to| self.to.contains(to))
+    }
+
+    /// Recipients only. Log based strategies look for them as emitters.
+    pub fn to_addresses(&self) -> Vec<H160> {
+        self.to.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_by_role() {
+        let sender = H160::from_low_u64_be(1);
+        let recipient = H160::from_low_u64_be(2);
+        let both = H160::from_low_u64_be(3);
+        let other = H160::from_low_u64_be(4);
+
+        let filter = TrxFilter::new()
+            .watch(&format!("{:#x}", sender), Role::From)
+            .watch(&format!("{:#x}", recipient), Role::To)
+            .watch(&format!("{:#x}", both), Role::Either)
+            .watch("not an address", Role::Either);
+
+        assert!(filter.matches(&sender, Some(&other)));
+        assert!(filter.matches(&other, Some(&recipient)));
+        assert!(filter.matches(&both, None));
+        assert!(filter.matches(&other, Some(&both)));
+
+        assert!(!filter.matches(&recipient, Some(&sender)));
+        assert!(!filter.matches(&other, None));
+
+        let mut to = filter.to_addresses();
+        to.sort();
+        assert_eq!(to, vec![recipient, both]);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::{debug, info};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::traversal::config::TraversalConfig;
+
+lazy_static! {
+    static ref THROTTLES: Mutex<HashMap<String, Arc<Throttle>>> = Mutex::new(HashMap::new());
+}
+
+/// Everything that limits the load we put on a single RPC endpoint.
+#[derive(Debug)]
+pub struct Throttle {
+    pub config: TraversalConfig,
+    rate: RateLimiter,
+    concurrency: Semaphore,
+    batch: AdaptiveBatch,
+}
+
+impl Throttle {
+    pub fn new(config: TraversalConfig) -> Self {
+        Throttle {
+            rate: RateLimiter::new(config.requests_per_sec),
+            concurrency: Semaphore::new(config.max_concurrency.max(1)),
+            batch: AdaptiveBatch::new(&config),
+            config,
+        }
+    }
+
+    /// Returns throttle shared by all users of the endpoint, so they share its rate limit and concurrency.
+    /// Endpoint keeps the config it was first throttled with, asking with a different one is an error.
+    pub fn for_endpoint(url: &str, config: &TraversalConfig) -> Result<Arc<Throttle>> {
+        let mut throttles = THROTTLES.lock().unwrap();
+
+        if let Some(throttle) = throttles.get(url) {
+            if &throttle.config != config {
+                bail!("Endpoint [{}] is already throttled with {:?}. Requested {:?}", url, throttle.config, config);
+            }
+
+            return Ok(throttle.clone());
+        }
+
+        debug!("Creating throttle for [{}]. {:?}", url, config);
+
+        let throttle = Arc::new(Throttle::new(config.clone()));
+        throttles.insert(url.to_string(), throttle.clone());
+        Ok(throttle)
+    }
+
+    /// Waits for a free request slot.
+    pub async fn acquire_request(&self) {
+        self.rate.acquire().await
+    }
+
+    /// Waits for a free task slot. Slot is released once permit dropped.
+    pub async fn acquire_task(&self) -> SemaphorePermit<'_> {
+        self.concurrency.acquire().await
+    }
+
+    pub fn record(&self, latency: Duration, failed: bool) {
+        self.batch.record(latency, failed)
+    }
+
+    pub fn batch_size(&self) -> u64 {
+        self.batch.current()
+    }
+
+    /// Recalculates batch size from the stats gathered since last call.
+    pub fn adjust_batch_size(&self) -> u64 {
+        self.batch.adjust()
+    }
+}
+
+/// Token bucket. Allows up to `per_sec` requests each second with bursts of the same size.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_sec: u32,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32) -> Self {
+        RateLimiter {
+            per_sec,
+            state: Mutex::new(Bucket {
+                tokens: per_sec as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire() {
+            tokio::time::delay_for(wait).await;
+        }
+    }
+
+    /// Takes a token if available, otherwise returns time to wait for the next one.
+    fn try_acquire(&self) -> Option<Duration> {
+        if self.per_sec == 0 {
+            return None;
+        }
+
+        let mut bucket = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = (now - bucket.updated).as_secs_f64() * self.per_sec as f64;
+
+        bucket.tokens = (bucket.tokens + refill).min(self.per_sec as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_sec as f64))
+    }
+}
+
+/// Batch size driven by observed latency and error rate.
+/// Shrinks by half when node struggles and grows by a quarter when it's fast.
+#[derive(Debug)]
+pub struct AdaptiveBatch {
+    min: u64,
+    max: u64,
+    target_latency: Duration,
+    max_error_rate: f64,
+    state: Mutex<BatchStats>,
+}
+
+#[derive(Debug, Default)]
+struct BatchStats {
+    current: u64,
+    requests: u64,
+    errors: u64,
+    latency: Duration,
+}
+
+impl AdaptiveBatch {
+    pub fn new(config: &TraversalConfig) -> Self {
+        let min = config.min_batch_size.max(1);
+        let max = config.max_batch_size.max(min);
+
+        AdaptiveBatch {
+            min,
+            max,
+            target_latency: Duration::from_millis(config.target_latency_ms),
+            max_error_rate: config.max_error_rate,
+            state: Mutex::new(BatchStats {
+                current: config.batch_size.max(min).min(max),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.state.lock().unwrap().current
+    }
+
+    pub fn record(&self, latency: Duration, failed: bool) {
+        let mut stats = self.state.lock().unwrap();
+        stats.requests += 1;
+        stats.latency += latency;
+        if failed {
+            stats.errors += 1;
+        }
+    }
+
+    pub fn adjust(&self) -> u64 {
+        let mut stats = self.state.lock().unwrap();
+
+        if stats.requests == 0 {
+            return stats.current;
+        }
+
+        let error_rate = stats.errors as f64 / stats.requests as f64;
+        let avg_latency = stats.latency / stats.requests as u32;
+        let previous = stats.current;
+
+        stats.current = match (error_rate, avg_latency) {
+            (e, _) if e > self.max_error_rate => previous / 2,
+            (_, l) if l > self.target_latency => previous / 2,
+            (_, l) if l < self.target_latency / 2 => previous + (previous / 4).max(1),
+            _ => previous,
+        }.max(self.min).min(self.max);
+
+        if stats.current != previous {
+            info!("Batch size changed {} -> {}. Error rate: {:.3}, avg latency: {:?}",
+                  previous, stats.current, error_rate, avg_latency);
+        }
+
+        stats.requests = 0;
+        stats.errors = 0;
+        stats.latency = Duration::default();
+
+        stats.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TraversalConfig {
+        TraversalConfig {
+            batch_size: 100,
+            min_batch_size: 10,
+            max_batch_size: 200,
+            target_latency_ms: 100,
+            max_error_rate: 0.1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn throttle_per_endpoint_config() {
+        let shared = Throttle::for_endpoint("http://throttled:8545", &config()).unwrap();
+        assert!(Arc::ptr_eq(&shared, &Throttle::for_endpoint("http://throttled:8545", &config()).unwrap()));
+
+        let faster = TraversalConfig { requests_per_sec: 500, ..config() };
+        assert!(Throttle::for_endpoint("http://throttled:8545", &faster).is_err());
+        assert_eq!(Throttle::for_endpoint("http://other:8545", &faster).unwrap().config, faster);
+    }
+
+    #[test]
+    fn batch_shrinks_on_errors() {
+        let batch = AdaptiveBatch::new(&config());
+
+        for i in 0..10 {
+            batch.record(Duration::from_millis(10), i < 5);
+        }
+
+        assert_eq!(batch.adjust(), 50);
+    }
+
+    #[test]
+    fn batch_shrinks_on_latency_till_min() {
+        let batch = AdaptiveBatch::new(&config());
+
+        for _ in 0..5 {
+            batch.record(Duration::from_millis(500), false);
+            batch.adjust();
+        }
+
+        assert_eq!(batch.current(), 10);
+    }
+
+    #[test]
+    fn batch_grows_till_max() {
+        let batch = AdaptiveBatch::new(&config());
+
+        for _ in 0..10 {
+            batch.record(Duration::from_millis(10), false);
+            batch.adjust();
+        }
+
+        assert_eq!(batch.current(), 200);
+    }
+
+    #[test]
+    fn rate_limiter_waits_when_empty() {
+        let limiter = RateLimiter::new(2);
+
+        assert!(limiter.try_acquire().is_none());
+        assert!(limiter.try_acquire().is_none());
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn rate_limiter_unlimited() {
+        let limiter = RateLimiter::new(0);
+
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire().is_none());
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{debug, warn};
+use tiny_keccak::{Hasher, Keccak};
+use web3::types::{H160, H2048};
+
+use crate::traversal::batch::create_ranges;
+use crate::traversal::source::ChainSource;
+
+/// Max amount of blocks queried by a single `eth_getLogs`. Most of nodes reject wider ranges.
+pub(crate) const LOGS_RANGE_SIZE: u64 = 5_000;
+
+pub fn to_h160(address: &str) -> Result<H160> {
+    Ok(H160::from_str(address.trim_start_matches("0x"))?)
+}
+
+/// Checks whether address could have emitted a log in block with given bloom.
+/// False positives are possible, false negatives are not.
+pub fn bloom_contains(bloom: &H2048, address: &H160) -> bool {
+    let bloom = bloom.as_bytes();
+
+    bloom_bits(address.as_bytes()).iter()
+        .all(|bit| bloom[255 - bit / 8] & (1 << (bit % 8)) != 0)
+}
+
+/// Adds address or topic to bloom.
+#[cfg(test)]
+pub fn accrue_bloom(bloom: &mut H2048, input: &[u8]) {
+    let bloom = bloom.as_bytes_mut();
+
+    for bit in bloom_bits(input).iter() {
+        bloom[255 - bit / 8] |= 1 << (bit % 8);
+    }
+}
+
+fn bloom_bits(input: &[u8]) -> [usize; 3] {
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(input);
+    keccak.finalize(&mut hash);
+
+    let mut bits = [0; 3];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = (((hash[2 * i] as usize) << 8) | hash[2 * i + 1] as usize) & 2047;
+    }
+    bits
+}
+
+/// Blocks in range having logs emitted by any of addresses. Uses `eth_getLogs`.
+pub async fn blocks_with_logs(source: Arc<dyn ChainSource>, addresses: &[H160], range: &Range<u64>) -> Result<Vec<u64>> {
+    let mut blocks = BTreeSet::new();
+
+    for sub_range in create_ranges(range, LOGS_RANGE_SIZE) {
+        let logs = source.logs(addresses, sub_range.clone()).await?;
+
+        debug!("Range {:?}. Found {} logs", sub_range, logs.len());
+
+        blocks.extend(logs.iter()
+            .filter(|l| !l.is_removed())
+            .filter_map(|l| l.block_number)
+            .map(|n| n.as_u64()));
+    }
+
+    Ok(blocks.into_iter().collect())
+}
+
+/// Blocks in range which bloom matches any of addresses. Bloom of a block `stored` before is taken as it is,
+/// header is downloaded for the rest. Cheaper than full scan, but still a request per unknown block.
+pub async fn blocks_by_bloom(source: Arc<dyn ChainSource>, stored: &BTreeMap<u64, H2048>, addresses: &[H160], range: &Range<u64>) -> Result<Vec<u64>> {
+    let mut blocks = vec![];
+
+    for block_id in range.clone() {
+        let bloom = match stored.get(&block_id) {
+            Some(bloom) => Some(*bloom),
+            None => match source.block(block_id).await? {
+                Some(h) => h.logs_bloom,
+                None => {
+                    warn!("Block is None {}", block_id);
+                    break;
+                }
+            },
+        };
+
+        let hit = bloom
+            .map(|bloom| addresses.iter().any(|a| bloom_contains(&bloom, a)))
+            .unwrap_or(true);
+
+        if hit {
+            blocks.push(block_id);
+        }
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+
+    use crate::traversal::source::ChainBuilder;
+
+    use super::*;
+
+    #[test]
+    fn bloom_contains_address() {
+        let address = to_h160("0xdac17f958d2ee523a2206206994597c13d831ec7").unwrap();
+        let other = to_h160("0xf12b5dd4ead5f743c6baa640b0216200e89b60da").unwrap();
+
+        let mut bloom = H2048::zero();
+        accrue_bloom(&mut bloom, address.as_bytes());
+
+        assert!(bloom_contains(&bloom, &address));
+        assert!(!bloom_contains(&bloom, &other));
+        assert!(!bloom_contains(&H2048::zero(), &address));
+    }
+
+    #[tokio::test]
+    async fn stored_bloom_taken_as_is() -> Result<()> {
+        let contract = H160::from_low_u64_be(0xc0);
+        let source: Arc<dyn ChainSource> = Arc::new(ChainBuilder::new()
+            .empty_blocks(3)
+            .transaction(H160::from_low_u64_be(1), Some(contract), vec![])
+            .log(vec![H256::from_low_u64_be(1)], vec![])
+            .mine()
+            .empty_blocks(3)
+            .build());
+
+        assert_eq!(blocks_by_bloom(source.clone(), &BTreeMap::new(), &[contract], &(0..7)).await?, vec![3]);
+
+        let mut stored = BTreeMap::new();
+        stored.insert(3, H2048::zero());
+        accrue_bloom(stored.entry(5).or_insert_with(H2048::zero), contract.as_bytes());
+
+        assert_eq!(blocks_by_bloom(source, &stored, &[contract], &(0..7)).await?, vec![5]);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
-pub(crate) use model::ChainData;
+pub(crate) use filter::{Role, TrxFilter};
+pub(crate) use model::{ChainData, RangeSet};
 
 pub(crate) mod connection;
 pub(crate) mod batch;
+pub(crate) mod filter;
 pub(crate) mod model;
-
+pub(crate) mod config;
+pub(crate) mod limiter;
+pub(crate) mod logs;
+pub(crate) mod source;
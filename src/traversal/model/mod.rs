@@ -3,17 +3,24 @@ use std::ops::Range;
 
 use web3::types::{Block, Transaction};
 
+pub(crate) use range_set::RangeSet;
+
+mod range_set;
+
 #[derive(Debug)]
 pub struct ChainData {
     pub range: Range<u64>,
     pub blocks: Vec<Block<Transaction>>,
+    /// Sub-ranges fully processed. Might be less than range if chain head reached.
+    pub covered: Vec<Range<u64>>,
 }
 
 impl ChainData {
-    pub fn new(range: Range<u64>, blocks: Vec<Block<Transaction>>) -> Self {
+    pub fn new(range: Range<u64>, blocks: Vec<Block<Transaction>>, covered: Vec<Range<u64>>) -> Self {
         ChainData {
             range,
             blocks,
+            covered,
         }
     }
 }
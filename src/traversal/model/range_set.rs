@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Sorted set of non overlapping block ranges. Adjacent ranges are merged.
+/// Stored as i64 ranges as bson doesn't support unsigned types.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RangeSet {
+    ranges: Vec<Range<i64>>,
+}
+
+impl RangeSet {
+    pub fn new() -> Self {
+        RangeSet::default()
+    }
+
+    pub fn ranges(&self) -> Vec<Range<u64>> {
+        self.ranges.iter()
+            .map(|r| r.start as u64..r.end as u64)
+            .collect()
+    }
+
+    pub fn insert(&mut self, range: Range<u64>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let mut new = range.start as i64..range.end as i64;
+        let mut merged = Vec::with_capacity(self.ranges.len() + 1);
+
+        for r in self.ranges.drain(..) {
+            if r.end < new.start || r.start > new.end {
+                merged.push(r);
+            } else {
+                new = new.start.min(r.start)..new.end.max(r.end);
+            }
+        }
+
+        merged.push(new);
+        merged.sort_by_key(|r| r.start);
+
+        self.ranges = merged;
+    }
+
+    /// Whether the whole range is covered.
+    #[cfg(test)]
+    pub fn contains(&self, range: &Range<u64>) -> bool {
+        range.start >= range.end || self.missing(range).is_empty()
+    }
+
+    /// Parts of range not covered by the set.
+    pub fn missing(&self, range: &Range<u64>) -> Vec<Range<u64>> {
+        let mut missing = vec![];
+        let mut pos = range.start;
+
+        for r in self.ranges() {
+            if r.end <= pos {
+                continue;
+            }
+            if r.start >= range.end {
+                break;
+            }
+            if r.start > pos {
+                missing.push(pos..r.start);
+            }
+            pos = r.end;
+        }
+
+        if pos < range.end {
+            missing.push(pos..range.end);
+        }
+
+        missing
+    }
+
+    /// Extends range with covered ranges overlapping or adjacent to it.
+    pub fn extend_contiguous(&self, range: Range<u64>) -> Range<u64> {
+        self.ranges()
+            .into_iter()
+            .find(|r| r.start <= range.end && r.end >= range.start)
+            .map(|r| r.start.min(range.start)..r.end.max(range.end))
+            .unwrap_or(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ranges: &[Range<u64>]) -> RangeSet {
+        let mut set = RangeSet::new();
+        for r in ranges {
+            set.insert(r.clone());
+        }
+        set
+    }
+
+    #[test]
+    fn insert_merges_adjacent_and_overlapping() {
+        let set = set(&[20..30, 0..10, 10..15, 25..40, 50..60]);
+
+        assert_eq!(set.ranges(), vec![0..15, 20..40, 50..60]);
+    }
+
+    #[test]
+    fn insert_bridges_gap() {
+        let set = set(&[0..10, 20..30, 5..25]);
+
+        assert_eq!(set.ranges(), vec![0..30]);
+    }
+
+    #[test]
+    fn missing_and_contains() {
+        let set = set(&[10..20, 30..40]);
+
+        assert_eq!(set.missing(&(0..50)), vec![0..10, 20..30, 40..50]);
+        assert_eq!(set.missing(&(12..35)), vec![20..30]);
+        assert!(set.contains(&(12..18)));
+        assert!(!set.contains(&(12..22)));
+    }
+
+    #[test]
+    fn extend_contiguous() {
+        let set = set(&[0..100, 200..300]);
+
+        assert_eq!(set.extend_contiguous(100..150), 0..150);
+        assert_eq!(set.extend_contiguous(300..300), 200..300);
+        assert_eq!(set.extend_contiguous(120..150), 120..150);
+    }
+}
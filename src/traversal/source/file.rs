@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+#[cfg(test)]
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use web3::types::{Block, Bytes, CallRequest, H160, H256, Log, Transaction, TransactionReceipt, U256};
+
+use crate::traversal::source::{ChainSource, MemoryChainSource};
+
+/// Line of fixture file. Plain block lines are accepted as well.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum FixtureLine {
+    WithReceipts {
+        block: Block<Transaction>,
+        #[serde(default)]
+        receipts: Vec<TransactionReceipt>,
+    },
+    Block(Block<Transaction>),
+}
+
+/// Chain recorded to a JSONL file, one block per line. Loaded into memory on open.
+#[derive(Debug)]
+pub struct FileChainSource {
+    inner: MemoryChainSource,
+}
+
+impl FileChainSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path).with_context(|| format!("Can't open chain fixture {:?}", path))?;
+
+        let mut blocks = vec![];
+        let mut receipts = vec![];
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line).with_context(|| format!("Bad fixture line {} in {:?}", i + 1, path))? {
+                FixtureLine::WithReceipts { block, receipts: r } => {
+                    blocks.push(block);
+                    receipts.extend(r);
+                }
+                FixtureLine::Block(block) => blocks.push(block),
+            }
+        }
+
+        Ok(FileChainSource {
+            inner: MemoryChainSource::new(blocks, receipts),
+        })
+    }
+
+    /// Writes chain to fixture file readable by `open`.
+    #[cfg(test)]
+    pub fn write<P: AsRef<Path>>(chain: &MemoryChainSource, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        for block in chain.blocks() {
+            let line = FixtureLine::WithReceipts {
+                block: block.clone(),
+                receipts: chain.receipts_of(block),
+            };
+
+            serde_json::to_writer(&mut out, &line)?;
+            out.write_all(b"\n")?;
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChainSource for FileChainSource {
+    async fn block_number(&self) -> Result<u64> {
+        self.inner.block_number().await
+    }
+
+    async fn block(&self, number: u64) -> Result<Option<Block<H256>>> {
+        self.inner.block(number).await
+    }
+
+    async fn block_with_txs(&self, number: u64) -> Result<Option<Block<Transaction>>> {
+        self.inner.block_with_txs(number).await
+    }
+
+    async fn transaction(&self, hash: H256) -> Result<Option<Transaction>> {
+        self.inner.transaction(hash).await
+    }
+
+    async fn transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
+        self.inner.transaction_receipt(hash).await
+    }
+
+    async fn transaction_count(&self, address: H160) -> Result<U256> {
+        self.inner.transaction_count(address).await
+    }
+
+    async fn logs(&self, addresses: &[H160], range: Range<u64>) -> Result<Vec<Log>> {
+        self.inner.logs(addresses, range).await
+    }
+
+    async fn call(&self, request: CallRequest) -> Result<Bytes> {
+        self.inner.call(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::traversal::source::ChainBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_and_open() -> Result<()> {
+        let contract = H160::from_low_u64_be(2);
+        let chain = ChainBuilder::new()
+            .empty_blocks(3)
+            .transaction(H160::from_low_u64_be(1), Some(contract), vec![])
+            .log(vec![H256::from_low_u64_be(7)], vec![])
+            .mine()
+            .build();
+
+        let path = std::env::temp_dir().join("chain_scraper_fixture.jsonl");
+        FileChainSource::write(&chain, &path)?;
+
+        let source = FileChainSource::open(&path)?;
+
+        assert_eq!(source.block_number().await?, 3);
+        assert_eq!(source.logs(&[contract], 0..4).await?.len(), 1);
+
+        Ok(())
+    }
+}
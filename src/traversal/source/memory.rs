@@ -0,0 +1,369 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+#[cfg(test)]
+use tiny_keccak::{Hasher, Keccak};
+use web3::types::{Block, Bytes, CallRequest, H160, H256, Log, Transaction, TransactionReceipt, U256};
+#[cfg(test)]
+use web3::types::{H2048, Index, U64};
+
+#[cfg(test)]
+use crate::traversal::logs::accrue_bloom;
+use crate::traversal::source::ChainSource;
+
+/// Chain kept in memory. Deterministic, so suitable for tests and offline demos.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryChainSource {
+    blocks: BTreeMap<u64, Block<Transaction>>,
+    receipts: HashMap<H256, TransactionReceipt>,
+    /// (to, data) -> result. Both keys are hex strings
+    calls: HashMap<(String, String), Bytes>,
+}
+
+impl MemoryChainSource {
+    pub fn new(blocks: Vec<Block<Transaction>>, receipts: Vec<TransactionReceipt>) -> Self {
+        MemoryChainSource {
+            blocks: blocks.into_iter()
+                .filter(|b| b.number.is_some())
+                .map(|b| (b.number.unwrap().as_u64(), b))
+                .collect(),
+            receipts: receipts.into_iter()
+                .map(|r| (r.transaction_hash, r))
+                .collect(),
+            calls: HashMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn blocks(&self) -> impl Iterator<Item=&Block<Transaction>> {
+        self.blocks.values()
+    }
+
+    pub fn receipts_of(&self, block: &Block<Transaction>) -> Vec<TransactionReceipt> {
+        block.transactions.iter()
+            .filter_map(|t| self.receipts.get(&t.hash))
+            .cloned()
+            .collect()
+    }
+}
+
+fn header(block: &Block<Transaction>) -> Block<H256> {
+    Block {
+        hash: block.hash,
+        parent_hash: block.parent_hash,
+        uncles_hash: block.uncles_hash,
+        author: block.author,
+        state_root: block.state_root,
+        transactions_root: block.transactions_root,
+        receipts_root: block.receipts_root,
+        number: block.number,
+        gas_used: block.gas_used,
+        gas_limit: block.gas_limit,
+        extra_data: block.extra_data.clone(),
+        logs_bloom: block.logs_bloom,
+        timestamp: block.timestamp,
+        difficulty: block.difficulty,
+        total_difficulty: block.total_difficulty,
+        seal_fields: block.seal_fields.clone(),
+        uncles: block.uncles.clone(),
+        transactions: block.transactions.iter().map(|t| t.hash).collect(),
+        size: block.size,
+        mix_hash: block.mix_hash,
+        nonce: block.nonce,
+    }
+}
+
+fn call_key(to: &serde_json::Value, data: &serde_json::Value) -> (String, String) {
+    (to.as_str().unwrap_or_default().to_lowercase(), data.as_str().unwrap_or_default().to_lowercase())
+}
+
+#[async_trait]
+impl ChainSource for MemoryChainSource {
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self.blocks.keys().next_back().cloned().unwrap_or_default())
+    }
+
+    async fn block(&self, number: u64) -> Result<Option<Block<H256>>> {
+        Ok(self.blocks.get(&number).map(header))
+    }
+
+    async fn block_with_txs(&self, number: u64) -> Result<Option<Block<Transaction>>> {
+        Ok(self.blocks.get(&number).cloned())
+    }
+
+    async fn transaction(&self, hash: H256) -> Result<Option<Transaction>> {
+        let number = match self.receipts.get(&hash).and_then(|r| r.block_number) {
+            Some(n) => n.as_u64(),
+            None => return Ok(None),
+        };
+
+        Ok(self.blocks.get(&number)
+            .and_then(|b| b.transactions.iter().find(|t| t.hash == hash))
+            .cloned())
+    }
+
+    async fn transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
+        Ok(self.receipts.get(&hash).cloned())
+    }
+
+    async fn transaction_count(&self, address: H160) -> Result<U256> {
+        Ok(self.blocks.values()
+            .flat_map(|b| b.transactions.iter())
+            .filter(|t| t.from == address)
+            .count()
+            .into())
+    }
+
+    async fn logs(&self, addresses: &[H160], range: Range<u64>) -> Result<Vec<Log>> {
+        Ok(self.blocks.range(range)
+            .flat_map(|(_, b)| self.receipts_of(b))
+            .flat_map(|r| r.logs.into_iter())
+            .filter(|l| addresses.contains(&l.address))
+            .collect())
+    }
+
+    async fn call(&self, request: CallRequest) -> Result<Bytes> {
+        let request = serde_json::to_value(&request)?;
+        let key = call_key(&request["to"], &request["data"]);
+
+        match self.calls.get(&key) {
+            Some(result) => Ok(result.clone()),
+            None => bail!("No call result registered for {:?}", key),
+        }
+    }
+}
+
+/// Builds deterministic chain. Transactions are added to the pending block which is sealed by `mine`.
+///
+/// ```ignore
+/// let chain = ChainBuilder::new()
+///     .empty_blocks(10)
+///     .transaction(sender, Some(contract), input)
+///     .log(vec![topic], vec![])
+///     .mine()
+///     .build();
+/// ```
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ChainBuilder {
+    chain: MemoryChainSource,
+    next: u64,
+    pending: Vec<(Transaction, Vec<Log>)>,
+    nonces: HashMap<H160, u64>,
+}
+
+/// Seconds between blocks
+#[cfg(test)]
+const BLOCK_TIME: u64 = 15;
+#[cfg(test)]
+const GENESIS_TIME: u64 = 1_600_000_000;
+
+#[cfg(test)]
+fn hash_of(input: &str) -> H256 {
+    let mut hash = [0u8; 32];
+    let mut keccak = Keccak::v256();
+    keccak.update(input.as_bytes());
+    keccak.finalize(&mut hash);
+    H256::from(hash)
+}
+
+#[cfg(test)]
+impl ChainBuilder {
+    pub fn new() -> Self {
+        ChainBuilder::default()
+    }
+
+    /// First block number of the chain.
+    pub fn starting_at(mut self, number: u64) -> Self {
+        self.next = number;
+        self
+    }
+
+    pub fn empty_blocks(mut self, count: u64) -> Self {
+        for _ in 0..count {
+            self = self.mine();
+        }
+        self
+    }
+
+    pub fn transaction(mut self, from: H160, to: Option<H160>, input: Vec<u8>) -> Self {
+        let index = self.pending.len();
+        let nonce = self.nonces.entry(from).or_insert(0);
+
+        let trx = Transaction {
+            hash: hash_of(&format!("tx:{}:{}", self.next, index)),
+            nonce: (*nonce).into(),
+            block_hash: Some(hash_of(&format!("block:{}", self.next))),
+            block_number: Some(self.next.into()),
+            transaction_index: Some(Index::from(index)),
+            from,
+            to,
+            value: U256::zero(),
+            gas_price: 1_000_000_000u64.into(),
+            gas: 100_000u64.into(),
+            input: Bytes(input),
+            raw: None,
+        };
+
+        *nonce += 1;
+        self.pending.push((trx, vec![]));
+        self
+    }
+
+    /// Log emitted by the recipient of the last added transaction.
+    pub fn log(mut self, topics: Vec<H256>, data: Vec<u8>) -> Self {
+        let number = self.next;
+
+        if let Some((trx, logs)) = self.pending.last_mut() {
+            logs.push(Log {
+                address: trx.to.unwrap_or_default(),
+                topics,
+                data: Bytes(data),
+                block_hash: trx.block_hash,
+                block_number: Some(number.into()),
+                transaction_hash: Some(trx.hash),
+                transaction_index: trx.transaction_index,
+                log_index: None,
+                transaction_log_index: Some(logs.len().into()),
+                log_type: None,
+                removed: Some(false),
+            });
+        }
+        self
+    }
+
+    pub fn call_result(mut self, to: H160, data: Vec<u8>, result: Vec<u8>) -> Self {
+        let key = call_key(&serde_json::to_value(to).unwrap(), &serde_json::to_value(Bytes(data)).unwrap());
+        self.chain.calls.insert(key, Bytes(result));
+        self
+    }
+
+    /// Seals pending transactions into a block.
+    pub fn mine(mut self) -> Self {
+        let number = self.next;
+        let mut bloom = H2048::zero();
+        let mut transactions = vec![];
+
+        for (trx, logs) in self.pending.drain(..) {
+            for log in logs.iter() {
+                accrue_bloom(&mut bloom, log.address.as_bytes());
+                for topic in log.topics.iter() {
+                    accrue_bloom(&mut bloom, topic.as_bytes());
+                }
+            }
+
+            self.chain.receipts.insert(trx.hash, TransactionReceipt {
+                transaction_hash: trx.hash,
+                transaction_index: trx.transaction_index.unwrap_or_default(),
+                block_hash: trx.block_hash,
+                block_number: Some(U64::from(number)),
+                cumulative_gas_used: trx.gas,
+                gas_used: Some(trx.gas),
+                contract_address: None,
+                logs,
+                status: Some(U64::from(1)),
+                root: None,
+                logs_bloom: H2048::zero(),
+            });
+
+            transactions.push(trx);
+        }
+
+        let parent_hash = match number {
+            0 => H256::zero(),
+            n => hash_of(&format!("block:{}", n - 1)),
+        };
+
+        self.chain.blocks.insert(number, Block {
+            hash: Some(hash_of(&format!("block:{}", number))),
+            parent_hash,
+            uncles_hash: H256::zero(),
+            author: H160::zero(),
+            state_root: H256::zero(),
+            transactions_root: H256::zero(),
+            receipts_root: H256::zero(),
+            number: Some(number.into()),
+            gas_used: U256::zero(),
+            gas_limit: 8_000_000u64.into(),
+            extra_data: Bytes(vec![]),
+            logs_bloom: Some(bloom),
+            timestamp: (GENESIS_TIME + number * BLOCK_TIME).into(),
+            difficulty: U256::one(),
+            total_difficulty: Some(number.into()),
+            seal_fields: vec![],
+            uncles: vec![],
+            transactions,
+            size: None,
+            mix_hash: None,
+            nonce: None,
+        });
+
+        self.next += 1;
+        self
+    }
+
+    pub fn build(mut self) -> MemoryChainSource {
+        if !self.pending.is_empty() {
+            self = self.mine();
+        }
+        self.chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn build_chain() -> Result<()> {
+        let sender = H160::from_low_u64_be(1);
+        let contract = H160::from_low_u64_be(2);
+
+        let chain = ChainBuilder::new()
+            .empty_blocks(5)
+            .transaction(sender, Some(contract), vec![1, 2, 3])
+            .log(vec![H256::from_low_u64_be(7)], vec![])
+            .transaction(sender, Some(contract), vec![])
+            .mine()
+            .empty_blocks(3)
+            .build();
+
+        assert_eq!(chain.block_number().await?, 8);
+
+        let block = chain.block_with_txs(5).await?.unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(block.transactions[1].nonce, U256::one());
+        assert!(crate::traversal::logs::bloom_contains(&block.logs_bloom.unwrap(), &contract));
+
+        let logs = chain.logs(&[contract], 0..9).await?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_number, Some(5.into()));
+
+        assert_eq!(chain.transaction_count(sender).await?, 2.into());
+        assert!(chain.block(9).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registered_calls() -> Result<()> {
+        let contract = H160::from_low_u64_be(2);
+
+        let chain = ChainBuilder::new()
+            .starting_at(100)
+            .empty_blocks(2)
+            .call_result(contract, vec![0xab], vec![1])
+            .build();
+
+        assert_eq!(chain.block_number().await?, 101);
+        assert!(chain.block(99).await?.is_none());
+
+        let call = |data: Vec<u8>| CallRequest { to: Some(contract), data: Some(Bytes(data)), ..Default::default() };
+        assert_eq!(chain.call(call(vec![0xab])).await?, Bytes(vec![1]));
+        assert!(chain.call(call(vec![0xcd])).await.is_err());
+
+        Ok(())
+    }
+}
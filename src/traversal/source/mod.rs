@@ -0,0 +1,70 @@
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use web3::types::{Block, Bytes, CallRequest, H160, H256, Log, Transaction, TransactionReceipt, U256};
+use web3::Web3;
+
+use crate::traversal::connection::{self, RecordingTransport, ReplayTransport, Transport};
+use crate::traversal::limiter::Throttle;
+
+pub(crate) use file::FileChainSource;
+#[cfg(test)]
+pub(crate) use memory::ChainBuilder;
+pub(crate) use memory::MemoryChainSource;
+pub(crate) use rpc::RpcChainSource;
+
+mod file;
+mod memory;
+mod rpc;
+
+/// Everything scraper reads from a chain.
+#[async_trait]
+pub trait ChainSource: Debug + Send + Sync {
+    async fn block_number(&self) -> Result<u64>;
+
+    /// Block with transaction hashes only
+    async fn block(&self, number: u64) -> Result<Option<Block<H256>>>;
+
+    async fn block_with_txs(&self, number: u64) -> Result<Option<Block<Transaction>>>;
+
+    async fn transaction(&self, hash: H256) -> Result<Option<Transaction>>;
+
+    async fn transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>>;
+
+    /// Nonce of address at the latest block
+    async fn transaction_count(&self, address: H160) -> Result<U256>;
+
+    /// Logs emitted by any of addresses in range
+    async fn logs(&self, addresses: &[H160], range: Range<u64>) -> Result<Vec<Log>>;
+
+    /// `eth_call` at the latest block
+    #[allow(dead_code)]
+    async fn call(&self, request: CallRequest) -> Result<Bytes>;
+}
+
+/// Source by url. `ws://` and `http(s)://` are RPC nodes, `file://` is a fixture file,
+/// `replay://` is a cassette recorded with `--record_rpc`.
+pub async fn create_source(url: &str, throttle: Arc<Throttle>) -> Result<Arc<dyn ChainSource>> {
+    Ok(match url {
+        u if u.starts_with("file://") => Arc::new(FileChainSource::open(u.trim_start_matches("file://"))?),
+        u if u.starts_with("replay://") => {
+            let transport = ReplayTransport::open(u.trim_start_matches("replay://"))?;
+            Arc::new(RpcChainSource::new(Web3::new(transport), throttle))
+        }
+        u if u.starts_with("http") || u.starts_with("ws") => {
+            rpc_source(connection::create_transport(url).await, throttle)?
+        }
+        _ => bail!("Unsupported chain source: {}", url),
+    })
+}
+
+/// RPC source over transport. Traffic is recorded if `record_rpc` is configured.
+pub fn rpc_source(transport: Transport, throttle: Arc<Throttle>) -> Result<Arc<dyn ChainSource>> {
+    Ok(match throttle.config.record_rpc.clone() {
+        Some(path) => Arc::new(RpcChainSource::new(Web3::new(RecordingTransport::new(transport, path)?), throttle)),
+        None => Arc::new(RpcChainSource::new(Web3::new(transport), throttle)),
+    })
+}
@@ -0,0 +1,89 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::Future;
+use web3::types::{Block, BlockId, BlockNumber, Bytes, CallRequest, FilterBuilder, H160, H256, Log, Transaction, TransactionId, TransactionReceipt, U256};
+use web3::Web3;
+
+use crate::traversal::connection::Transport;
+use crate::traversal::limiter::Throttle;
+use crate::traversal::source::ChainSource;
+
+/// JSON-RPC node. Every request is rate limited by endpoint throttle.
+#[derive(Debug)]
+pub struct RpcChainSource<T: web3::Transport = Transport> {
+    web3: Web3<T>,
+    throttle: Arc<Throttle>,
+}
+
+impl<T: web3::Transport + Send + 'static> RpcChainSource<T> {
+    pub fn new(web3: Web3<T>, throttle: Arc<Throttle>) -> Self {
+        RpcChainSource {
+            web3,
+            throttle,
+        }
+    }
+
+    /// Rate limited request. web3 futures aren't `Send`, so they are driven on blocking pool.
+    async fn request<R, F, Fut>(&self, call: F) -> Result<R>
+        where
+            R: Send + 'static,
+            F: FnOnce(Web3<T>) -> Fut + Send + 'static,
+            Fut: Future<Output=web3::Result<R>>,
+    {
+        self.throttle.acquire_request().await;
+
+        let web3 = self.web3.clone();
+        Ok(tokio::task::spawn_blocking(move || futures::executor::block_on(call(web3))).await??)
+    }
+}
+
+fn block_id(number: u64) -> BlockId {
+    BlockId::Number(BlockNumber::Number(number.into()))
+}
+
+#[async_trait]
+impl<T> ChainSource for RpcChainSource<T>
+    where
+        T: web3::Transport + Send + Sync + 'static,
+{
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self.request(|w| w.eth().block_number()).await?.as_u64())
+    }
+
+    async fn block(&self, number: u64) -> Result<Option<Block<H256>>> {
+        self.request(move |w| w.eth().block(block_id(number))).await
+    }
+
+    async fn block_with_txs(&self, number: u64) -> Result<Option<Block<Transaction>>> {
+        self.request(move |w| w.eth().block_with_txs(block_id(number))).await
+    }
+
+    async fn transaction(&self, hash: H256) -> Result<Option<Transaction>> {
+        self.request(move |w| w.eth().transaction(TransactionId::Hash(hash))).await
+    }
+
+    async fn transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
+        self.request(move |w| w.eth().transaction_receipt(hash)).await
+    }
+
+    async fn transaction_count(&self, address: H160) -> Result<U256> {
+        self.request(move |w| w.eth().transaction_count(address, Some(BlockNumber::Latest))).await
+    }
+
+    async fn logs(&self, addresses: &[H160], range: Range<u64>) -> Result<Vec<Log>> {
+        let filter = FilterBuilder::default()
+            .from_block(BlockNumber::Number(range.start.into()))
+            .to_block(BlockNumber::Number((range.end - 1).into()))
+            .address(addresses.to_vec())
+            .build();
+
+        self.request(move |w| w.eth().logs(filter)).await
+    }
+
+    async fn call(&self, request: CallRequest) -> Result<Bytes> {
+        self.request(move |w| w.eth().call(request, None)).await
+    }
+}
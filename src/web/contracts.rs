@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::es::ContractProcessor;
+use crate::mongo::model::{Contract, Job, JobKind};
+use crate::parse::rules::Rule;
+
+/// Contract without its ABI
+fn summary(contract: &Contract) -> Value {
+    json!({
+        "chain_id": contract.chain_id,
+        "address": contract.address,
+        "create_block": contract.create_block,
+        "processed_range": contract.processed_range,
+        "backfilling": contract.backfilling,
+        "paused": contract.paused,
+        "labels": contract.labels,
+        "description": contract.description,
+        "rules": contract.rules,
+        "abi_version": contract.abi_version(),
+    })
+}
+
+async fn find(cp: &ContractProcessor, chain_id: u64, address: &str) -> Result<Contract, HttpResponse> {
+    match cp.get_storage().get_contract(chain_id, address).await {
+        Ok(Some(c)) => Ok(c),
+        Ok(None) => Err(HttpResponse::NotFound().body(format!("Contract {} not found on chain {}", address, chain_id))),
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to get contract"))
+        }
+    }
+}
+
+pub(crate) async fn list(chain_id: web::Path<u64>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_contracts(chain_id.into_inner()).await {
+        Ok(contracts) => HttpResponse::Ok().json(contracts.iter().map(summary).collect::<Vec<_>>()),
+        Err(e) => {
+            error!("Failed to get contracts. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get contracts")
+        }
+    }
+}
+
+pub(crate) async fn get(path: web::Path<(u64, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+
+    match find(&cp, chain_id, &address).await {
+        Ok(contract) => HttpResponse::Ok().json(contract),
+        Err(response) => response,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ContractInfo {
+    labels: Option<Vec<String>>,
+    description: Option<String>,
+    rules: Option<Vec<Rule>>,
+}
+
+/// Sets labels, description and filter rules. Omitted fields stay as they are.
+/// Changed rules queue reindex, so transactions indexed before follow them as well.
+pub(crate) async fn update(path: web::Path<(u64, String)>, info: web::Json<ContractInfo>,
+                           cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    let info = info.into_inner();
+
+    let mut contract = match find(&cp, chain_id, &address).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if let Some(labels) = info.labels {
+        contract.labels = labels;
+    }
+    if let Some(description) = info.description {
+        contract.description = Some(description).filter(|d| !d.is_empty());
+    }
+
+    let mut reindex = false;
+
+    if let Some(rules) = info.rules {
+        if let Err(e) = rules.iter().try_for_each(Rule::validate) {
+            return HttpResponse::BadRequest().body(format!("Invalid rule. {}", e));
+        }
+
+        reindex = rules != contract.rules;
+        contract.rules = rules;
+    }
+
+    let storage = cp.get_storage();
+
+    if let Err(e) = storage.update_contract_info(&contract).await {
+        error!("Failed to update contract. {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to update contract");
+    }
+
+    if reindex {
+        let job = Job::new(JobKind::Reindex, chain_id, &contract.address, 0..i64::MAX as u64);
+
+        if let Err(e) = storage.enqueue_job(&job).await {
+            error!("Failed to queue reindex of {}. {:?}", contract.id, e);
+            return HttpResponse::InternalServerError().body("Rules updated, but reindex wasn't queued");
+        }
+
+        info!("Rules of {} changed. Queued reindex job {}", contract.id, job.id);
+        crate::jobs::wake(chain_id);
+    }
+
+    HttpResponse::Ok().json(summary(&contract))
+}
+
+/// `pause` or `resume` following contract by live scraper. Resumed contract catches up from where it was paused.
+pub(crate) async fn control(path: web::Path<(u64, String, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address, action) = path.into_inner();
+
+    let paused = match action.as_str() {
+        "pause" => true,
+        "resume" => false,
+        _ => return HttpResponse::BadRequest().body(format!("Unknown action {}", action)),
+    };
+
+    let mut contract = match find(&cp, chain_id, &address).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    contract.paused = paused;
+
+    match cp.get_storage().update_contract_info(&contract).await {
+        Ok(_) => {
+            info!("Contract {} paused: {}", contract.id, paused);
+            HttpResponse::Ok().json(summary(&contract))
+        }
+        Err(e) => {
+            error!("Failed to update contract. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update contract")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DeleteParams {
+    /// Also remove stored and indexed transactions of contract
+    #[serde(default)]
+    purge: bool,
+}
+
+pub(crate) async fn delete(path: web::Path<(u64, String)>, params: web::Query<DeleteParams>,
+                           cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+
+    let contract = match find(&cp, chain_id, &address).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = cp.get_storage().delete_contract(&contract).await {
+        error!("Failed to delete contract. {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to delete contract");
+    }
+
+    info!("Contract {} deleted", contract.id);
+
+    if params.purge {
+        if let Err(e) = cp.purge(&contract).await {
+            error!("Failed to purge transactions of {}. {:?}", contract.id, e);
+            return HttpResponse::InternalServerError().body("Contract deleted, but its transactions weren't purged");
+        }
+
+        info!("Transactions of {} purged", contract.id);
+    }
+
+    HttpResponse::Ok().json(json!({
+        "chain_id": chain_id,
+        "address": contract.address,
+        "deleted": true,
+        "purged": params.purge,
+    }))
+}
@@ -0,0 +1,148 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::es::ContractProcessor;
+use crate::mongo::model::{Job, JobKind, JobStatus};
+
+/// Job with its progress in percent and ETA in millis.
+pub(crate) fn job_json(job: &Job) -> Value {
+    let mut value = serde_json::to_value(job).unwrap_or(Value::Null);
+
+    if let Value::Object(map) = &mut value {
+        map.insert("progress".into(), job.progress().into());
+        map.insert("eta_ms".into(), serde_json::json!(job.eta_ms(chrono::Utc::now().timestamp_millis())));
+    }
+
+    value
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct JobQuery {
+    chain_id: Option<u64>,
+    address: Option<String>,
+    status: Option<JobStatus>,
+}
+
+pub(crate) async fn list(query: web::Query<JobQuery>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_jobs(query.chain_id, query.address.as_deref(), query.status).await {
+        Ok(jobs) => HttpResponse::Ok().json(jobs.iter().map(job_json).collect::<Vec<_>>()),
+        Err(e) => {
+            error!("Failed to get jobs. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get jobs")
+        }
+    }
+}
+
+pub(crate) async fn get(id: web::Path<String>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_job(&id).await {
+        Ok(Some(job)) => HttpResponse::Ok().json(job_json(&job)),
+        Ok(None) => HttpResponse::NotFound().body(format!("Job {} not found", id)),
+        Err(e) => {
+            error!("Failed to get job. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get job")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewJob {
+    /// Default chain if omitted
+    chain_id: Option<u64>,
+    address: String,
+    kind: JobKind,
+    /// Required for backfill. Every stored transaction is decoded if omitted. Reindex takes none,
+    /// as it drops every indexed transaction of contract first.
+    range: Option<Range<u64>>,
+    priority: Option<i32>,
+}
+
+pub(crate) async fn create(new_job: web::Json<NewJob>, cp: web::Data<Arc<ContractProcessor>>,
+                           config: web::Data<Arc<Config>>) -> HttpResponse {
+    let new_job = new_job.into_inner();
+    let chain_id = new_job.chain_id.unwrap_or_else(|| config.default_chain().id);
+    let address = new_job.address.to_lowercase();
+
+    if config.chain(chain_id).is_none() {
+        return HttpResponse::BadRequest().body(format!("Unknown chain {}", chain_id));
+    }
+
+    let range = match (new_job.kind, new_job.range) {
+        (JobKind::GapFill, _) | (JobKind::Initial, _) => {
+            return HttpResponse::BadRequest().body(format!("{:?} jobs are queued by scraper itself", new_job.kind));
+        }
+        (JobKind::Backfill, None) => return HttpResponse::BadRequest().body("Backfill requires range"),
+        (JobKind::Reindex, Some(_)) => return HttpResponse::BadRequest().body("Reindex takes no range, it indexes every stored transaction again"),
+        (_, Some(range)) if range.is_empty() => return HttpResponse::BadRequest().body("Empty range"),
+        (_, Some(range)) => range,
+        (_, None) => 0..i64::MAX as u64,
+    };
+
+    let storage = cp.get_storage();
+
+    match storage.get_contract(chain_id, &address).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return HttpResponse::NotFound().body(format!("Contract {} not found on chain {}", address, chain_id)),
+        Err(e) => {
+            error!("Failed to get contracts. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contracts");
+        }
+    }
+
+    let mut job = Job::new(new_job.kind, chain_id, &address, range);
+    if let Some(priority) = new_job.priority {
+        job.priority = priority;
+    }
+
+    match storage.enqueue_job(&job).await {
+        Ok(_) => {
+            info!("Queued {:?} job {}", job.kind, job.id);
+            crate::jobs::wake(chain_id);
+            HttpResponse::Ok().json(job_json(&job))
+        }
+        Err(e) => {
+            error!("Failed to queue job. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to queue job")
+        }
+    }
+}
+
+/// `cancel`, `pause` or `resume`. Running job stops after the chunk it works on.
+pub(crate) async fn control(path: web::Path<(String, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (id, action) = path.into_inner();
+
+    let (from, to) = match action.as_str() {
+        "cancel" => (vec![JobStatus::Queued, JobStatus::Running, JobStatus::Paused], JobStatus::Cancelled),
+        "pause" => (vec![JobStatus::Queued, JobStatus::Running], JobStatus::Paused),
+        "resume" => (vec![JobStatus::Paused], JobStatus::Queued),
+        _ => return HttpResponse::BadRequest().body(format!("Unknown action {}", action)),
+    };
+
+    let storage = cp.get_storage();
+
+    match storage.set_job_status(&id, &from, to).await {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::Conflict().body(format!("Job {} can't be {:?}", id, to)),
+        Err(e) => {
+            error!("Failed to update job. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update job");
+        }
+    }
+
+    info!("Job {} is {:?}", id, to);
+
+    match storage.get_job(&id).await {
+        Ok(Some(job)) => {
+            if to == JobStatus::Queued {
+                crate::jobs::wake(job.chain_id as u64);
+            }
+            HttpResponse::Ok().json(job_json(&job))
+        }
+        _ => HttpResponse::Ok().finish(),
+    }
+}
@@ -1 +1,5 @@
+pub(crate) mod contracts;
+pub(crate) mod jobs;
 pub(crate) mod server;
+pub(crate) mod watchlist;
+pub(crate) mod webhooks;
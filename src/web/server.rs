@@ -1,46 +1,261 @@
 use std::sync::Arc;
 
-use actix_web::{App, HttpServer, middleware, Responder, web};
+use actix_web::{App, HttpResponse, HttpServer, middleware, web};
 use actix_web::web::resource;
 use log::{error, info, debug};
+use serde::Deserialize;
 
+use crate::config::Config;
 use crate::es::ContractProcessor;
-use crate::mongo::model::Contract;
+use crate::live::audit::find_holes;
+use crate::shutdown::Shutdown;
+use crate::storage::Storage;
+use crate::mongo::model::{Contract, Job, JobKind};
 use crate::parse::contract_abi::ContractAbi;
+use crate::web::{contracts, jobs, watchlist, webhooks};
 
-pub async fn run_server(cp: Arc<ContractProcessor>, port: u16) -> tokio::io::Result<()> {
+pub async fn run_server(cp: Arc<ContractProcessor>, config: Arc<Config>, port: u16, shutdown: Shutdown) -> tokio::io::Result<()> {
     debug!("Starting server on port: {}", port);
 
     let factory = move || {
         App::new()
             .data(cp.clone())
+            .data(config.clone())
             .wrap(middleware::Logger::default())
-            .service(resource("/abi/upload/{address}").route(web::post().to(abi_upload)))
+            .service(resource("/abi/upload/{chain_id}/{address}").route(web::post().to(abi_upload)))
+            .service(resource("/abi/upload/{address}").route(web::post().to(abi_upload_default_chain)))
+            .service(resource("/abi/{chain_id}/{address}").route(web::put().to(abi_update)))
+            .service(resource("/abi/{address}").route(web::put().to(abi_update_default_chain)))
+            .service(resource("/coverage/{chain_id}/{address}").route(web::get().to(coverage)))
+            .service(resource("/coverage/{address}").route(web::get().to(coverage_default_chain)))
+            .service(resource("/backfill/{chain_id}/{address}").route(web::get().to(backfill_status)))
+            .service(resource("/contracts/{chain_id}").route(web::get().to(contracts::list)))
+            .service(resource("/contracts/{chain_id}/{address}")
+                .route(web::get().to(contracts::get))
+                .route(web::patch().to(contracts::update))
+                .route(web::delete().to(contracts::delete)))
+            .service(resource("/contracts/{chain_id}/{address}/{action}").route(web::post().to(contracts::control)))
+            .service(resource("/jobs").route(web::get().to(jobs::list)).route(web::post().to(jobs::create)))
+            .service(resource("/jobs/{id}").route(web::get().to(jobs::get)))
+            .service(resource("/jobs/{id}/{action}").route(web::post().to(jobs::control)))
+            .service(resource("/watchlist/{chain_id}").route(web::get().to(watchlist::list)).route(web::post().to(watchlist::add)))
+            .service(resource("/watchlist/{chain_id}/{address}").route(web::delete().to(watchlist::delete)))
+            .service(resource("/webhooks").route(web::get().to(webhooks::list)).route(web::post().to(webhooks::create)))
+            .service(resource("/webhooks/dead_letters").route(web::get().to(webhooks::dead_letters)))
+            .service(resource("/webhooks/{id}").route(web::delete().to(webhooks::delete)))
     };
 
-    HttpServer::new(factory).bind(format!("0.0.0.0:{}", port))?.run().await
+    let server = HttpServer::new(factory)
+        .shutdown_timeout(shutdown.grace_period().as_secs())
+        .bind(format!("0.0.0.0:{}", port))?
+        .run();
+
+    // server stops by itself on SIGTERM/SIGINT, shutdown requested by anything else has to stop it
+    let handle = server.clone();
+    actix_web::rt::spawn(async move {
+        shutdown.requested().await;
+        handle.stop(true).await;
+    });
+
+    server.await
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadParams {
+    /// Block contract was deployed at. Backfill starts there. Required for a new contract, re-upload keeps the known one.
+    create_block: Option<u64>,
 }
 
-async fn abi_upload(address: web::Path<String>, contract_abi: web::Json<ContractAbi>, cp: web::Data<Arc<ContractProcessor>>) -> impl Responder {
-    info!("Received /abi/upload/{}", address);
+async fn abi_upload_default_chain(address: web::Path<String>, params: web::Query<UploadParams>, contract_abi: web::Json<ContractAbi>,
+                                  cp: web::Data<Arc<ContractProcessor>>, config: web::Data<Arc<Config>>) -> HttpResponse {
+    let chain_id = config.default_chain().id;
+    abi_upload(web::Path::from((chain_id, address.into_inner())), params, contract_abi, cp, config).await
+}
+
+async fn abi_upload(path: web::Path<(u64, String)>, params: web::Query<UploadParams>, contract_abi: web::Json<ContractAbi>,
+                    cp: web::Data<Arc<ContractProcessor>>, config: web::Data<Arc<Config>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    info!("Received /abi/upload/{}/{}", chain_id, address);
 
-    let contract = Contract::new(address.as_str(), contract_abi.into_inner());
+    if config.chain(chain_id).is_none() {
+        return HttpResponse::NotFound().body(format!("Unknown chain {}", chain_id));
+    }
+
+    let storage = cp.get_storage();
+
+    // re-upload replaces ABI of tracked contract
+    match storage.get_contract(chain_id, &address).await {
+        Ok(Some(mut contract)) => {
+            return match replace_abi(storage.as_ref(), &mut contract, contract_abi.into_inner()).await {
+                Ok(job) => abi_json(&contract, &job),
+                Err(e) => {
+                    error!("Failed to replace ABI. {:?}", e);
+                    HttpResponse::InternalServerError().body("Failed to replace ABI")
+                }
+            };
+        }
+        Ok(None) => (),
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contract");
+        }
+    }
+
+    // scanning history from genesis would take ages
+    let create_block = match params.create_block {
+        Some(b) => b,
+        None => return HttpResponse::BadRequest().body("create_block is required for a new contract"),
+    };
+
+    let mut contract = Contract::new(chain_id, address.as_str(), contract_abi.into_inner());
+    contract.create_block = Some(create_block as i64);
 
     debug!("Parsed contract: {:?}", contract);
 
-    let contract = Arc::new(contract);
-    let contract_cloned = contract.clone();
-    let cp_cloned = cp.clone();
+    if let Err(e) = cp.save_contract(&contract).await {
+        error!("Failed to save contract. {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to save contract");
+    }
+
+    let job = Job::initial(chain_id, &contract.address, create_block);
 
-    match futures::executor::block_on(async {
-        cp_cloned.save_contract(&contract_cloned).await
-    }) {
-        Ok(_res) => (),
+    match storage.enqueue_job(&job).await {
+        Ok(true) => crate::jobs::wake(chain_id),
+        Ok(false) => info!("Backfill job {} is already queued", job.id),
         Err(e) => {
-            error!("Failed to save contract. {:?}", e);
-            return "Failed to save contract".to_string();
+            error!("Failed to queue backfill. {:?}", e);
+            return HttpResponse::InternalServerError().body("ABI saved, but backfill wasn't queued");
+        }
+    }
+
+    abi_json(&contract, &job)
+}
+
+/// Contract with its ABI version and the job working on it
+fn abi_json(contract: &Contract, job: &Job) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "chain_id": contract.chain_id,
+        "address": contract.address,
+        "abi_version": contract.abi_version(),
+        "job": jobs::job_json(job),
+    }))
+}
+
+async fn abi_update_default_chain(address: web::Path<String>, contract_abi: web::Json<ContractAbi>,
+                                  cp: web::Data<Arc<ContractProcessor>>, config: web::Data<Arc<Config>>) -> HttpResponse {
+    let chain_id = config.default_chain().id;
+    abi_update(web::Path::from((chain_id, address.into_inner())), contract_abi, cp).await
+}
+
+/// Replaces ABI of tracked contract and queues re-decode of its stored transactions.
+async fn replace_abi(storage: &dyn Storage, contract: &mut Contract, contract_abi: ContractAbi) -> anyhow::Result<Job> {
+    contract.update_abi(contract_abi);
+    storage.update_abi(contract).await?;
+
+    let job = Job::new(JobKind::Redecode, contract.chain_id as u64, &contract.address, 0..i64::MAX as u64);
+    storage.enqueue_job(&job).await?;
+
+    crate::jobs::wake(contract.chain_id as u64);
+
+    info!("ABI of {} replaced with version {}", contract.id, contract.abi_version());
+
+    Ok(job)
+}
+
+async fn abi_update(path: web::Path<(u64, String)>, contract_abi: web::Json<ContractAbi>,
+                    cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    let address = address.to_lowercase();
+    info!("Received ABI update for {}/{}", chain_id, address);
+
+    let storage = cp.get_storage();
+
+    let mut contract = match storage.get_contract(chain_id, &address).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Contract {} not found on chain {}", address, chain_id)),
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contract");
+        }
+    };
+
+    let job = match replace_abi(storage.as_ref(), &mut contract, contract_abi.into_inner()).await {
+        Ok(job) => job,
+        Err(e) => {
+            error!("Failed to update ABI. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update ABI");
         }
     };
 
-    format!("ABI saved successfully. Address: {}", address)
+    abi_json(&contract, &job)
+}
+
+/// Backfill jobs of contract. `backfilling` turns false once initial backfill caught up with live head.
+async fn backfill_status(path: web::Path<(u64, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    let address = address.to_lowercase();
+    let storage = cp.get_storage();
+
+    let contract = match storage.get_contract(chain_id, &address).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Contract {} not found on chain {}", address, chain_id)),
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contract");
+        }
+    };
+
+    match storage.get_jobs(Some(chain_id), Some(&address), None).await {
+        Ok(found) => HttpResponse::Ok().json(serde_json::json!({
+            "chain_id": chain_id,
+            "address": address,
+            "backfilling": contract.backfilling,
+            "processed_range": contract.processed_range,
+            "jobs": found.iter().map(jobs::job_json).collect::<Vec<_>>(),
+        })),
+        Err(e) => {
+            error!("Failed to get backfill jobs. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get backfill jobs")
+        }
+    }
+}
+
+async fn coverage_default_chain(address: web::Path<String>, cp: web::Data<Arc<ContractProcessor>>,
+                                config: web::Data<Arc<Config>>) -> HttpResponse {
+    let chain_id = config.default_chain().id;
+    coverage(web::Path::from((chain_id, address.into_inner())), cp).await
+}
+
+async fn coverage(path: web::Path<(u64, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    let address = address.to_lowercase();
+
+    let storage = cp.get_storage();
+
+    let start = match storage.get_contract(chain_id, &address).await {
+        Ok(contract) => contract.and_then(|c| c.create_block).unwrap_or(0).max(0) as u64,
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contract");
+        }
+    };
+
+    match storage.get_coverage(chain_id, &address).await {
+        Ok(coverage) => {
+            // blocks ahead of the last scanned one are followed by live scraper
+            let end = coverage.covered.ranges().last().map_or(start, |r| r.end);
+            let holes = find_holes(&coverage, start, end);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "chain_id": chain_id,
+                "address": address,
+                "covered": coverage.covered.ranges(),
+                "holes": holes,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to get coverage. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get coverage")
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::es::ContractProcessor;
+use crate::mongo::model::WatchedAddress;
+use crate::traversal::logs;
+use crate::traversal::Role;
+
+pub(crate) async fn list(chain_id: web::Path<u64>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_watchlist(chain_id.into_inner()).await {
+        Ok(watched) => HttpResponse::Ok().json(watched),
+        Err(e) => {
+            error!("Failed to get watchlist. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get watchlist")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewWatched {
+    address: String,
+    #[serde(default)]
+    role: Role,
+    #[serde(default)]
+    labels: Vec<String>,
+    description: Option<String>,
+    /// Watching starts from chain head if omitted
+    start_block: Option<u64>,
+}
+
+/// Adds address to watchlist. Entry of already watched address is replaced, scanned blocks are kept.
+pub(crate) async fn add(chain_id: web::Path<u64>, new: web::Json<NewWatched>, cp: web::Data<Arc<ContractProcessor>>,
+                        config: web::Data<Arc<Config>>) -> HttpResponse {
+    let chain_id = chain_id.into_inner();
+    let new = new.into_inner();
+
+    if config.chain(chain_id).is_none() {
+        return HttpResponse::BadRequest().body(format!("Unknown chain {}", chain_id));
+    }
+
+    if logs::to_h160(&new.address).is_err() {
+        return HttpResponse::BadRequest().body(format!("Invalid address {}", new.address));
+    }
+
+    let mut watched = WatchedAddress::new(chain_id, &new.address, new.role);
+    watched.labels = new.labels;
+    watched.description = new.description.filter(|d| !d.is_empty());
+    watched.start_block = new.start_block.map(|b| b as i64);
+
+    match cp.get_storage().save_watched(&watched).await {
+        Ok(_) => {
+            info!("Watching {} as {:?}", watched.id, watched.role);
+            HttpResponse::Ok().json(watched)
+        }
+        Err(e) => {
+            error!("Failed to save watched address. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save watched address")
+        }
+    }
+}
+
+pub(crate) async fn delete(path: web::Path<(u64, String)>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    let (chain_id, address) = path.into_inner();
+    let storage = cp.get_storage();
+
+    let watched = match storage.get_watched(chain_id, &address).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return HttpResponse::NotFound().body(format!("Address {} isn't watched on chain {}", address, chain_id)),
+        Err(e) => {
+            error!("Failed to get watched address. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get watched address");
+        }
+    };
+
+    match storage.delete_watched(&watched).await {
+        Ok(_) => {
+            info!("Stopped watching {}", watched.id);
+            HttpResponse::Ok().json(json!({
+                "chain_id": chain_id,
+                "address": watched.address,
+                "deleted": true,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to delete watched address. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to delete watched address")
+        }
+    }
+}
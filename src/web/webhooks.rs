@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::es::ContractProcessor;
+use crate::mongo::model::Webhook;
+use crate::parse::rules::Rule;
+
+/// Webhook without its secret
+fn summary(webhook: &Webhook) -> Value {
+    json!({
+        "id": webhook.id,
+        "chain_id": webhook.chain_id,
+        "address": webhook.address,
+        "url": webhook.url,
+        "rules": webhook.rules,
+        "signed": webhook.secret.is_some(),
+        "template": webhook.template,
+        "created_at": webhook.created_at,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebhookQuery {
+    chain_id: Option<u64>,
+    address: Option<String>,
+}
+
+pub(crate) async fn list(query: web::Query<WebhookQuery>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_webhooks(query.chain_id, query.address.as_deref()).await {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks.iter().map(summary).collect::<Vec<_>>()),
+        Err(e) => {
+            error!("Failed to get webhooks. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get webhooks")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewWebhook {
+    /// Default chain if omitted
+    chain_id: Option<u64>,
+    address: String,
+    url: String,
+    #[serde(default)]
+    rules: Vec<Rule>,
+    secret: Option<String>,
+    template: Option<Value>,
+}
+
+pub(crate) async fn create(new: web::Json<NewWebhook>, cp: web::Data<Arc<ContractProcessor>>,
+                           config: web::Data<Arc<Config>>) -> HttpResponse {
+    let new = new.into_inner();
+    let chain_id = new.chain_id.unwrap_or_else(|| config.default_chain().id);
+
+    if !new.url.starts_with("http://") && !new.url.starts_with("https://") {
+        return HttpResponse::BadRequest().body(format!("Invalid url {}", new.url));
+    }
+
+    if let Err(e) = new.rules.iter().try_for_each(Rule::validate) {
+        return HttpResponse::BadRequest().body(format!("Invalid rule. {}", e));
+    }
+
+    let storage = cp.get_storage();
+
+    match storage.get_contract(chain_id, &new.address).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return HttpResponse::NotFound().body(format!("Contract {} not found on chain {}", new.address, chain_id)),
+        Err(e) => {
+            error!("Failed to get contract. {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to get contract");
+        }
+    }
+
+    let mut webhook = Webhook::new(chain_id, &new.address, &new.url);
+    webhook.rules = new.rules;
+    webhook.secret = new.secret.filter(|s| !s.is_empty());
+    webhook.template = new.template;
+
+    match storage.save_webhook(&webhook).await {
+        Ok(_) => {
+            info!("Webhook {} added", webhook.id);
+            HttpResponse::Ok().json(summary(&webhook))
+        }
+        Err(e) => {
+            error!("Failed to save webhook. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save webhook")
+        }
+    }
+}
+
+pub(crate) async fn delete(id: web::Path<String>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().delete_webhook(&id).await {
+        Ok(true) => {
+            info!("Webhook {} deleted", id);
+            HttpResponse::Ok().json(json!({ "id": id.into_inner(), "deleted": true }))
+        }
+        Ok(false) => HttpResponse::NotFound().body(format!("Webhook {} not found", id)),
+        Err(e) => {
+            error!("Failed to delete webhook. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to delete webhook")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DeadLetterQuery {
+    webhook_id: Option<String>,
+}
+
+/// Notifications not delivered, the latest first.
+pub(crate) async fn dead_letters(query: web::Query<DeadLetterQuery>, cp: web::Data<Arc<ContractProcessor>>) -> HttpResponse {
+    match cp.get_storage().get_dead_letters(query.webhook_id.as_deref()).await {
+        Ok(letters) => HttpResponse::Ok().json(letters),
+        Err(e) => {
+            error!("Failed to get dead letters. {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to get dead letters")
+        }
+    }
+}